@@ -0,0 +1,116 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use oxide::board::Board;
+use oxide::limits::SearchLimits;
+use oxide::network::EvalTable;
+use oxide::search::find_best_move;
+use oxide::tables::SearchData;
+
+const POSITIONS: [(&str, &str); 3] = [
+    (
+        "startpos",
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    ),
+    (
+        "kiwipete",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    ),
+    ("endgame", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"),
+];
+
+/// Nodes searched by the fixed node search, low enough for builds without target features
+const SEARCH_NODES: u64 = 20_000;
+
+fn movegen(c: &mut Criterion) {
+    let mut group = c.benchmark_group("movegen");
+    for (name, fen) in POSITIONS {
+        let board = Board::from_fen(fen);
+        group.bench_function(name, |b| {
+            b.iter(|| black_box(&board).generate_pseudo_moves::<true, true>())
+        });
+    }
+    group.finish();
+}
+
+fn make_move(c: &mut Criterion) {
+    let mut group = c.benchmark_group("make_move");
+    for (name, fen) in POSITIONS {
+        let board = Board::from_fen(fen);
+        let moves = board.generate_pseudo_moves::<true, true>();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for m in &moves {
+                    let mut child = *black_box(&board);
+                    child.make_move(m);
+                    black_box(child);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn evaluate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("evaluate");
+    for (name, fen) in POSITIONS {
+        let board = Board::from_fen(fen);
+
+        // A disabled cache refreshes the accumulators from scratch on every evaluation
+        let mut cold = EvalTable {
+            enabled: false,
+            ..Default::default()
+        };
+        group.bench_function(format!("cold/{name}"), |b| {
+            b.iter(|| black_box(&board).evaluate(&mut cold))
+        });
+
+        let mut warm = EvalTable::default();
+        board.evaluate(&mut warm);
+        group.bench_function(format!("warm/{name}"), |b| {
+            b.iter(|| black_box(&board).evaluate(&mut warm))
+        });
+    }
+    group.finish();
+}
+
+fn see(c: &mut Criterion) {
+    let mut group = c.benchmark_group("see");
+    for (name, fen) in POSITIONS {
+        let board = Board::from_fen(fen);
+        let moves = board.generate_pseudo_moves::<true, true>();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                moves
+                    .as_slice()
+                    .iter()
+                    .filter(|&&m| board.see(m, 0))
+                    .count()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+
+    let mut data = SearchData::new();
+    data.deterministic = true;
+    data.silent = true;
+    let limits = SearchLimits::new().nodes(SEARCH_NODES);
+
+    for (name, fen) in POSITIONS {
+        let board = Board::from_fen(fen);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                data.new_game();
+                find_best_move(&board, &limits, &mut data);
+                data.nodes
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, movegen, make_move, evaluate, see, search);
+criterion_main!(benches);
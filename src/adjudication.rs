@@ -0,0 +1,199 @@
+use crate::board::Board;
+use crate::piece::Colour;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum GameResult {
+    WhiteWin,
+    BlackWin,
+    Draw,
+}
+
+impl GameResult {
+    /// Result from White's point of view: 1.0 win, 0.5 draw, 0.0 loss
+    pub fn score(self) -> f32 {
+        match self {
+            GameResult::WhiteWin => 1.0,
+            GameResult::BlackWin => 0.0,
+            GameResult::Draw => 0.5,
+        }
+    }
+
+    /// Side to move loses
+    pub fn loss_for(side: Colour) -> Self {
+        match side {
+            Colour::White => GameResult::BlackWin,
+            Colour::Black => GameResult::WhiteWin,
+        }
+    }
+}
+
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            GameResult::WhiteWin => "1-0",
+            GameResult::BlackWin => "0-1",
+            GameResult::Draw => "1/2-1/2",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Reason {
+    Resign,
+    DrawAdjudication,
+    Tablebase,
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Reason::Resign => "resign adjudication",
+            Reason::DrawAdjudication => "draw adjudication",
+            Reason::Tablebase => "tablebase adjudication",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Probe returning the result of a position with few pieces, e.g. backed by Syzygy tables
+pub type TablebaseProbe = Box<dyn Fn(&Board) -> Option<GameResult> + Send>;
+
+/// Adjudication thresholds, scores in centipawns and lengths in full moves (one score per side)
+pub struct Rules {
+    pub resign_score: i32,
+    pub resign_moves: usize,
+    pub draw_score: i32,
+    pub draw_moves: usize,
+    pub draw_min_move: usize,
+    pub tb_pieces: u32,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            resign_score: 1000,
+            resign_moves: 3,
+            draw_score: 10,
+            draw_moves: 8,
+            draw_min_move: 40,
+            tb_pieces: 6,
+        }
+    }
+}
+
+/// Keeps the score history of a game and decides when it can be stopped early
+pub struct Adjudicator {
+    rules: Rules,
+    tablebase: Option<TablebaseProbe>,
+    scores: Vec<i32>, // From White's point of view, one per ply
+}
+
+impl Adjudicator {
+    pub fn new(rules: Rules, tablebase: Option<TablebaseProbe>) -> Self {
+        Self {
+            rules,
+            tablebase,
+            scores: Vec::with_capacity(256),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.scores.clear();
+    }
+
+    /// Records the score reported by the side to move in `board` (before playing its move) and
+    /// returns the adjudicated result, if any. `fullmove` is the current move number.
+    pub fn update(
+        &mut self,
+        board: &Board,
+        score: i32,
+        fullmove: usize,
+    ) -> Option<(GameResult, Reason)> {
+        self.scores.push(match board.side {
+            Colour::White => score,
+            Colour::Black => -score,
+        });
+
+        if let Some(probe) = &self.tablebase {
+            let pieces = board.occupancy().count_bits();
+            if pieces <= self.rules.tb_pieces {
+                if let Some(result) = probe(board) {
+                    return Some((result, Reason::Tablebase));
+                }
+            }
+        }
+
+        // Both sides must agree on the evaluation, so consider plies from both of them
+        let resign_plies = 2 * self.rules.resign_moves;
+        if resign_plies > 0 && self.scores.len() >= resign_plies {
+            let last = &self.scores[self.scores.len() - resign_plies..];
+            if last.iter().all(|&s| s <= -self.rules.resign_score) {
+                return Some((GameResult::BlackWin, Reason::Resign));
+            }
+            if last.iter().all(|&s| s >= self.rules.resign_score) {
+                return Some((GameResult::WhiteWin, Reason::Resign));
+            }
+        }
+
+        let draw_plies = 2 * self.rules.draw_moves;
+        if draw_plies > 0 && fullmove >= self.rules.draw_min_move && self.scores.len() >= draw_plies
+        {
+            let last = &self.scores[self.scores.len() - draw_plies..];
+            if last.iter().all(|&s| s.abs() <= self.rules.draw_score) {
+                return Some((GameResult::Draw, Reason::DrawAdjudication));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resign() {
+        let mut adj = Adjudicator::new(Rules::default(), None);
+        let mut board = Board::default();
+
+        // White is lost, but only once Black agrees it is resigned
+        for ply in 0..6 {
+            let score = if board.side == Colour::White {
+                -1200
+            } else {
+                1500
+            };
+            let result = adj.update(&board, score, 1 + ply / 2);
+            if ply < 5 {
+                assert_eq!(result, None);
+            } else {
+                assert_eq!(result, Some((GameResult::BlackWin, Reason::Resign)));
+            }
+            board.make_null_move();
+        }
+    }
+
+    #[test]
+    fn test_draw_and_tablebase() {
+        let mut adj = Adjudicator::new(Rules::default(), None);
+        let mut board = Board::default();
+        for ply in 0..16 {
+            assert_eq!(adj.update(&board, 5, 10 + ply / 2), None);
+            board.make_null_move();
+        }
+        assert_eq!(
+            adj.update(&board, 0, 40),
+            Some((GameResult::Draw, Reason::DrawAdjudication))
+        );
+
+        let probe: TablebaseProbe = Box::new(|_| Some(GameResult::WhiteWin));
+        let mut adj = Adjudicator::new(Rules::default(), Some(probe));
+        assert_eq!(adj.update(&Board::default(), 0, 1), None);
+        assert_eq!(
+            adj.update(&Board::from_fen("8/8/4k3/8/8/3QK3/8/8 w - - 0 1"), 0, 1),
+            Some((GameResult::WhiteWin, Reason::Tablebase))
+        );
+    }
+}
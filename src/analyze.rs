@@ -0,0 +1,273 @@
+use crate::board::Board;
+use crate::limits::SearchLimits;
+use crate::moves::Move;
+use crate::search::{find_best_move, legal_best_move, MAX_DEPTH};
+use crate::tables::{Bound, SearchData};
+use crate::uci::parse_fen;
+use std::io::{BufRead, Write};
+
+/// Depth searched when no limit is given
+const DEFAULT_DEPTH: u8 = 12;
+
+/// Limits of every search and format of the results
+struct Options {
+    limits: SearchLimits,
+    json: bool,
+}
+
+/// Result of the search of one position
+#[derive(Debug, PartialEq)]
+pub struct Analysis {
+    pub fen: String,
+    pub best_move: String,
+    pub score: String, // `cp <x>` or `mate <n>`, from the side to move as in UCI
+    pub depth: u8,
+    pub nodes: u64,
+    pub pv: Vec<String>,
+}
+
+impl Analysis {
+    /// `bestmove <m> score (cp <x> | mate <n>) depth <d> nodes <n> pv <moves>`
+    pub fn to_text(&self) -> String {
+        format!(
+            "bestmove {} score {} depth {} nodes {} pv {}",
+            self.best_move,
+            self.score,
+            self.depth,
+            self.nodes,
+            self.pv.join(" ")
+        )
+    }
+
+    /// One JSON object per line, the score as `{"cp": x}` or `{"mate": n}`
+    pub fn to_json(&self) -> String {
+        let score = match self.score.split_once(' ') {
+            Some((kind, value)) => format!("{{\"{kind}\":{value}}}"),
+            None => String::from("null"),
+        };
+        let pv: Vec<String> = self.pv.iter().map(|m| format!("\"{m}\"")).collect();
+        format!(
+            "{{\"fen\":\"{}\",\"bestmove\":\"{}\",\"score\":{score},\"depth\":{},\"nodes\":{},\"pv\":[{}]}}",
+            self.fen,
+            self.best_move,
+            self.depth,
+            self.nodes,
+            pv.join(",")
+        )
+    }
+}
+
+/// Searches `board` within the limits, on `data` which is cleared first
+fn analyse(board: &Board, options: &Options, data: &mut SearchData) -> Analysis {
+    data.clear();
+    data.tt.inc_age();
+    find_best_move(board, &options.limits, data);
+
+    let best_move = legal_best_move(board, data);
+    let pv = match data.last_pv.as_slice() {
+        [first, ..] if *first == best_move => data.last_pv.as_slice().to_vec(),
+        _ => vec![best_move],
+    };
+    let uci = |m: Move| match m {
+        Move::NULL => String::from("0000"),
+        m => m.to_string(),
+    };
+
+    Analysis {
+        fen: board.to_fen(),
+        best_move: uci(best_move),
+        score: data.uci_score(data.eval, Bound::Exact),
+        depth: data.depth.saturating_sub(1),
+        nodes: data.nodes,
+        pv: pv
+            .into_iter()
+            .filter(|&m| m != Move::NULL)
+            .map(uci)
+            .collect(),
+    }
+}
+
+/// Position of `startpos` or a FEN, whose move counters may be left out
+fn parse_position(fields: &[&str]) -> Result<Board, String> {
+    match fields {
+        ["startpos"] => Ok(Board::default()),
+        fen => parse_fen(fen).map_err(|e| format!("Invalid position: {e}")),
+    }
+}
+
+/// Positions of a list of FENs, one per line, or of the `[FEN "..."]` tags of a PGN. Other
+/// tags and the movetext are skipped, so games are analysed from their starting position.
+fn read_positions<R: BufRead>(input: R) -> Vec<Result<Board, String>> {
+    let mut positions = Vec::new();
+    for line in input.lines() {
+        let Ok(line) = line else { break };
+        let line = line.trim();
+        let fen = match line.strip_prefix("[FEN \"") {
+            Some(tag) => tag.trim_end_matches("\"]"),
+            None => line,
+        };
+
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let is_position = match fields.first() {
+            Some(&"startpos") => fields.len() == 1,
+            Some(pieces) => pieces.contains('/'),
+            None => false,
+        };
+        if is_position {
+            positions.push(parse_position(&fields));
+        }
+    }
+    positions
+}
+
+/// Flags following the position. Without a depth the search goes as deep as a node or time
+/// limit allows, or to `DEFAULT_DEPTH` when there is none.
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        limits: SearchLimits::new(),
+        json: false,
+    };
+    let mut depth = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        if flag == "--json" {
+            options.json = true;
+            i += 1;
+            continue;
+        }
+
+        let value = args.get(i + 1).map(String::as_str).unwrap_or_default();
+        let invalid = || format!("Invalid value for {flag}: {value}");
+        let limits = std::mem::take(&mut options.limits);
+        options.limits = match flag {
+            "--depth" => {
+                depth = Some(value.parse().map_err(|_| invalid())?);
+                limits
+            }
+            "--nodes" => limits.nodes(value.parse().map_err(|_| invalid())?),
+            "--movetime" => limits.movetime(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("Invalid argument: {flag}")),
+        };
+        i += 2;
+    }
+
+    let limited = options.limits != SearchLimits::new();
+    let depth = match depth {
+        Some(depth) => depth,
+        None if limited => MAX_DEPTH,
+        None => DEFAULT_DEPTH,
+    };
+    options.limits = std::mem::take(&mut options.limits).depth(depth);
+    Ok(options)
+}
+
+/// Analyses each position in turn, writing the results in the same order. Positions which
+/// cannot be read are reported on stderr and skipped.
+fn analyse_all<W: Write>(positions: Vec<Result<Board, String>>, options: &Options, mut out: W) {
+    let mut data = SearchData::new();
+    data.silent = true;
+    for position in positions {
+        match position {
+            Ok(board) => {
+                let analysis = analyse(&board, options, &mut data);
+                let line = if options.json {
+                    analysis.to_json()
+                } else {
+                    analysis.to_text()
+                };
+                let _ = writeln!(out, "{line}");
+                let _ = out.flush();
+            }
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// `oxide analyze (<fen> | startpos | -) [--depth N] [--nodes N] [--movetime MS] [--json]`,
+/// where `-` reads a FEN list or a PGN from stdin
+pub fn run(args: &[String]) -> Result<(), String> {
+    let flags = args
+        .iter()
+        .position(|a| a.starts_with("--"))
+        .unwrap_or(args.len());
+    let options = parse_options(&args[flags..])?;
+
+    let positions = match &args[..flags] {
+        [] => return Err(String::from("Missing position")),
+        [stdin] if stdin == "-" => read_positions(std::io::stdin().lock()),
+        // The FEN may come as a single argument or as one per field
+        fields => {
+            let fields: Vec<&str> = fields.iter().flat_map(|f| f.split_whitespace()).collect();
+            vec![Ok(parse_position(&fields)?)]
+        }
+    };
+
+    analyse_all(positions, &options, std::io::stdout().lock());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_analyze() {
+        let input = "[Event \"Test\"]\n\
+                     [FEN \"6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1\"]\n\
+                     \n\
+                     1. Rd8# 1-0\n\
+                     startpos\n\
+                     8/8 w - - 0 1\n\
+                     8/8/8/8/8/8/8/8 w - - 0 1\n\
+                     6k1/5ppp/8/8/8/8/5PPP/3R2K1 b - -\n";
+        let positions = read_positions(input.as_bytes());
+        assert_eq!(positions.len(), 5);
+        assert!(positions[2].is_err() && positions[3].is_err());
+
+        let options = parse_options(&args("--nodes 5000 --json")).unwrap();
+        let limits = &options.limits;
+        assert_eq!(
+            (limits.depth, limits.nodes, options.json),
+            (MAX_DEPTH, 5000, true)
+        );
+        let mut output = Vec::new();
+        analyse_all(positions, &options, &mut output);
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3, "{output}");
+        assert!(lines[0].starts_with(
+            "{\"fen\":\"6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1\",\"bestmove\":\"d1d8\",\"score\":{\"mate\":1},"
+        ));
+        assert!(lines[0].ends_with(",\"pv\":[\"d1d8\"]}"));
+        assert!(lines[2].starts_with("{\"fen\":\"6k1/5ppp/8/8/8/8/5PPP/3R2K1 b - - 0 1\""));
+
+        // Plain text, with the PV starting at the best move
+        let options = parse_options(&args("--depth 4")).unwrap();
+        let mut data = SearchData::new();
+        data.silent = true;
+        let analysis = analyse(&Board::default(), &options, &mut data);
+        assert_eq!(analysis.depth, 4);
+        assert_eq!(analysis.pv[0], analysis.best_move);
+        assert!(analysis
+            .to_text()
+            .starts_with(&format!("bestmove {} score cp ", analysis.best_move)));
+
+        assert!(parse_options(&args("--depth x")).is_err());
+        assert!(parse_options(&args("--ponder")).is_err());
+        assert!(run(&args("--depth 3")).is_err());
+        assert!(run(&args("8/8/8 w - - 0 1 --depth 3")).is_err());
+
+        // A malformed score does not bring the JSON output down
+        let analysis = Analysis {
+            score: String::from("none"),
+            ..analysis
+        };
+        assert!(analysis.to_json().contains(",\"score\":null,"));
+    }
+}
@@ -9,6 +9,21 @@ use crate::square::Square;
 #[derive(PartialEq, Eq, PartialOrd, Clone, Copy, Default, Hash)]
 pub struct BitBoard(pub u64);
 
+impl std::fmt::Debug for BitBoard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BitBoard({:#018x})", self.0)
+    }
+}
+
+/// Iterates over the occupied squares, from the least significant bit
+impl Iterator for BitBoard {
+    type Item = Square;
+
+    fn next(&mut self) -> Option<Square> {
+        (self.0 != 0).then(|| self.pop_lsb())
+    }
+}
+
 impl std::ops::BitAnd for BitBoard {
     type Output = Self;
     fn bitand(self, rhs: Self) -> Self {
@@ -68,15 +83,6 @@ impl BitBoard {
     /// Starting ranks for pawns: White (rank 2) and Black (rank 7).
     pub const START_RANKS: [Self; 2] = [Self(0x000000000000FF00), Self(0x00FF000000000000)];
 
-    /// Intermediate squares for white king-side castle
-    pub const WHITE_KING_CASTLE: Self = Self(0x0000000000000060);
-    /// Intermediate squares for black king-side castle
-    pub const BLACK_KING_CASTLE: Self = Self(0x6000000000000000);
-    /// Intermediate squares for white queen-side castle
-    pub const WHITE_QUEEN_CASTLE: Self = Self(0x000000000000000e);
-    /// Intermediate squares for black queen-side castle
-    pub const BLACK_QUEEN_CASTLE: Self = Self(0x0e00000000000000);
-
     // White squares
     pub const WHITE_SQUARES: Self = Self(0x55AA55AA55AA55AA);
     // Black squares
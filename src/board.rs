@@ -1,12 +1,13 @@
-use crate::castle::CASTLE_MASK;
 use crate::constants::{queen_attacks, FILE_A, FILE_H};
-use crate::network::{EvalTable, Network};
+use crate::network::{Accumulator, EvalEntry, EvalTable, Network};
+use crate::tunables::{BLEND_THRESHOLD, BLEND_WEIGHT, SCALE_BASE, SCALE_DIVISOR, SCALE_NORM};
 use crate::{
     bitboard::BitBoard,
     castle::CastlingRights,
     constants::{bishop_attacks, rook_attacks, KING_ATTACKS, KNIGHT_ATTACKS, PIECE_VALUES},
     moves::{Move, MoveKind},
-    piece::{Colour, Piece},
+    piece::{Colour, Piece, PieceType},
+    search::MAX_EVAL,
     square::Square,
     zobrist::ZHash,
 };
@@ -14,9 +15,18 @@ use crate::{
 use super::constants::{between, pinned_moves, PAWN_ATTACKS};
 use super::moves::MoveList;
 
+/// Material which can never mate: bare kings, or a single minor piece against a bare king
+const INSUFFICIENT_MATERIAL: [ZHash; 5] = [
+    ZHash::material_key(&[Piece::WK, Piece::BK]),
+    ZHash::material_key(&[Piece::WK, Piece::BK, Piece::WN]),
+    ZHash::material_key(&[Piece::WK, Piece::BK, Piece::BN]),
+    ZHash::material_key(&[Piece::WK, Piece::BK, Piece::WB]),
+    ZHash::material_key(&[Piece::WK, Piece::BK, Piece::BB]),
+];
+
 #[derive(Copy, Clone)]
 pub struct Board {
-    pub pieces: [BitBoard; 6],
+    pub pieces: PieceBoards,
     pub sides: [BitBoard; 2],
 
     piece_map: [Piece; Square::COUNT],
@@ -25,37 +35,83 @@ pub struct Board {
     pub castling_rights: CastlingRights,
     pub en_passant: Option<Square>,
     pub halfmoves: u8,
+    pub fullmoves: u16,
     pub hash: ZHash,
+    pub pawn_hash: ZHash,
+    pub material_hash: ZHash, // Number of pieces of each kind, regardless of their squares
     pub checkers: BitBoard,
     pub threats: BitBoard,
     pub pinned: BitBoard,
 }
 
+/// Bitboards of each piece type with both colours together, intersect with `Board::sides` (or
+/// use `Board::pieces`) for the pieces of one side
+#[derive(Clone, Copy)]
+pub struct PieceBoards(pub [BitBoard; 6]);
+
+impl std::ops::Index<PieceType> for PieceBoards {
+    type Output = BitBoard;
+
+    #[inline]
+    fn index(&self, piece_type: PieceType) -> &BitBoard {
+        &self.0[piece_type as usize]
+    }
+}
+
+impl std::ops::IndexMut<PieceType> for PieceBoards {
+    #[inline]
+    fn index_mut(&mut self, piece_type: PieceType) -> &mut BitBoard {
+        &mut self.0[piece_type as usize]
+    }
+}
+
+impl Default for Board {
+    fn default() -> Self {
+        Self::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
+    }
+}
+
 impl Board {
     pub fn new() -> Self {
         Board {
-            pieces: [BitBoard::EMPTY; 6],
+            pieces: PieceBoards([BitBoard::EMPTY; 6]),
             sides: [BitBoard::EMPTY; 2],
             piece_map: [Piece::Empty; Square::COUNT],
             en_passant: None,
             castling_rights: CastlingRights::NONE,
             halfmoves: 0,
+            fullmoves: 1,
             side: Colour::White,
             hash: ZHash::NULL,
+            pawn_hash: ZHash::NULL,
+            material_hash: ZHash::NULL,
             checkers: BitBoard::EMPTY,
             threats: BitBoard::EMPTY,
             pinned: BitBoard::EMPTY,
         }
     }
 
-    pub fn default() -> Self {
-        Self::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1")
-    }
-
     pub fn piece_at(&self, square: Square) -> Piece {
         self.piece_map[square.index()]
     }
 
+    /// Squares occupied by either side
+    #[inline]
+    pub fn occupancy(&self) -> BitBoard {
+        self.sides[Colour::White as usize] | self.sides[Colour::Black as usize]
+    }
+
+    /// Pieces of `colour` and type `piece`
+    #[inline]
+    pub fn pieces(&self, colour: Colour, piece: PieceType) -> BitBoard {
+        self.pieces[piece] & self.sides[colour as usize]
+    }
+
+    /// Every piece on the board with its square, from a1 to h8
+    pub fn iter_pieces(&self) -> impl Iterator<Item = (Square, Piece)> + '_ {
+        self.occupancy().map(|sq| (sq, self.piece_at(sq)))
+    }
+
     pub fn capture_piece(&self, m: Move) -> Piece {
         if m.get_type() == MoveKind::EnPassant {
             match self.side {
@@ -71,20 +127,130 @@ impl Board {
         let colour = piece.colour() as usize;
         let bit = 1u64 << square.index();
         self.sides[colour] ^= bit;
-        self.pieces[piece.index()] ^= bit;
+        self.pieces[piece.piece_type()] ^= bit;
         self.piece_map[square.index()] = piece;
         self.hash.hash_piece(piece, square);
+        if piece.is_pawn() {
+            self.pawn_hash.hash_piece(piece, square);
+        }
+        self.material_hash.add_material(piece);
     }
 
     fn remove_piece(&mut self, square: Square) {
         let piece = self.piece_at(square);
         let colour = piece.colour() as usize;
         let bit = 1u64 << square.index();
+        self.material_hash.remove_material(piece);
 
         self.sides[colour] ^= bit;
-        self.pieces[piece.index()] ^= bit;
+        self.pieces[piece.piece_type()] ^= bit;
         self.piece_map[square.index()] = Piece::Empty;
         self.hash.hash_piece(piece, square);
+        if piece.is_pawn() {
+            self.pawn_hash.hash_piece(piece, square);
+        }
+    }
+
+    /// Puts `piece` on `square`, replacing whatever was there, for setting up positions without
+    /// a FEN. Castling rights and en passant squares which no longer hold are dropped. A side
+    /// may have at most 16 pieces, as in a FEN.
+    pub fn put_piece(&mut self, piece: Piece, square: Square) {
+        if self.piece_at(square) != Piece::Empty {
+            self.remove_piece(square);
+        }
+        if piece != Piece::Empty {
+            self.set_piece(piece, square);
+        }
+        self.refresh();
+    }
+
+    /// Removes the piece on `square`, returning it (Empty if there was none)
+    pub fn take_piece(&mut self, square: Square) -> Piece {
+        let piece = self.piece_at(square);
+        self.put_piece(Piece::Empty, square);
+        piece
+    }
+
+    /// Gives the move to `side`, which loses any en passant square as it belonged to the other
+    pub fn set_side(&mut self, side: Colour) {
+        if side != self.side {
+            self.side = side;
+            self.en_passant = None;
+        }
+        self.refresh();
+    }
+
+    /// Sets the castling rights from the castling field of a FEN, `KQkq` or rook files, failing
+    /// when a king or rook holding a right is not on its square
+    pub fn set_castling(&mut self, rights: &str) -> Result<(), String> {
+        let parsed = CastlingRights::parse(rights, self)?;
+        let previous = std::mem::replace(&mut self.castling_rights, parsed);
+        for colour in [Colour::White, Colour::Black] {
+            for kingside in [true, false] {
+                if parsed.has(CastlingRights::flag(colour, kingside))
+                    && !self.has_castling_pieces(colour, kingside)
+                {
+                    self.castling_rights = previous;
+                    return Err(format!("Invalid CastlingRights for the position: {rights}"));
+                }
+            }
+        }
+
+        self.refresh();
+        Ok(())
+    }
+
+    /// Whether the king of `colour` is alone on its back rank with the rook of its castling
+    /// right on the `kingside` or queenside
+    fn has_castling_pieces(&self, colour: Colour, kingside: bool) -> bool {
+        let row = match colour {
+            Colour::White => 0,
+            Colour::Black => 7,
+        };
+        let king = self.pieces(colour, PieceType::King);
+        let rook = self.castling_rights.rook_square(colour, kingside);
+        king.count_bits() == 1
+            && king.lsb().row() == row
+            && self.piece_at(rook) == Piece::new(PieceType::Rook, colour)
+            && (rook.col() > king.lsb().col()) == kingside
+    }
+
+    /// Brings everything derived from the pieces and the side to move up to date after editing
+    /// them: rights and en passant squares which no longer hold are dropped, then the hash is
+    /// recomputed, and the threats, checkers and pins once each side has a single king
+    fn refresh(&mut self) {
+        for colour in [Colour::White, Colour::Black] {
+            for kingside in [true, false] {
+                if !self.has_castling_pieces(colour, kingside) {
+                    self.castling_rights.flags &= !CastlingRights::flag(colour, kingside);
+                }
+            }
+        }
+
+        // The pawn which double pushed stands in front of the square, with both squares it
+        // went through empty
+        let (row, pushed, start) = match self.side {
+            Colour::White => (5, 4, 6),
+            Colour::Black => (2, 3, 1),
+        };
+        self.en_passant = self.en_passant.filter(|&sq| {
+            let square = |row| self.piece_at(Square::from_row_col(row, sq.col()));
+            sq.row() == row
+                && square(pushed) == Piece::new(PieceType::Pawn, !self.side)
+                && square(row) == Piece::Empty
+                && square(start) == Piece::Empty
+                && self.can_capture_en_passant(sq, !self.side)
+        });
+
+        self.hash = ZHash::new(self);
+        let kings = |colour| self.pieces(colour, PieceType::King).count_bits() == 1;
+        if kings(Colour::White) && kings(Colour::Black) {
+            self.calculate_threats();
+            self.pinned_and_checkers();
+        } else {
+            (self.threats, self.checkers, self.pinned) =
+                (BitBoard::EMPTY, BitBoard::EMPTY, BitBoard::EMPTY);
+        }
     }
 
     pub fn make_move(&mut self, m: Move) {
@@ -104,11 +270,12 @@ impl Board {
             self.halfmoves += 1;
         }
 
-        let new_rights =
-            CastlingRights(old_rights.0 & CASTLE_MASK[src.index()] & CASTLE_MASK[dest.index()]);
-        if new_rights != old_rights {
-            self.castling_rights = new_rights;
-            self.hash.swap_castle(old_rights, new_rights);
+        if old_rights.flags != 0 {
+            let new_rights = old_rights.update(src_piece, src, dest);
+            if new_rights != old_rights {
+                self.castling_rights = new_rights;
+                self.hash.swap_castle(old_rights, new_rights);
+            }
         }
 
         match move_type {
@@ -116,9 +283,12 @@ impl Board {
                 self.remove_piece(src);
                 self.set_piece(src_piece, dest);
 
-                if matches!(move_type, MoveKind::DoublePush) {
-                    self.en_passant = Some(src.shift::<8>(src_piece.colour()));
-                    self.hash.hash_enpassant(self.en_passant.unwrap());
+                if move_type == MoveKind::DoublePush {
+                    let skipped = src.shift::<8>(src_piece.colour());
+                    if self.can_capture_en_passant(skipped, src_piece.colour()) {
+                        self.en_passant = Some(skipped);
+                        self.hash.hash_enpassant(skipped);
+                    }
                 }
             }
             MoveKind::Capture => {
@@ -133,11 +303,7 @@ impl Board {
                 self.set_piece(src_piece, dest);
             }
             MoveKind::Castle => {
-                let is_kingside = dest.col() > src.col();
-                let (rook_src_col, rook_dest_col) = if is_kingside { (7, 5) } else { (0, 3) };
-                let row = src.row();
-                let rook_src = Square::from_row_col(row, rook_src_col);
-                let rook_dest = Square::from_row_col(row, rook_dest_col);
+                let (rook_src, rook_dest) = old_rights.castle_rook(dest);
                 let rook_piece = self.piece_at(rook_src);
 
                 self.remove_piece(src);
@@ -157,6 +323,7 @@ impl Board {
             }
         }
 
+        self.fullmoves += u16::from(self.side == Colour::Black);
         self.side = !self.side;
         self.hash.hash_side();
         self.calculate_threats();
@@ -171,7 +338,7 @@ impl Board {
         let occ = (self.sides[0] | self.sides[1]) ^ self.king_square(self.side as usize).to_board();
 
         // Pawn attacks
-        let pawns = self.pieces[Piece::WP.index()] & self.sides[attacker];
+        let pawns = self.pieces[PieceType::Pawn] & self.sides[attacker];
         if attacker == 0 {
             self.threats.0 |= (pawns.0 & !FILE_A) << 7;
             self.threats.0 |= (pawns.0 & !FILE_H) << 9;
@@ -181,23 +348,23 @@ impl Board {
         }
 
         // Rooks and queens (Orthogonal)
-        let mut rooks = (self.pieces[Piece::WR.index()] | self.pieces[Piece::WQ.index()])
-            & self.sides[attacker];
+        let mut rooks =
+            (self.pieces[PieceType::Rook] | self.pieces[PieceType::Queen]) & self.sides[attacker];
         while rooks != BitBoard::EMPTY {
             let sq = rooks.pop_lsb();
             self.threats |= rook_attacks(occ.0, sq.index());
         }
 
         // Bishops and queens (diagonals)
-        let mut bishops = (self.pieces[Piece::WB.index()] | self.pieces[Piece::WQ.index()])
-            & self.sides[attacker];
+        let mut bishops =
+            (self.pieces[PieceType::Bishop] | self.pieces[PieceType::Queen]) & self.sides[attacker];
         while bishops != BitBoard::EMPTY {
             let sq = bishops.pop_lsb();
             self.threats |= bishop_attacks(occ.0, sq.index());
         }
 
         // Knight attacks (jumpers)
-        let mut knights = self.pieces[Piece::WN.index()] & self.sides[attacker];
+        let mut knights = self.pieces[PieceType::Knight] & self.sides[attacker];
         while knights != BitBoard::EMPTY {
             let sq = knights.pop_lsb();
             self.threats |= KNIGHT_ATTACKS[sq.index()];
@@ -215,20 +382,20 @@ impl Board {
         self.pinned = BitBoard::EMPTY;
         let attacker = !self.side as usize;
         let king_sq = self.king_square(self.side as usize);
-        let occ = self.sides[0] | self.sides[1];
+        let occ = self.occupancy();
 
         self.checkers = (KNIGHT_ATTACKS[king_sq.index()]
-            & self.pieces[Piece::WN.index()]
+            & self.pieces[PieceType::Knight]
             & self.sides[attacker])
             | (PAWN_ATTACKS[self.side as usize][king_sq.index()]
-                & self.pieces[Piece::WP.index()]
+                & self.pieces[PieceType::Pawn]
                 & self.sides[attacker]);
 
-        let mut sliders_attacks = ((self.pieces[Piece::WB.index()]
-            | self.pieces[Piece::WQ.index()])
+        let mut sliders_attacks = ((self.pieces[PieceType::Bishop]
+            | self.pieces[PieceType::Queen])
             & self.sides[attacker]
             & bishop_attacks(BitBoard::EMPTY.0, king_sq.index()))
-            | ((self.pieces[Piece::WR.index()] | self.pieces[Piece::WQ.index()])
+            | ((self.pieces[PieceType::Rook] | self.pieces[PieceType::Queen])
                 & self.sides[attacker]
                 & rook_attacks(BitBoard::EMPTY.0, king_sq.index()));
 
@@ -243,55 +410,46 @@ impl Board {
         }
     }
 
-    /// Checks if the given castle is legal by checking castling_rights, checks, that there
-    /// are no pieces in between and passing squares are not threatened.
+    /// Checks if the castle of the king to `dest` is legal by checking castling_rights, checks,
+    /// that there are no pieces in the way of the king and its rook and that the squares the
+    /// king passes are not threatened.
     pub fn is_castle_legal(&self, dest: Square) -> bool {
-        let (rook_sq, king_pass, inter_squares, right_bit) = match (self.side, dest) {
-            (Colour::White, d) if d == Square::from("g1") => (
-                Square::from("h1"),
-                Square::from("f1"),
-                BitBoard::WHITE_KING_CASTLE,
-                CastlingRights::WK,
-            ),
-            (Colour::White, d) if d == Square::from("c1") => (
-                Square::from("a1"),
-                Square::from("d1"),
-                BitBoard::WHITE_QUEEN_CASTLE,
-                CastlingRights::WQ,
-            ),
-            (Colour::Black, d) if d == Square::from("g8") => (
-                Square::from("h8"),
-                Square::from("f8"),
-                BitBoard::BLACK_KING_CASTLE,
-                CastlingRights::BK,
-            ),
-            (Colour::Black, d) if d == Square::from("c8") => (
-                Square::from("a8"),
-                Square::from("d8"),
-                BitBoard::BLACK_QUEEN_CASTLE,
-                CastlingRights::BQ,
-            ),
+        let (back_row, rights) = match self.side {
+            Colour::White => (0, [CastlingRights::WQ, CastlingRights::WK]),
+            Colour::Black => (7, [CastlingRights::BQ, CastlingRights::BK]),
+        };
+        let right = match dest.col() {
+            2 => rights[0],
+            6 => rights[1],
             _ => return false,
         };
+        let king = self.king_square(self.side as usize);
+        if dest.row() != back_row
+            || king.row() != back_row
+            || king == dest
+            || !self.castling_rights.has(right)
+            || self.checkers != BitBoard::EMPTY
+        {
+            return false;
+        }
 
-        let occ = self.sides[Colour::White as usize] | self.sides[Colour::Black as usize];
-        let rights_ok = self.castling_rights.0 & right_bit != 0;
-        let path_clear = inter_squares & occ == BitBoard::EMPTY;
-        if !(path_clear && rights_ok) {
+        let (rook_sq, rook_dest) = self.castling_rights.castle_rook(dest);
+        if !self.pieces(self.side, PieceType::Rook).contains(rook_sq) {
             return false;
         }
-        let safe = self.checkers == BitBoard::EMPTY
-            && (king_pass.to_board() | dest.to_board()) & self.threats == BitBoard::EMPTY;
-        safe && self.pieces[Piece::WR.index()].0
-            & self.sides[self.side as usize].0
-            & (1 << rook_sq.index())
-            != 0
+
+        // The king and the castling rook do not block each other
+        let occ = self.occupancy() ^ king.to_board() ^ rook_sq.to_board();
+        let king_path = between(king, dest) | dest.to_board();
+        let rook_path = between(rook_sq, rook_dest) | rook_dest.to_board();
+        (king_path | rook_path) & occ == BitBoard::EMPTY
+            && king_path & self.threats == BitBoard::EMPTY
     }
 
     pub fn generate_pseudo_moves<const QUIET: bool, const CAP: bool>(&self) -> MoveList {
         let mut moves = MoveList::default();
         let side_idx = self.side as usize;
-        let occ = self.sides[Colour::White as usize] | self.sides[Colour::Black as usize];
+        let occ = self.occupancy();
 
         // King moves
         self.all_king_moves::<QUIET, CAP>(occ.0, &mut moves);
@@ -308,21 +466,21 @@ impl Board {
         self.all_knight_moves::<QUIET, CAP>(occ, &mut moves);
 
         // Bishop moves
-        let mut bishop_bb = self.pieces[Piece::WB.index()] & self.sides[side_idx];
+        let mut bishop_bb = self.pieces[PieceType::Bishop] & self.sides[side_idx];
         while bishop_bb != BitBoard::EMPTY {
             let src = bishop_bb.pop_lsb();
             self.all_slider_moves::<QUIET, CAP>(src, occ.0, bishop_attacks, &mut moves);
         }
 
         // Rook moves
-        let mut rook_bb = self.pieces[Piece::WR.index()] & self.sides[side_idx];
+        let mut rook_bb = self.pieces[PieceType::Rook] & self.sides[side_idx];
         while rook_bb != BitBoard::EMPTY {
             let src = rook_bb.pop_lsb();
             self.all_slider_moves::<QUIET, CAP>(src, occ.0, rook_attacks, &mut moves);
         }
 
         // Queen moves
-        let mut queen_bb = self.pieces[Piece::WQ.index()] & self.sides[side_idx];
+        let mut queen_bb = self.pieces[PieceType::Queen] & self.sides[side_idx];
         while queen_bb != BitBoard::EMPTY {
             let src = queen_bb.pop_lsb();
             self.all_slider_moves::<QUIET, CAP>(src, occ.0, queen_attacks, &mut moves);
@@ -345,10 +503,9 @@ impl Board {
                 ^ src.to_board()
                 ^ dest.to_board()
                 ^ captured_pawn_sq.to_board();
-            let diagonal_pieces = (self.pieces[Piece::WB.index()] | self.pieces[Piece::WQ.index()])
+            let diagonal_pieces = (self.pieces[PieceType::Bishop] | self.pieces[PieceType::Queen])
                 & self.sides[!self.side as usize];
-            let orthogonal_pieces = (self.pieces[Piece::WR.index()]
-                | self.pieces[Piece::WQ.index()])
+            let orthogonal_pieces = (self.pieces[PieceType::Rook] | self.pieces[PieceType::Queen])
                 & self.sides[!self.side as usize];
             return (bishop_attacks(occ.0, king_pos.index()) & diagonal_pieces == BitBoard::EMPTY)
                 && (rook_attacks(occ.0, king_pos.index()) & orthogonal_pieces == BitBoard::EMPTY);
@@ -378,10 +535,34 @@ impl Board {
         }
     }
 
+    /// Whether `m` can be generated in this position, ignoring pins and checks
+    pub fn is_pseudo_legal(&self, m: Move) -> bool {
+        m != Move::NULL
+            && self
+                .generate_pseudo_moves::<true, true>()
+                .into_iter()
+                .any(|x| x == m)
+    }
+
+    /// Whether the side to move has any legal move, being mated or stalemated otherwise
+    pub fn has_legal_move(&self) -> bool {
+        self.generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .any(|m| self.is_legal(m))
+    }
+
     pub fn in_check(&self) -> bool {
         self.checkers != BitBoard::EMPTY
     }
 
+    /// Whether a pawn of the opponent of `pusher` attacks the square skipped by its double push,
+    /// as only then is there an en passant square, the position (and its hash) otherwise being
+    /// the same as with a single step
+    fn can_capture_en_passant(&self, square: Square, pusher: Colour) -> bool {
+        let pawns = self.pieces[PieceType::Pawn] & self.sides[!pusher as usize];
+        PAWN_ATTACKS[pusher as usize][square.index()] & pawns != BitBoard::EMPTY
+    }
+
     pub fn make_null_move(&mut self) {
         self.side = !self.side;
         self.hash.hash_side();
@@ -395,34 +576,34 @@ impl Board {
         self.pinned_and_checkers();
     }
 
-    pub fn is_king_pawn(&self) -> bool {
-        let occ = self.sides[self.side as usize];
-        let pawn_king = self.pieces[Piece::WP.index()] | self.pieces[Piece::WK.index()];
-        occ ^ (occ & pawn_king) == BitBoard::EMPTY
+    /// Whether `side` has any piece other than pawns and its king
+    pub fn has_non_pawn_material(&self, side: Colour) -> bool {
+        let pawn_king = self.pieces[PieceType::Pawn] | self.pieces[PieceType::King];
+        self.sides[side as usize] & !pawn_king != BitBoard::EMPTY
+    }
+
+    /// Number of squares of the side to move's king and its surroundings which the opponent
+    /// attacks, a cheap measure of how dangerous the position is for it
+    pub fn king_danger(&self) -> u32 {
+        let king = self.king_square(self.side as usize);
+        ((KING_ATTACKS[king.index()] | king.to_board()) & self.threats).count_bits()
     }
 
     pub fn is_draw(&self) -> bool {
-        if self.halfmoves >= 100 {
+        if self.halfmoves >= 100 || INSUFFICIENT_MATERIAL.contains(&self.material_hash) {
             return true;
         }
 
-        if self.pieces[Piece::WP.index()]
-            | self.pieces[Piece::WQ.index()]
-            | self.pieces[Piece::WR.index()]
+        if self.pieces[PieceType::Pawn]
+            | self.pieces[PieceType::Queen]
+            | self.pieces[PieceType::Rook]
             == BitBoard::EMPTY
         {
-            if (self.sides[Colour::White as usize] | self.sides[Colour::Black as usize])
-                .count_bits()
-                <= 3
-            {
-                return true;
-            }
-
-            if self.pieces[Piece::WN.index()] != BitBoard::EMPTY {
+            if self.pieces[PieceType::Knight] != BitBoard::EMPTY {
                 return false;
             }
 
-            let bishop_pos = self.pieces[Piece::WB.index()];
+            let bishop_pos = self.pieces[PieceType::Bishop];
             return bishop_pos & BitBoard::WHITE_SQUARES == bishop_pos
                 || bishop_pos & BitBoard::BLACK_SQUARES == bishop_pos;
         }
@@ -431,64 +612,102 @@ impl Board {
     }
 
     pub fn king_square(&self, colour: usize) -> Square {
-        let king_bb = self.pieces[Piece::WK.index()] & self.sides[colour];
+        let king_bb = self.pieces[PieceType::King] & self.sides[colour];
         king_bb.lsb()
     }
 
     pub fn evaluate(&self, cache: &mut EvalTable) -> i32 {
-        let white_king_sq = self.king_square(Colour::White as usize).index();
-        let black_king_sq = self.king_square(Colour::Black as usize).index();
-
-        let wbucket = Network::get_bucket::<0>(white_king_sq);
-        let bbucket = Network::get_bucket::<1>(black_king_sq);
-
-        let entry = &mut cache.table[wbucket][bbucket];
-
-        let mut addf = [[0u16; 32]; 2];
-        let mut subf = [[0u16; 32]; 2];
-        let (adds, subs) = self.fill_diff(
-            &entry.bbs,
-            &mut addf,
-            &mut subf,
-            white_king_sq,
-            black_king_sq,
-        );
+        self.scale(self.blend(self.hybrid(self.network_eval(cache))))
+    }
+
+    /// Output of the network from the side to move, before blending and material scaling
+    pub fn network_eval(&self, cache: &mut EvalTable) -> i32 {
+        let white = *self.refresh_accumulator::<0>(cache);
+        let black = self.refresh_accumulator::<1>(cache);
+        self.network_out(&white, black)
+    }
+
+    /// Same as `evaluate`, but computing both accumulators from scratch on the stack instead
+    /// of through an `EvalTable`. It only reads the board, so any number of threads can score
+    /// positions at once without each allocating a cache.
+    pub fn evaluate_fresh(&self) -> i32 {
+        let mut white = EvalEntry::default();
+        let mut black = EvalEntry::default();
+        self.update_entry::<0>(&mut white);
+        self.update_entry::<1>(&mut black);
+        self.scale(self.blend(self.hybrid(self.network_out(&white.acc, &black.acc))))
+    }
+
+    fn network_out(&self, white: &Accumulator, black: &Accumulator) -> i32 {
+        match self.side {
+            Colour::White => Network::out(self, white, black),
+            Colour::Black => Network::out(self, black, white),
+        }
+    }
+
+    /// Brings the cached accumulator of the king bucket of `SIDE` up to date with this position
+    /// by diffing it against the position it was last used for
+    pub fn refresh_accumulator<'a, const SIDE: usize>(
+        &self,
+        cache: &'a mut EvalTable,
+    ) -> &'a Accumulator {
+        let bucket = Network::get_bucket::<SIDE>(self.king_square(SIDE).index());
+        let enabled = cache.enabled;
+        let entry = cache.entry::<SIDE>(bucket);
+        if !enabled {
+            *entry = EvalEntry::default();
+        }
+
+        let empty = entry.bbs[0] | entry.bbs[1] == 0;
+        let changes = self.update_entry::<SIDE>(entry);
+
+        cache.stats.probes += 1;
+        if empty {
+            cache.stats.refreshes += 1;
+        } else if changes == 0 {
+            cache.stats.hits += 1;
+        } else {
+            cache.stats.partial += 1;
+        }
+
+        &cache.entry::<SIDE>(bucket).acc
+    }
+
+    /// Applies the features which differ between the position of `entry` and this one to its
+    /// accumulator, returning how many changed. An empty entry is a full refresh.
+    fn update_entry<const SIDE: usize>(&self, entry: &mut EvalEntry) -> usize {
+        let king_sq = self.king_square(SIDE).index();
+        let mut addf = [0u16; 32];
+        let mut subf = [0u16; 32];
+        let (adds, subs) = self.fill_diff::<SIDE>(&entry.bbs, &mut addf, &mut subf, king_sq);
 
-        entry.white.update_multi(&addf[0][..adds], &subf[0][..subs]);
-        entry.black.update_multi(&addf[1][..adds], &subf[1][..subs]);
+        entry.acc.update_multi(&addf[..adds], &subf[..subs]);
 
         entry.bbs = [
             self.sides[Colour::White as usize].0,
             self.sides[Colour::Black as usize].0,
-            self.pieces[Piece::WP.index()].0,
-            self.pieces[Piece::WN.index()].0,
-            self.pieces[Piece::WB.index()].0,
-            self.pieces[Piece::WR.index()].0,
-            self.pieces[Piece::WQ.index()].0,
-            self.pieces[Piece::WK.index()].0,
+            self.pieces[PieceType::Pawn].0,
+            self.pieces[PieceType::Knight].0,
+            self.pieces[PieceType::Bishop].0,
+            self.pieces[PieceType::Rook].0,
+            self.pieces[PieceType::Queen].0,
+            self.pieces[PieceType::King].0,
         ];
 
-        let eval = match self.side {
-            Colour::White => Network::out(&entry.white, &entry.black),
-            Colour::Black => Network::out(&entry.black, &entry.white),
-        };
-
-        self.scale(eval)
+        adds + subs
     }
 
-    fn fill_diff(
+    fn fill_diff<const SIDE: usize>(
         &self,
         bbs: &[u64; 8],
-        add_feats: &mut [[u16; 32]; 2],
-        sub_feats: &mut [[u16; 32]; 2],
-        white_king_sq: usize,
-        black_king_sq: usize,
+        add_feats: &mut [u16; 32],
+        sub_feats: &mut [u16; 32],
+        king_sq: usize,
     ) -> (usize, usize) {
         let mut adds = 0;
         let mut subs = 0;
 
-        let wflip = if white_king_sq % 8 > 3 { 7 } else { 0 };
-        let bflip = if black_king_sq % 8 > 3 { 7 } else { 0 } ^ 56;
+        let flip = if king_sq % 8 > 3 { 7 } else { 0 } ^ if SIDE == 1 { 56 } else { 0 };
 
         for side in [Colour::White as usize, Colour::Black as usize] {
             let old_boys = bbs[side];
@@ -496,26 +715,21 @@ impl Board {
 
             for (piece, &old_bb) in bbs[2..8].iter().enumerate() {
                 let old_bb = old_bb & old_boys;
-                let new_bb = self.pieces[piece].0 & new_boys;
+                let new_bb = self.pieces[PieceType::ALL[piece]].0 & new_boys;
 
-                let wbase = Network::get_base_index::<0>(side, piece, white_king_sq) as u16;
-                let bbase = Network::get_base_index::<1>(side, piece, black_king_sq) as u16;
+                let base = Network::get_base_index::<SIDE>(side, piece, king_sq) as u16;
 
                 let mut add_diff = BitBoard(new_bb & !old_bb);
                 while add_diff != BitBoard::EMPTY {
                     let sq = add_diff.pop_lsb();
-                    let sq_idx = sq.index() as u16;
-                    add_feats[0][adds] = wbase + (sq_idx ^ wflip);
-                    add_feats[1][adds] = bbase + (sq_idx ^ bflip);
+                    add_feats[adds] = base + (sq.index() as u16 ^ flip);
                     adds += 1;
                 }
 
                 let mut sub_diff = BitBoard(old_bb & !new_bb);
                 while sub_diff != BitBoard::EMPTY {
                     let sq = sub_diff.pop_lsb();
-                    let sq_idx = sq.index() as u16;
-                    sub_feats[0][subs] = wbase + (sq_idx ^ wflip);
-                    sub_feats[1][subs] = bbase + (sq_idx ^ bflip);
+                    sub_feats[subs] = base + (sq.index() as u16 ^ flip);
                     subs += 1;
                 }
             }
@@ -524,19 +738,160 @@ impl Board {
         (adds, subs)
     }
 
+    /// Scales the eval by the material left, saturating at `MAX_EVAL`
     pub fn scale(&self, eval: i32) -> i32 {
-        let mat = 700
-            + (self.pieces[Piece::WN.index()].count_bits() as i32
-                * PIECE_VALUES[Piece::WN.index()]
-                + self.pieces[Piece::WB.index()].count_bits() as i32
-                    * PIECE_VALUES[Piece::WB.index()]
-                + self.pieces[Piece::WR.index()].count_bits() as i32
-                    * PIECE_VALUES[Piece::WR.index()]
-                + self.pieces[Piece::WQ.index()].count_bits() as i32
-                    * PIECE_VALUES[Piece::WQ.index()])
-                / 32;
+        let mat = SCALE_BASE.get()
+            + (self.pieces[PieceType::Knight].count_bits() as i32
+                * PIECE_VALUES[PieceType::Knight.index()]
+                + self.pieces[PieceType::Bishop].count_bits() as i32
+                    * PIECE_VALUES[PieceType::Bishop.index()]
+                + self.pieces[PieceType::Rook].count_bits() as i32
+                    * PIECE_VALUES[PieceType::Rook.index()]
+                + self.pieces[PieceType::Queen].count_bits() as i32
+                    * PIECE_VALUES[PieceType::Queen.index()])
+                / SCALE_DIVISOR.get();
+
+        // Wide enough for any input, however large
+        let scaled = i64::from(eval) * i64::from(mat) / i64::from(SCALE_NORM.get());
+        scaled.clamp(-i64::from(MAX_EVAL), i64::from(MAX_EVAL)) as i32
+    }
+
+    /// Mixes the simple eval into the network output when the material imbalance is at least
+    /// `BLEND_THRESHOLD`, where the net has seen few training positions
+    pub fn blend(&self, eval: i32) -> i32 {
+        let weight = BLEND_WEIGHT.get();
+        if weight == 0 {
+            return eval;
+        }
+
+        let (material, simple) = self.simple_eval();
+        if material.abs() < BLEND_THRESHOLD.get() {
+            return eval;
+        }
 
-        eval * mat / 1024
+        (eval * (1024 - weight) + simple * weight) / 1024
+    }
+
+    /// Adds the auxiliary eval of the `hybrid` feature to the network output
+    #[cfg(feature = "hybrid")]
+    pub fn hybrid(&self, eval: i32) -> i32 {
+        eval + crate::hybrid::aux_eval(self)
+    }
+
+    #[cfg(not(feature = "hybrid"))]
+    #[inline(always)]
+    pub fn hybrid(&self, eval: i32) -> i32 {
+        eval
+    }
+
+    /// Material and piece-square evaluation from the side to move, returned along with the
+    /// material balance alone
+    pub fn simple_eval(&self) -> (i32, i32) {
+        let mut material = 0;
+        let mut psqt = 0;
+
+        for side in [Colour::White, Colour::Black] {
+            let sign = if side == self.side { 1 } else { -1 };
+            for (piece, value) in PIECE_VALUES.iter().enumerate() {
+                let mut bb = self.pieces[PieceType::ALL[piece]] & self.sides[side as usize];
+                while bb != BitBoard::EMPTY {
+                    let sq = bb.pop_lsb();
+                    material += sign * value;
+
+                    let rank = match side {
+                        Colour::White => sq.row() as i32,
+                        Colour::Black => 7 - sq.row() as i32,
+                    };
+                    let centre = 6
+                        - (2 * sq.row() as i32 - 7).abs() / 2
+                        - (2 * sq.col() as i32 - 7).abs() / 2;
+                    psqt += sign
+                        * match piece {
+                            0 => 8 * (rank - 1),
+                            1 | 2 => 6 * centre,
+                            4 => 2 * centre,
+                            _ => 0,
+                        };
+                }
+            }
+        }
+
+        (material, material + psqt)
+    }
+
+    /// Most valuable piece of `side` which is hanging: attacked by a cheaper piece, or attacked
+    /// and not defended at all. Kings are left out, being in check is handled by the search.
+    pub fn biggest_threat(&self, side: Colour) -> Option<Square> {
+        let occ = self.occupancy();
+
+        for piece in [
+            PieceType::Queen,
+            PieceType::Rook,
+            PieceType::Knight,
+            PieceType::Bishop,
+            PieceType::Pawn,
+        ] {
+            let value = PIECE_VALUES[piece.index()];
+            for sq in self.pieces(side, piece) {
+                let Some((_, attacker)) = self.cheapest_attacker(sq, !side, occ) else {
+                    continue;
+                };
+                if (attacker != PieceType::King && PIECE_VALUES[attacker.index()] < value)
+                    || self.cheapest_attacker(sq, side, occ).is_none()
+                {
+                    return Some(sq);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Least valuable piece of `side` attacking `square` with the given occupancy, and where it
+    /// stands. The king comes last, as it can only capture undefended pieces.
+    pub fn cheapest_attacker(
+        &self,
+        square: Square,
+        side: Colour,
+        occ: BitBoard,
+    ) -> Option<(Square, PieceType)> {
+        let attackers = self.attackers_to(square, occ) & self.sides[side as usize];
+        if attackers == BitBoard::EMPTY {
+            return None;
+        }
+
+        [
+            PieceType::Pawn,
+            PieceType::Bishop,
+            PieceType::Knight,
+            PieceType::Rook,
+            PieceType::Queen,
+            PieceType::King,
+        ]
+        .into_iter()
+        .find_map(|piece| {
+            let squares = attackers & self.pieces[piece];
+            (squares != BitBoard::EMPTY).then(|| (squares.lsb(), piece))
+        })
+    }
+
+    /// Pieces of both sides attacking `square` with the given occupancy, pieces outside of
+    /// `occ` are taken as captured
+    pub fn attackers_to(&self, square: Square, occ: BitBoard) -> BitBoard {
+        let idx = square.index();
+        ((KNIGHT_ATTACKS[idx] & self.pieces[PieceType::Knight])
+            | (KING_ATTACKS[idx] & self.pieces[PieceType::King])
+            | (PAWN_ATTACKS[Colour::White as usize][idx]
+                & self.pieces[PieceType::Pawn]
+                & self.sides[Colour::Black as usize])
+            | (PAWN_ATTACKS[Colour::Black as usize][idx]
+                & self.pieces[PieceType::Pawn]
+                & self.sides[Colour::White as usize])
+            | (rook_attacks(occ.0, idx)
+                & (self.pieces[PieceType::Rook] | self.pieces[PieceType::Queen]))
+            | (bishop_attacks(occ.0, idx)
+                & (self.pieces[PieceType::Bishop] | self.pieces[PieceType::Queen])))
+            & occ
     }
 
     /// Static exchange evaluation method, it does not check wether
@@ -549,21 +904,22 @@ impl Board {
         let mut next_piece = None;
 
         let mut score = if mt == MoveKind::EnPassant {
-            PIECE_VALUES[Piece::WP.index()] - threshold
+            PIECE_VALUES[PieceType::Pawn.index()] - threshold
         } else {
             let cap = self.piece_at(dest);
             let mut value = if cap == Piece::Empty {
                 0
             } else {
-                PIECE_VALUES[cap.index()]
+                PIECE_VALUES[cap.piece_type().index()]
             };
             if mt.is_promotion() {
-                next_piece = Some(mt.get_promotion(self.side).index());
-                unsafe {
-                    // There is a promotion
-                    value += PIECE_VALUES[next_piece.unwrap_unchecked()]
-                        - PIECE_VALUES[Piece::WP.index()];
-                }
+                next_piece = Some(mt.get_promotion(self.side).piece_type().index());
+                // There is a promotion
+                #[cfg(not(feature = "safe-only"))]
+                let promo = unsafe { next_piece.unwrap_unchecked() };
+                #[cfg(feature = "safe-only")]
+                let promo = next_piece.expect("There is a promotion");
+                value += PIECE_VALUES[promo] - PIECE_VALUES[PieceType::Pawn.index()];
             }
             value - threshold
         };
@@ -572,7 +928,7 @@ impl Board {
             return false;
         }
 
-        score -= PIECE_VALUES[next_piece.unwrap_or(self.piece_at(src).index())];
+        score -= PIECE_VALUES[next_piece.unwrap_or(self.piece_at(src).piece_type().index())];
 
         if score >= 0 {
             return true;
@@ -586,20 +942,11 @@ impl Board {
             occ ^= ep_dest.to_board();
         }
 
-        let idx = dest.index();
-        let mut attackers = ((KNIGHT_ATTACKS[idx] & self.pieces[Piece::WN.index()])
-            | (KING_ATTACKS[idx] & self.pieces[Piece::WK.index()])
-            | (PAWN_ATTACKS[Colour::White as usize][idx] & self.pieces[Piece::WP.index()])
-            | (PAWN_ATTACKS[Colour::Black as usize][idx] & self.pieces[Piece::WP.index()])
-            | (rook_attacks(occ.0, idx)
-                & (self.pieces[Piece::WR.index()] | self.pieces[Piece::WQ.index()]))
-            | (bishop_attacks(occ.0, idx)
-                & (self.pieces[Piece::WB.index()] | self.pieces[Piece::WQ.index()])))
-            & occ;
+        let mut attackers = self.attackers_to(dest, occ);
 
         let mut stm = !self.side;
-        let diagonal = self.pieces[Piece::WB.index()] | self.pieces[Piece::WQ.index()];
-        let normal = self.pieces[Piece::WR.index()] | self.pieces[Piece::WQ.index()];
+        let diagonal = self.pieces[PieceType::Bishop] | self.pieces[PieceType::Queen];
+        let normal = self.pieces[PieceType::Rook] | self.pieces[PieceType::Queen];
 
         loop {
             let own_attackers = attackers & self.sides[stm as usize];
@@ -612,7 +959,7 @@ impl Board {
             let att_sq_piece = Piece::COLOUR_PIECES[stm as usize]
                 .iter()
                 .find_map(|&piece| {
-                    let squares = own_attackers & self.pieces[piece.index()] & side_bb;
+                    let squares = own_attackers & self.pieces[piece.piece_type()] & side_bb;
                     if squares != BitBoard::EMPTY {
                         Some((squares.lsb(), piece))
                     } else {
@@ -633,7 +980,7 @@ impl Board {
             attackers &= occ;
             stm = !stm;
 
-            score = -score - 1 - PIECE_VALUES[att.index()];
+            score = -score - 1 - PIECE_VALUES[att.piece_type().index()];
             if score >= 0 {
                 if att.is_king() && attackers & self.sides[stm as usize] != BitBoard::EMPTY {
                     return self.side == stm;
@@ -646,64 +993,691 @@ impl Board {
         self.side != stm
     }
 
+    /// Position of a FEN known to be well formed, panicking otherwise. Input from users or files
+    /// goes through `try_from_fen`.
     pub fn from_fen(state: &str) -> Self {
-        let fen: Vec<&str> = state.split_whitespace().take(6).collect();
+        Self::parse_fen(state).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Position of a FEN, or why it cannot be searched: on top of being well formed, the side
+    /// not to move must not be in check
+    pub fn try_from_fen(state: &str) -> Result<Self, String> {
+        let board = Self::parse_fen(state)?;
+        let them = board.king_square(!board.side as usize);
+        if board.attackers_to(them, board.occupancy()) & board.sides[board.side as usize]
+            != BitBoard::EMPTY
+        {
+            return Err(String::from(
+                "Invalid FEN, the side not to move is in check",
+            ));
+        }
+        Ok(board)
+    }
 
+    /// Every rank must hold eight squares, each side a single king and no pawn may stand on a
+    /// back rank
+    fn parse_fen(state: &str) -> Result<Self, String> {
+        let fen: Vec<&str> = state.split_whitespace().take(6).collect();
         if fen.len() != 6 {
-            panic!("Invalid input FEN string");
+            return Err(format!("Invalid FEN, expected 6 fields: {state}"));
+        }
+
+        let ranks: Vec<&str> = fen[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("Invalid FEN, expected 8 ranks: {}", fen[0]));
         }
 
-        let board_layout = fen[0];
         let mut board = Self::new();
-        let (mut row, mut col): (u8, u8) = (7, 0);
-        let mut tokens = 0;
-
-        for token in board_layout.chars() {
-            match token {
-                '/' => {
-                    if tokens != 8 {
-                        panic!("Invalid number of positions in FEN");
+        for (rank, row) in ranks.into_iter().zip((0..8).rev()) {
+            let mut col = 0;
+            for token in rank.chars() {
+                match token {
+                    '1'..='8' => col += token as usize - '0' as usize,
+                    'P' | 'N' | 'B' | 'R' | 'Q' | 'K' | 'p' | 'n' | 'b' | 'r' | 'q' | 'k'
+                        if col < 8 =>
+                    {
+                        let piece = Piece::from_fen(token);
+                        if piece.piece_type() == PieceType::Pawn && (row == 0 || row == 7) {
+                            return Err(format!("Invalid FEN, pawn on a back rank: {rank}"));
+                        }
+                        board.set_piece(piece, Square::from_row_col(row, col));
+                        col += 1;
                     }
-
-                    row -= 1;
-                    col = 0;
-                    tokens = 0;
-                }
-                '1'..='8' => {
-                    let empty_pos = token.to_digit(10).expect("Not a number") as u8;
-                    col += empty_pos;
-                    tokens += empty_pos;
+                    _ => return Err(format!("Invalid FEN rank: {rank}")),
                 }
-                _ => {
-                    board.set_piece(
-                        Piece::from_fen(token),
-                        Square::from_row_col(row as usize, col as usize),
-                    );
+            }
+            if col != 8 {
+                return Err(format!("Invalid FEN, rank without 8 squares: {rank}"));
+            }
+        }
 
-                    col += 1;
-                    tokens += 1;
-                }
+        for (colour, name) in [(Colour::White, "white"), (Colour::Black, "black")] {
+            let kings = (board.pieces[PieceType::King] & board.sides[colour as usize]).count_bits();
+            if kings != 1 {
+                return Err(format!("Invalid FEN, {kings} {name} kings"));
+            }
+            // The material key counts up to 15 pieces of a kind
+            let pieces = board.sides[colour as usize].count_bits();
+            if pieces > 16 {
+                return Err(format!("Invalid FEN, {pieces} {name} pieces"));
             }
         }
 
         board.side = match fen[1] {
             "w" => Colour::White,
             "b" => Colour::Black,
-            _ => unreachable!(),
+            side => return Err(format!("Invalid FEN side to move: {side}")),
         };
 
-        board.castling_rights = CastlingRights::from(fen[2]);
+        board.castling_rights = CastlingRights::parse(fen[2], &board)?;
 
-        board.en_passant = match fen[3] {
-            "-" => None,
-            _ => Some(Square::from(fen[3])),
+        board.en_passant = match fen[3].as_bytes() {
+            b"-" => None,
+            [b'a'..=b'h', b'3' | b'6'] => Some(Square::from(fen[3]))
+                .filter(|&sq| board.can_capture_en_passant(sq, !board.side)),
+            _ => return Err(format!("Invalid FEN en passant square: {}", fen[3])),
         };
 
-        board.halfmoves = fen[4].parse::<u8>().unwrap();
+        // Counters past the range only matter as a claimable draw
+        board.halfmoves = match fen[4].parse::<u32>() {
+            Ok(halfmoves) => halfmoves.min(u32::from(u8::MAX)) as u8,
+            Err(_) => return Err(format!("Invalid FEN halfmove clock: {}", fen[4])),
+        };
+        board.fullmoves = fen[5].parse::<u16>().unwrap_or(1).max(1);
         board.hash = ZHash::new(&board);
         board.calculate_threats();
         board.pinned_and_checkers();
 
-        board
+        Ok(board)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        for row in (0..8).rev() {
+            let mut empty = 0;
+            for col in 0..8 {
+                let piece = self.piece_at(Square::from_row_col(row, col));
+                if piece == Piece::Empty {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    fen.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                fen.push(piece.to_char());
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if row > 0 {
+                fen.push('/');
+            }
+        }
+
+        let side = if self.side == Colour::White { 'w' } else { 'b' };
+        let ep = self
+            .en_passant
+            .map_or(String::from("-"), |sq| sq.to_string());
+
+        format!(
+            "{fen} {side} {} {ep} {} {}",
+            self.castling_rights.to_fen(self),
+            self.halfmoves,
+            self.fullmoves
+        )
+    }
+}
+
+impl std::fmt::Display for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const SEPARATOR: &str = " +---+---+---+---+---+---+---+---+";
+        writeln!(f, "{SEPARATOR}")?;
+        for row in (0..8).rev() {
+            for col in 0..8 {
+                let piece = self.piece_at(Square::from_row_col(row, col));
+                let c = if piece == Piece::Empty {
+                    ' '
+                } else {
+                    piece.to_char()
+                };
+                write!(f, " | {c}")?;
+            }
+            writeln!(f, " | {}", row + 1)?;
+            writeln!(f, "{SEPARATOR}")?;
+        }
+        write!(f, "   a   b   c   d   e   f   g   h")
+    }
+}
+
+/// The FEN and the bitboards kept besides the pieces, in hex
+impl std::fmt::Debug for Board {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Board")
+            .field("fen", &self.to_fen())
+            .field("white", &self.sides[Colour::White as usize])
+            .field("black", &self.sides[Colour::Black as usize])
+            .field("checkers", &self.checkers)
+            .field("threats", &self.threats)
+            .field("pinned", &self.pinned)
+            .field("hash", &format_args!("{:#018x}", self.hash.0))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/4r3/4K3 w - - 0 1");
+        assert_eq!(
+            format!("{board:?}"),
+            format!(
+                "Board {{ fen: \"4k3/8/8/8/8/8/4r3/4K3 w - - 0 1\", \
+                 white: BitBoard(0x0000000000000010), black: BitBoard(0x1000000000001000), \
+                 checkers: BitBoard(0x0000000000001000), threats: {:?}, \
+                 pinned: BitBoard(0x0000000000000000), hash: {:#018x} }}",
+                board.threats, board.hash.0
+            )
+        );
+
+        let m = board.find_move("e1e2").unwrap();
+        assert_eq!(
+            format!("{m:?}"),
+            "Move { src: e1, dest: e2, kind: Capture }"
+        );
+        assert_eq!(
+            format!("{:?}", Move(0b1011 << 12 | m.0 & 0x0FFF)),
+            "Move { src: e1, dest: e2, kind: 0b1011 }"
+        );
+    }
+
+    #[test]
+    fn test_non_pawn_material() {
+        #[rustfmt::skip]
+        const POSITIONS: [(&str, bool, bool); 5] = [
+            ("4k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1", false, false),
+            ("4k3/pppp4/8/8/8/8/PPPP4/2N1K3 b - - 0 1", true, false),
+            ("2r1k3/pppp4/8/8/8/8/PPPP4/4K3 w - - 0 1", false, true),
+            ("4kb2/8/8/8/8/8/8/3QK3 w - - 0 1", true, true),
+            ("4k3/8/8/8/8/8/8/4K3 b - - 0 1", false, false),
+        ];
+
+        for (fen, white, black) in POSITIONS {
+            let board = Board::from_fen(fen);
+            assert_eq!(board.has_non_pawn_material(Colour::White), white, "{fen}");
+            assert_eq!(board.has_non_pawn_material(Colour::Black), black, "{fen}");
+        }
+    }
+
+    #[test]
+    fn test_material_hash() {
+        // Captures, promotions, capture promotions, en passant and castling
+        let mut board = Board::from_fen("r3k2r/1P4p1/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1");
+        for uci in [
+            "e5d6", "g7g5", "b7a8n", "e8g8", "e1c1", "g5g4", "d6d7", "g4g3", "d7d8q",
+        ] {
+            let m = board.find_move(uci).unwrap();
+            let before = board.material_hash;
+            board.make_move(m);
+
+            assert_eq!(board.material_hash, ZHash::material(&board), "{uci}");
+            let pieces = board.occupancy().count_bits() as usize;
+            assert_eq!(board.material_hash.piece_count(), pieces, "{uci}");
+            let quiet = !m.get_type().is_capture() && !m.get_type().is_promotion();
+            assert_eq!(board.material_hash == before, quiet, "{uci}");
+        }
+
+        // Queen, knight and both rooks of White against a rook and a pawn
+        #[rustfmt::skip]
+        let pieces = [
+            Piece::WK, Piece::WQ, Piece::WN, Piece::WR, Piece::WR, Piece::BK, Piece::BR, Piece::BP,
+        ];
+        let key = ZHash::material_key(&pieces);
+        assert_eq!(board.material_hash, key);
+        assert_eq!(Board::default().material_hash.piece_count(), 32);
+
+        // Insufficient material is recognised by its key alone
+        for (fen, draw) in [
+            ("8/8/4k3/8/8/3NK3/8/8 w - - 0 1", true),
+            ("8/8/4k3/2b5/8/4K3/8/8 b - - 0 1", true),
+            ("8/8/4k3/8/8/3NKN2/8/8 w - - 0 1", false),
+            ("8/8/4k3/8/8/3RK3/8/8 w - - 0 1", false),
+        ] {
+            let board = Board::from_fen(fen);
+            assert_eq!(
+                INSUFFICIENT_MATERIAL.contains(&board.material_hash),
+                draw,
+                "{fen}"
+            );
+            assert_eq!(board.is_draw(), draw, "{fen}");
+        }
+    }
+
+    #[test]
+    fn test_biggest_threat() {
+        for (fen, side, threat) in [
+            // Nothing attacked
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", Colour::White, None),
+            // A queen attacked by a defended pawn, and an undefended knight
+            (
+                "4k3/8/8/3p4/2Q1N3/8/8/4K3 w - - 0 1",
+                Colour::White,
+                Some("c4"),
+            ),
+            (
+                "4k3/8/8/3p4/4N3/8/8/4K3 w - - 0 1",
+                Colour::White,
+                Some("e4"),
+            ),
+            // Defended pieces attacked by pieces worth as much or more are safe
+            ("4k3/8/8/2r5/8/2R5/2K5/8 w - - 0 1", Colour::White, None),
+            (
+                "4k3/8/8/2r5/8/2R5/2K5/8 b - - 0 1",
+                Colour::Black,
+                Some("c5"),
+            ),
+            // A knight attacked by a bishop is lost even when defended
+            (
+                "4k3/8/8/8/4n3/3p1B2/8/4K3 b - - 0 1",
+                Colour::Black,
+                Some("e4"),
+            ),
+        ] {
+            let board = Board::from_fen(fen);
+            assert_eq!(
+                board.biggest_threat(side),
+                threat.map(Square::from),
+                "{fen}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_king_danger() {
+        assert_eq!(Board::default().king_danger(), 0);
+
+        // The bishop and queen both check the king, the queen also covers f2
+        let board = Board::from_fen("4k3/8/8/8/7q/8/3b4/4K3 w - - 0 1");
+        assert_eq!(board.king_danger(), 2);
+        let board = Board::from_fen("4k3/8/8/8/7q/8/3b4/4K3 b - - 0 1");
+        assert_eq!(board.king_danger(), 0);
+    }
+
+    #[test]
+    fn test_cheapest_attacker() {
+        // The knight on e5 is attacked by a black bishop, knight and queen
+        let board = Board::from_fen("k7/2b5/8/4N2q/8/4Pn2/8/4R2K w - - 0 1");
+        let occ = board.occupancy();
+        let e5 = Square::from("e5");
+        assert_eq!(
+            board.cheapest_attacker(e5, Colour::Black, occ),
+            Some((Square::from("c7"), PieceType::Bishop))
+        );
+
+        // It is only defended by the rook once the pawn in between is gone
+        assert_eq!(board.cheapest_attacker(e5, Colour::White, occ), None);
+        let occ = occ ^ Square::from("e3").to_board();
+        assert_eq!(
+            board.cheapest_attacker(e5, Colour::White, occ),
+            Some((Square::from("e1"), PieceType::Rook))
+        );
+    }
+
+    #[test]
+    fn test_attackers_to() {
+        // Walks every piece's moves square by square, without the attack tables
+        fn brute_force(board: &Board, target: Square, occ: BitBoard) -> BitBoard {
+            let mut attackers = BitBoard::EMPTY;
+            for (sq, piece) in board.iter_pieces().filter(|&(sq, _)| occ.contains(sq)) {
+                let (row, col) = (sq.row() as i32, sq.col() as i32);
+                let (steps, slides): (Vec<(i32, i32)>, bool) = match piece.piece_type() {
+                    PieceType::Pawn if piece.colour() == Colour::White => {
+                        (vec![(1, -1), (1, 1)], false)
+                    }
+                    PieceType::Pawn => (vec![(-1, -1), (-1, 1)], false),
+                    PieceType::Knight => (
+                        vec![
+                            (1, 2),
+                            (2, 1),
+                            (2, -1),
+                            (1, -2),
+                            (-1, -2),
+                            (-2, -1),
+                            (-2, 1),
+                            (-1, 2),
+                        ],
+                        false,
+                    ),
+                    PieceType::Bishop => (vec![(1, 1), (1, -1), (-1, 1), (-1, -1)], true),
+                    PieceType::Rook => (vec![(1, 0), (-1, 0), (0, 1), (0, -1)], true),
+                    _ => (
+                        vec![
+                            (1, 1),
+                            (1, -1),
+                            (-1, 1),
+                            (-1, -1),
+                            (1, 0),
+                            (-1, 0),
+                            (0, 1),
+                            (0, -1),
+                        ],
+                        piece.is_queen(),
+                    ),
+                };
+
+                for (dr, dc) in steps {
+                    let (mut r, mut c) = (row + dr, col + dc);
+                    while (0..8).contains(&r) && (0..8).contains(&c) {
+                        let reached = Square::from_row_col(r as usize, c as usize);
+                        if reached == target {
+                            attackers |= sq.to_board();
+                        }
+                        if !slides || occ.contains(reached) {
+                            break;
+                        }
+                        (r, c) = (r + dr, c + dc);
+                    }
+                }
+            }
+            attackers
+        }
+
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ] {
+            let board = Board::from_fen(fen);
+            let full = board.occupancy();
+            // Also with some pieces taken off, as the exchanges of SEE do
+            let thinned = BitBoard(full.0 & 0x5A5A_5A5A_5A5A_5A5A);
+            for occ in [full, thinned] {
+                for target in (0..64).map(Square::new) {
+                    assert_eq!(
+                        board.attackers_to(target, occ).0,
+                        brute_force(&board, target, occ).0,
+                        "{fen} {target}"
+                    );
+                }
+            }
+        }
+
+        // The queen behind the rook only recaptures on d5 once the rook has left the file,
+        // winning the knight where the rook alone would lose the exchange
+        let board = Board::from_fen("3r2k1/8/8/3n4/8/8/3R4/3Q2K1 w - - 0 1");
+        let capture = board.find_move("d2d5").unwrap();
+        assert!(board.see(capture, 0));
+        let alone = Board::from_fen("3r2k1/8/8/3n4/8/8/3R4/6K1 w - - 0 1");
+        assert!(!alone.see(capture, 0));
+    }
+
+    #[test]
+    fn test_eval_saturation() {
+        let mut rng = crate::rng::Rng::new(5);
+        let boards = [
+            Board::default(),
+            Board::from_fen("4k3/8/8/8/8/8/8/4K3 w - - 0 1"),
+            Board::from_fen("qqqqkqqq/qqqqqqqq/8/8/8/8/QQQQQQQQ/QQQQKQQQ w - - 0 1"),
+        ];
+        for board in boards {
+            let extremes = [i32::MIN, i32::MAX, -MAX_EVAL, MAX_EVAL];
+            let random = (0..1000).map(|_| rng.next_u64() as i32);
+            for eval in extremes.into_iter().chain(random) {
+                let scaled = board.scale(eval);
+                assert!(scaled.abs() <= MAX_EVAL, "{eval} -> {scaled}");
+                assert_eq!(scaled.signum(), eval.signum() * scaled.abs().signum());
+            }
+            assert_eq!(board.scale(0), 0);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_fresh() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 b - - 0 1",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+        ];
+
+        // Scored from several threads at once, matching the cached path
+        let fresh: Vec<i32> = std::thread::scope(|s| {
+            let handles: Vec<_> = fens
+                .iter()
+                .map(|fen| s.spawn(|| Board::from_fen(fen).evaluate_fresh()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let mut cache = EvalTable::default();
+        for (fen, eval) in fens.iter().zip(fresh) {
+            assert_eq!(Board::from_fen(fen).evaluate(&mut cache), eval, "{fen}");
+        }
+    }
+
+    #[test]
+    fn test_eval_cache() {
+        // Incremental updates through a game must match full refreshes
+        let mut cached = EvalTable::default();
+        let mut fresh = EvalTable {
+            enabled: false,
+            ..EvalTable::default()
+        };
+
+        let mut board = Board::default();
+        for m in [
+            "e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1g1", "f6e4", "d2d4",
+        ] {
+            let m = board.find_move(m).unwrap();
+            board.make_move(m);
+            assert_eq!(board.evaluate(&mut cached), board.evaluate(&mut fresh));
+        }
+
+        // One probe per perspective
+        assert_eq!(cached.stats.probes, 18);
+        assert_eq!(fresh.stats.refreshes, 18);
+        assert!(cached.stats.partial > 0);
+    }
+
+    #[test]
+    fn test_piece_api() {
+        let board = Board::from_fen("4k3/8/8/3pP3/8/8/8/R3K1R1 w Q d6 0 12");
+        assert_eq!(board.occupancy().count_bits(), 6);
+        let rooks: Vec<usize> = board
+            .pieces(Colour::White, PieceType::Rook)
+            .map(|sq| sq.index())
+            .collect();
+        assert_eq!(rooks, [0, 6]);
+        assert_eq!(
+            board.pieces(Colour::Black, PieceType::Pawn).0,
+            Square::from("d5").to_board().0
+        );
+
+        let pieces: String = board.iter_pieces().map(|(_, p)| p.to_char()).collect();
+        assert_eq!(pieces, "RKRpPk");
+        assert!(board
+            .iter_pieces()
+            .all(|(_, p)| Piece::new(p.piece_type(), p.colour()) == p));
+        assert!(board
+            .iter_pieces()
+            .all(|(sq, p)| board.pieces(p.colour(), p.piece_type()).get_bit(sq)));
+    }
+
+    #[test]
+    fn test_board_editing() {
+        let same = |board: &Board, fen: &str| {
+            let expected = Board::from_fen(fen);
+            assert_eq!(board.to_fen(), fen);
+            assert_eq!(board.hash, expected.hash, "{fen}");
+            assert_eq!(board.pawn_hash, expected.pawn_hash, "{fen}");
+            assert_eq!(board.material_hash, expected.material_hash, "{fen}");
+            assert_eq!(
+                (board.threats, board.checkers, board.pinned),
+                (expected.threats, expected.checkers, expected.pinned),
+                "{fen}"
+            );
+        };
+
+        let mut board = Board::new();
+        for (piece, square) in [
+            (Piece::WK, "e1"),
+            (Piece::WR, "a1"),
+            (Piece::WR, "h1"),
+            (Piece::BK, "e8"),
+            (Piece::BQ, "e4"),
+            (Piece::WP, "d2"),
+        ] {
+            board.put_piece(piece, Square::from(square));
+        }
+        assert!(board.set_castling("KQ").is_ok());
+        same(&board, "4k3/8/8/8/4q3/8/3P4/R3K2R w KQ - 0 1");
+
+        // Replacing or removing a castling rook loses its right, and the pin follows the pieces
+        board.put_piece(Piece::WN, Square::from("h1"));
+        assert!(board.take_piece(Square::from("e4")) == Piece::BQ);
+        assert!(board.take_piece(Square::from("e4")) == Piece::Empty);
+        board.put_piece(Piece::BB, Square::from("a5"));
+        same(&board, "4k3/8/8/b7/8/8/3P4/R3K2N w Q - 0 1");
+        assert!(board.set_castling("KQ").is_err());
+        assert!(board.set_castling("Qk").is_err());
+        assert_eq!(board.castling_rights.flags, CastlingRights::WQ);
+
+        board.set_side(Colour::Black);
+        same(&board, "4k3/8/8/b7/8/8/3P4/R3K2N b Q - 0 1");
+
+        // The en passant square goes with the pawn which can take it, or the side to move
+        let mut board = Board::from_fen("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1");
+        board.put_piece(Piece::WP, Square::from("c5"));
+        same(&board, "4k3/8/8/2PpP3/8/8/8/4K3 w - d6 0 1");
+        board.take_piece(Square::from("e5"));
+        same(&board, "4k3/8/8/2Pp4/8/8/8/4K3 w - d6 0 1");
+        board.set_side(Colour::Black);
+        same(&board, "4k3/8/8/2Pp4/8/8/8/4K3 b - - 0 1");
+        board.take_piece(Square::from("e8"));
+        assert_eq!(board.checkers, BitBoard::EMPTY);
+    }
+
+    #[test]
+    fn test_fen_roundtrip() {
+        #[rustfmt::skip]
+        const FENS: [&str; 3] = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/8/8/3pP3/8/8/8/R3K1R1 w Qk d6 0 12",
+            "8/8/4k3/8/8/3QK3/8/8 b - - 37 81",
+        ];
+
+        for fen in FENS {
+            assert_eq!(Board::from_fen(fen).to_fen(), fen);
+        }
+
+        let mut board = Board::default();
+        for m in ["e2e4", "c7c5", "g1f3"] {
+            let m = board.find_move(m).unwrap();
+            board.make_move(m);
+        }
+        assert_eq!(
+            board.to_fen(),
+            "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"
+        );
+    }
+
+    #[test]
+    fn test_invalid_fen() {
+        #[rustfmt::skip]
+        const FENS: [&str; 9] = [
+            "8/8/8/8/8/8/8/8 w - - 0 1",
+            "4k3/8/8/8/8/NNNNNNNN/NNNNNNNN/4K3 w - - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R w KQkqX - 0 1",
+            "4k3/8/8/8/8/8/8/4K2P w - - 0 1",
+            "4k3/8/8/8/8/8/8/4R1K1 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - e9 0 1",
+        ];
+
+        for fen in FENS {
+            assert!(Board::try_from_fen(fen).is_err(), "{fen}");
+        }
+        let fen = Board::default().to_fen();
+        assert_eq!(Board::try_from_fen(&fen).unwrap().to_fen(), fen);
+    }
+
+    #[test]
+    fn test_en_passant_hash() {
+        // Double pushes which no pawn can capture leave no en passant square
+        let mut board = Board::default();
+        board.make_move(board.find_move("e2e4").unwrap());
+        assert_eq!(board.en_passant, None);
+        for uci in ["d7d5", "e4e5", "f7f5"] {
+            board.make_move(board.find_move(uci).unwrap());
+        }
+        assert_eq!(board.en_passant, Some(Square::from("f6")));
+        assert_eq!(
+            Board::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").en_passant,
+            None
+        );
+
+        // So transpositions ending with a double push hash as the others
+        let (mut knight_last, mut push_last) = (Board::default(), Board::default());
+        for (knight, push) in ["e2e4", "e7e5", "g1f3", "b8c6"]
+            .into_iter()
+            .zip(["g1f3", "b8c6", "e2e4", "e7e5"])
+        {
+            knight_last.make_move(knight_last.find_move(knight).unwrap());
+            push_last.make_move(push_last.find_move(push).unwrap());
+        }
+        assert_eq!(knight_last.hash, push_last.hash);
+
+        // Null moves drop the square along with its key
+        board.make_null_move();
+        assert_eq!(board.en_passant, None);
+        assert_eq!(board.hash, ZHash::new(&board));
+    }
+
+    #[test]
+    fn test_incremental_hash() {
+        #[rustfmt::skip]
+        const FENS: [&str; 5] = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/1P4p1/8/3pP3/8/8/6p1/R3K2R w KQkq d6 0 1",
+            "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+
+        // Random games mixing in null moves, checking every key against one computed from scratch
+        let mut rng = crate::rng::Rng::new(1707);
+        for fen in FENS {
+            for _ in 0..40 {
+                let mut board = Board::from_fen(fen);
+                for _ in 0..80 {
+                    let moves: Vec<Move> = board
+                        .generate_pseudo_moves::<true, true>()
+                        .into_iter()
+                        .filter(|&m| board.is_legal(m))
+                        .collect();
+                    if moves.is_empty() {
+                        break;
+                    }
+
+                    if !board.in_check() && rng.below(8) == 0 {
+                        board.make_null_move();
+                    } else {
+                        board.make_move(moves[rng.below(moves.len())]);
+                    }
+
+                    let fen = board.to_fen();
+                    assert_eq!(board.hash, ZHash::new(&board), "{fen}");
+                    assert_eq!(board.pawn_hash, ZHash::pawns(&board), "{fen}");
+                    assert_eq!(board.material_hash, ZHash::material(&board), "{fen}");
+                    assert_eq!(Board::from_fen(&fen).hash, board.hash, "{fen}");
+                }
+            }
+        }
     }
 }
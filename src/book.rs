@@ -0,0 +1,387 @@
+use crate::board::Board;
+use crate::limits::SearchLimits;
+use crate::moves::{Move, MoveKind};
+use crate::piece::Colour;
+use crate::rng::Rng;
+use crate::search::find_best_move;
+use crate::square::Square;
+use crate::tables::SearchData;
+
+/// Nodes of the search checking that a random opening is balanced
+const RANDOM_OPENING_NODES: u64 = 5000;
+
+/// Random openings tried per opening requested before giving up on the score threshold
+const RANDOM_OPENING_ATTEMPTS: usize = 100;
+
+/// Starting position taken from an opening suite
+#[derive(Clone, Copy)]
+pub struct Opening {
+    pub board: Board,
+    pub ply: usize,
+}
+
+/// Opening suite loaded from an EPD or PGN file, sampled with a seeded generator so that
+/// selfplay and datagen runs are reproducible
+pub struct OpeningBook {
+    openings: Vec<Opening>,
+    rng: Rng,
+}
+
+impl OpeningBook {
+    /// Loads a suite keeping openings of at least `min_ply` plies, PGN games are cut at
+    /// `max_ply` and EPD positions after it are skipped. PGN is detected by extension.
+    pub fn load(path: &str, min_ply: usize, max_ply: usize, seed: u64) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let openings = if path.ends_with(".pgn") {
+            parse_pgn(&text, max_ply)?
+        } else {
+            parse_epd(&text)?
+        };
+
+        Self::from_openings(openings, min_ply, max_ply, seed)
+    }
+
+    pub fn from_openings(
+        mut openings: Vec<Opening>,
+        min_ply: usize,
+        max_ply: usize,
+        seed: u64,
+    ) -> Result<Self, String> {
+        openings.retain(|o| (min_ply..=max_ply).contains(&o.ply));
+        if openings.is_empty() {
+            return Err(String::from("No openings within the ply range"));
+        }
+
+        Ok(Self {
+            openings,
+            rng: Rng::new(seed),
+        })
+    }
+
+    /// Suite of `count` openings of `plies` random legal moves from the start position, for
+    /// selfplay and datagen runs without an opening file. Openings are only kept if a short
+    /// search scores them below `max_score` for the side to move, so games start varied but
+    /// balanced. The same seed always generates the same suite.
+    pub fn random(count: usize, plies: usize, max_score: i32, seed: u64) -> Result<Self, String> {
+        let mut rng = Rng::new(seed);
+        let mut data = SearchData::new();
+        data.resize_tt(1);
+        data.deterministic = true;
+        data.silent = true;
+
+        let mut openings = Vec::with_capacity(count);
+        for _ in 0..count * RANDOM_OPENING_ATTEMPTS {
+            if openings.len() == count {
+                break;
+            }
+            if let Some(opening) = random_opening(&mut rng, &mut data, plies, max_score) {
+                openings.push(opening);
+            }
+        }
+        if openings.len() < count {
+            return Err(format!("No balanced openings found within {max_score}cp"));
+        }
+
+        Self::from_openings(openings, plies, plies, seed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.openings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.openings.is_empty()
+    }
+
+    /// Random opening of the suite, the sequence only depends on the seed
+    pub fn sample(&mut self) -> Opening {
+        self.openings[self.rng.below(self.openings.len())]
+    }
+}
+
+/// Plays `plies` random legal moves from the start position, None if the game ends on the way
+/// or the result is not balanced
+fn random_opening(
+    rng: &mut Rng,
+    data: &mut SearchData,
+    plies: usize,
+    max_score: i32,
+) -> Option<Opening> {
+    let mut board = Board::default();
+    for _ in 0..plies {
+        let moves: Vec<Move> = board
+            .generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .filter(|&m| board.is_legal(m))
+            .collect();
+        if moves.is_empty() {
+            return None;
+        }
+        board.make_move(moves[rng.below(moves.len())]);
+    }
+
+    // Searched from a clean state, so that every opening is judged the same way
+    data.new_game();
+    find_best_move(
+        &board,
+        &SearchLimits::new().nodes(RANDOM_OPENING_NODES),
+        data,
+    );
+    (data.best_move != Move::NULL && data.eval.abs() < max_score)
+        .then_some(Opening { board, ply: plies })
+}
+
+/// One position per line: the four FEN fields, optionally followed by the move counters and
+/// EPD operations (`bm`, `id`, ...) which are ignored
+pub fn parse_epd(text: &str) -> Result<Vec<Opening>, String> {
+    let mut openings = Vec::new();
+
+    for (n, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if fields.len() < 4 {
+            return Err(format!("Invalid EPD on line {}", n + 1));
+        }
+
+        let counters = match (
+            fields.get(4).and_then(|f| f.parse::<u8>().ok()),
+            fields.get(5).and_then(|f| f.parse::<u16>().ok()),
+        ) {
+            (Some(halfmoves), Some(fullmoves)) => (halfmoves, fullmoves.max(1)),
+            _ => (0, 1),
+        };
+
+        let fen = format!("{} {} {}", fields[..4].join(" "), counters.0, counters.1);
+        let board =
+            Board::try_from_fen(&fen).map_err(|e| format!("Invalid EPD on line {}: {e}", n + 1))?;
+        let ply = 2 * (counters.1 as usize - 1) + (board.side == Colour::Black) as usize;
+        openings.push(Opening { board, ply });
+    }
+
+    Ok(openings)
+}
+
+/// Games in PGN, starting from the `FEN` tag if present. Comments, variations, NAGs and
+/// results are skipped and only the first `max_ply` moves are played.
+pub fn parse_pgn(text: &str, max_ply: usize) -> Result<Vec<Opening>, String> {
+    let mut openings = Vec::new();
+    let mut start = Board::default();
+    let mut movetext = String::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            if !movetext.trim().is_empty() {
+                openings.push(play_game(start, &movetext, max_ply, openings.len())?);
+                movetext.clear();
+                start = Board::default();
+            }
+
+            if let Some(fen) = line
+                .strip_prefix("[FEN \"")
+                .and_then(|l| l.strip_suffix("\"]"))
+            {
+                start = Board::try_from_fen(fen)
+                    .map_err(|e| format!("Invalid FEN tag in game {}: {e}", openings.len() + 1))?;
+            }
+        } else if !line.starts_with('%') {
+            // Rest of line comments
+            movetext.push_str(line.split(';').next().unwrap_or_default());
+            movetext.push(' ');
+        }
+    }
+
+    if !movetext.trim().is_empty() {
+        openings.push(play_game(start, &movetext, max_ply, openings.len())?);
+    }
+
+    Ok(openings)
+}
+
+fn play_game(
+    mut board: Board,
+    movetext: &str,
+    max_ply: usize,
+    game: usize,
+) -> Result<Opening, String> {
+    let mut ply = 0;
+    let mut depth = 0; // Nesting of comments and variations
+
+    for token in movetext
+        .replace('{', " { ")
+        .replace('}', " } ")
+        .replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+    {
+        match token {
+            "{" | "(" => depth += 1,
+            "}" | ")" => depth -= 1,
+            _ if depth > 0 || token.starts_with('$') => {}
+            "1-0" | "0-1" | "1/2-1/2" | "*" => break,
+            _ => {
+                // Move numbers may come attached to the move: "1.e4", "12...Nf6"
+                let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+                if san.is_empty() || ply >= max_ply {
+                    continue;
+                }
+
+                let m = parse_san(&board, san)
+                    .ok_or_else(|| format!("Illegal move {san} in game {}", game + 1))?;
+                board.make_move(m);
+                ply += 1;
+            }
+        }
+    }
+
+    Ok(Opening { board, ply })
+}
+
+/// Finds the legal move matching a move in Standard Algebraic Notation
+pub fn parse_san(board: &Board, san: &str) -> Option<Move> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let mut legal = board
+        .generate_pseudo_moves::<true, true>()
+        .into_iter()
+        .filter(|&m| board.is_legal(m));
+
+    let castle_col = match san {
+        "O-O" | "0-0" => Some(6),
+        "O-O-O" | "0-0-0" => Some(2),
+        _ => None,
+    };
+    if let Some(col) = castle_col {
+        return legal.find(|m| m.get_type() == MoveKind::Castle && m.get_dest().col() == col);
+    }
+
+    let (body, promo) = match san.split_once('=') {
+        Some((body, promo)) => (body, promo.chars().next()),
+        None => match san.chars().last() {
+            Some(c @ ('Q' | 'R' | 'B' | 'N')) => (&san[..san.len() - 1], Some(c)),
+            _ => (san, None),
+        },
+    };
+
+    let mut chars: Vec<char> = body.chars().filter(|&c| c != 'x' && c != '-').collect();
+    let piece = match chars.first() {
+        Some(&c @ ('K' | 'Q' | 'R' | 'B' | 'N')) => {
+            chars.remove(0);
+            c
+        }
+        _ => 'P',
+    };
+
+    if chars.len() < 2 {
+        return None;
+    }
+
+    let (file, rank) = (chars[chars.len() - 2], chars[chars.len() - 1]);
+    if !('a'..='h').contains(&file) || !('1'..='8').contains(&rank) {
+        return None;
+    }
+    let dest = Square::from_row_col(rank as usize - '1' as usize, file as usize - 'a' as usize);
+    let disambiguation = &chars[..chars.len() - 2];
+
+    legal.find(|&m| {
+        let src = m.get_source();
+        let kind = m.get_type();
+        board.piece_at(src).to_char().to_ascii_uppercase() == piece
+            && m.get_dest() == dest
+            && disambiguation.iter().all(|&c| match c {
+                'a'..='h' => src.col() == c as usize - 'a' as usize,
+                '1'..='8' => src.row() == c as usize - '1' as usize,
+                _ => false,
+            })
+            && match promo {
+                Some(p) => kind.is_promotion() && kind.get_promotion(Colour::White).to_char() == p,
+                None => !kind.is_promotion(),
+            }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epd() {
+        let text = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - bm e5; id \"e4\";\n\
+                    r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3\n";
+        let openings = parse_epd(text).unwrap();
+
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].ply, 1);
+        assert_eq!(openings[1].ply, 4);
+        assert_eq!(openings[1].board.halfmoves, 2);
+        assert!(parse_epd("not an epd").is_err());
+        assert!(parse_epd("8/8/8/8/8/8/8/8 w - -").is_err());
+        assert!(parse_epd("r3k2r/8/8/8/8/8/8/R3K2R w KQkqX -").is_err());
+    }
+
+    #[test]
+    fn test_pgn() {
+        let text = "[Event \"?\"]\n[Result \"*\"]\n\n\
+                    1. e4 e5 2. Nf3 {main line} Nc6 (2... d6) 3. Bb5 a6 4. Bxc6 dxc6 5. O-O f6 *\n\n\
+                    [FEN \"4k3/1P6/8/8/8/8/6K1/R6R w - - 0 1\"]\n\n\
+                    1. Rhb1 $1 Kd7 2. b8=N+ Kc7 *\n";
+        let openings = parse_pgn(text, 100).unwrap();
+
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].ply, 10);
+        assert_eq!(
+            openings[0].board.hash.0,
+            Board::from_fen("r1bqkbnr/1pp3pp/p1p2p2/4p3/4P3/5N2/PPPP1PPP/RNBQ1RK1 w kq - 0 6")
+                .hash
+                .0
+        );
+        assert_eq!(
+            openings[1].board.hash.0,
+            Board::from_fen("1N6/2k5/8/8/8/8/6K1/RR6 w - - 1 3").hash.0
+        );
+
+        let cut = parse_pgn(text, 3).unwrap();
+        assert_eq!(cut[0].ply, 3);
+        assert!(parse_pgn("1. e4 e4 *", 100).is_err());
+        assert!(parse_pgn("[FEN \"8/8/8/8/8/8/8/8 w - - 0 1\"]\n\n1. e4 *", 100).is_err());
+    }
+
+    #[test]
+    fn test_sampling() {
+        let text =
+            "1. e4 *\n\n[Event \"\"]\n1. d4 *\n\n[Event \"\"]\n1. c4 *\n\n[Event \"\"]\n1. e4 e5 *";
+        let openings = parse_pgn(text, 100).unwrap();
+        let mut a = OpeningBook::from_openings(openings.clone(), 1, 1, 42).unwrap();
+        let mut b = OpeningBook::from_openings(openings, 1, 1, 42).unwrap();
+
+        assert_eq!(a.len(), 3);
+        for _ in 0..20 {
+            assert_eq!(a.sample().board.hash, b.sample().board.hash);
+        }
+    }
+
+    #[test]
+    fn test_random_openings() {
+        let book = OpeningBook::random(4, 6, 150, 7).unwrap();
+        assert_eq!(book.len(), 4);
+        for opening in &book.openings {
+            assert_eq!(opening.ply, 6);
+            assert_eq!(opening.board.fullmoves, 4);
+        }
+
+        // Reproducible for a given seed, and varied
+        let same = OpeningBook::random(4, 6, 150, 7).unwrap();
+        let hashes = |b: &OpeningBook| {
+            b.openings
+                .iter()
+                .map(|o| o.board.hash.0)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hashes(&book), hashes(&same));
+        let mut distinct = hashes(&book);
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert!(distinct.len() > 1);
+    }
+}
@@ -1,50 +1,256 @@
+use crate::board::Board;
+use crate::piece::{Colour, Piece, PieceType};
+use crate::square::Square;
+
 /// Castling rights struct
 /// Implemented through a flag bit vector. This allows for fast castle update without needing
 /// bitboard lookups.
 ///
 ///  WK | WQ | BK | BQ  --> only using least significant 8 bits
 ///  08   04   02   01
+///
+/// Besides the flags it keeps the file of the rook holding each right, so that positions where
+/// an inner rook castles (or Chess960 ones) keep track of the right rook. Only the flags are
+/// hashed, the files do not change during a game.
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Debug, Hash)]
-pub struct CastlingRights(pub u8);
+pub struct CastlingRights {
+    pub flags: u8,
+    pub rook_files: [[u8; 2]; 2], // [colour][kingside, queenside]
+}
 
 impl CastlingRights {
     pub const WK: u8 = 0x08;
     pub const WQ: u8 = 0x04;
     pub const BK: u8 = 0x02;
     pub const BQ: u8 = 0x01;
-    pub const NONE: CastlingRights = CastlingRights(0);
+    pub const NONE: CastlingRights = CastlingRights {
+        flags: 0,
+        rook_files: [[7, 0], [7, 0]],
+    };
+
+    /// Flag of each right by colour, kingside then queenside
+    const RIGHTS: [[u8; 2]; 2] = [[Self::WK, Self::WQ], [Self::BK, Self::BQ]];
 
     pub const fn index(self) -> usize {
-        self.0 as usize
+        self.flags as usize
+    }
+
+    pub const fn has(self, right: u8) -> bool {
+        self.flags & right != 0
+    }
+
+    /// Flag of the right of `colour` to castle on the `kingside` or queenside
+    pub const fn flag(colour: Colour, kingside: bool) -> u8 {
+        Self::RIGHTS[colour as usize][!kingside as usize]
+    }
+
+    /// Square of the rook which castles with the king on `colour`'s `kingside` or queenside
+    pub const fn rook_square(self, colour: Colour, kingside: bool) -> Square {
+        let row = match colour {
+            Colour::White => 0,
+            Colour::Black => 7,
+        };
+        Square::from_row_col(
+            row,
+            self.rook_files[colour as usize][!kingside as usize] as usize,
+        )
     }
 
-    pub fn from(rights: &str) -> Self {
+    /// Source and destination of the rook moved by the castle of the king to `dest`
+    pub const fn castle_rook(self, dest: Square) -> (Square, Square) {
+        let colour = if dest.row() == 0 {
+            Colour::White
+        } else {
+            Colour::Black
+        };
+        let kingside = dest.col() > 4;
+        let rook_dest = Square::from_row_col(dest.row(), if kingside { 5 } else { 3 });
+        (self.rook_square(colour, kingside), rook_dest)
+    }
+
+    /// Whether the rook on `square` still holds a castling right
+    pub fn is_castling_rook(self, square: Square) -> bool {
+        [Colour::White, Colour::Black].into_iter().any(|colour| {
+            [true, false].into_iter().any(|kingside| {
+                self.has(Self::RIGHTS[colour as usize][!kingside as usize])
+                    && self.rook_square(colour, kingside) == square
+            })
+        })
+    }
+
+    /// Rights left after `piece` moves from `src` to `dest`: a king move loses both rights of its
+    /// side, while moving or capturing a castling rook loses its own
+    pub fn update(self, piece: Piece, src: Square, dest: Square) -> Self {
+        let mut rights = self;
+        if piece.is_king() {
+            let [kingside, queenside] = Self::RIGHTS[piece.colour() as usize];
+            rights.flags &= !(kingside | queenside);
+        }
+        for colour in [Colour::White, Colour::Black] {
+            for kingside in [true, false] {
+                let rook = self.rook_square(colour, kingside);
+                if rook == src || rook == dest {
+                    rights.flags &= !Self::RIGHTS[colour as usize][!kingside as usize];
+                }
+            }
+        }
+        rights
+    }
+
+    /// Parses the castling field of a FEN, which can be either classical `KQkq`, where each
+    /// letter stands for the outermost rook on that side of the king, or X-FEN/Shredder file
+    /// letters such as `HAha`. Must be called once the pieces are on `board`.
+    pub fn parse(rights: &str, board: &Board) -> Result<Self, String> {
+        let mut parsed = Self::NONE;
         if rights == "-" {
-            return Self::NONE;
+            return Ok(parsed);
         }
 
-        let mut right = Self::NONE;
         for token in rights.chars() {
-            right.0 |= match token {
-                'K' => Self::WK,
-                'Q' => Self::WQ,
-                'k' => Self::BK,
-                'q' => Self::BQ,
-                _ => panic!("Invalid CastlingRights in FEN"),
+            let colour = if token.is_ascii_uppercase() {
+                Colour::White
+            } else {
+                Colour::Black
             };
+            let king = board.king_square(colour as usize).col() as u8;
+            let file = match token.to_ascii_lowercase() {
+                'k' => Self::outer_rook(board, colour, true),
+                'q' => Self::outer_rook(board, colour, false),
+                f @ 'a'..='h' if f as u8 - b'a' != king => f as u8 - b'a',
+                _ => return Err(format!("Invalid CastlingRights in FEN: {rights}")),
+            };
+
+            let side = usize::from(file < king);
+            parsed.flags |= Self::RIGHTS[colour as usize][side];
+            parsed.rook_files[colour as usize][side] = file;
         }
 
-        right
+        Ok(parsed)
+    }
+
+    /// Castling field of the FEN of `board`, with `KQkq` for the rights of the outermost rooks
+    /// and the rook file letters for any other
+    pub fn to_fen(self, board: &Board) -> String {
+        let mut fen = String::new();
+        for colour in [Colour::White, Colour::Black] {
+            for kingside in [true, false] {
+                let side = usize::from(!kingside);
+                if !self.has(Self::RIGHTS[colour as usize][side]) {
+                    continue;
+                }
+
+                let file = self.rook_files[colour as usize][side];
+                let token = if file == Self::outer_rook(board, colour, kingside) {
+                    if kingside {
+                        'k'
+                    } else {
+                        'q'
+                    }
+                } else {
+                    (b'a' + file) as char
+                };
+                fen.push(match colour {
+                    Colour::White => token.to_ascii_uppercase(),
+                    Colour::Black => token,
+                });
+            }
+        }
+
+        if fen.is_empty() {
+            fen.push('-');
+        }
+        fen
+    }
+
+    /// File of the outermost rook of `colour` on its back rank and on the given side of its
+    /// king, or the corner file if there is none
+    fn outer_rook(board: &Board, colour: Colour, kingside: bool) -> u8 {
+        let row = match colour {
+            Colour::White => 0,
+            Colour::Black => 7,
+        };
+        let king = board.king_square(colour as usize).col();
+        let files = board
+            .pieces(colour, PieceType::Rook)
+            .filter(|sq| sq.row() == row)
+            .map(|sq| sq.col());
+
+        let file = if kingside {
+            files.filter(|&f| f > king).max().unwrap_or(7)
+        } else {
+            files.filter(|&f| f < king).min().unwrap_or(0)
+        };
+        file as u8
     }
 }
 
-pub const CASTLE_MASK: [u8; 64] = {
-    let mut m = [0xFF_u8; 64];
-    m[0] = !CastlingRights::WQ;
-    m[4] = !(CastlingRights::WK | CastlingRights::WQ);
-    m[7] = !CastlingRights::WK;
-    m[56] = !CastlingRights::BQ;
-    m[60] = !(CastlingRights::BK | CastlingRights::BQ);
-    m[63] = !CastlingRights::BK;
-    m
-};
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_castling_fen() {
+        let round_trip = |fen: &str| Board::from_fen(fen).to_fen();
+
+        // Classical rights stay classical, either written with letters or files
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(round_trip(start), start);
+        assert_eq!(
+            round_trip("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1"),
+            start
+        );
+        assert_eq!(
+            Board::from_fen(start).castling_rights,
+            CastlingRights {
+                flags: 0x0F,
+                rook_files: [[7, 0], [7, 0]],
+            }
+        );
+
+        // Inner rooks holding the rights need their file, outer ones still use KQkq
+        let inner = "r3k1rr/8/8/8/8/8/8/1R2K2R w Kg - 0 1";
+        let board = Board::from_fen(inner);
+        assert_eq!(board.castling_rights.rook_files, [[7, 0], [6, 0]]);
+        assert_eq!(board.to_fen(), inner);
+        let board = Board::from_fen("r3k1rr/8/8/8/8/8/8/1R2K2R w BQkq - 0 1");
+        assert_eq!(board.castling_rights.rook_files, [[7, 1], [7, 0]]);
+        assert_eq!(board.to_fen(), "r3k1rr/8/8/8/8/8/8/1R2K2R w Qkq - 0 1");
+
+        // Chess960 rights, outermost rooks on each side of the king
+        let frc = "bqnbrkrn/pppppppp/8/8/8/8/PPPPPPPP/BQNBRKRN w KQkq - 0 1";
+        let board = Board::from_fen(frc);
+        assert_eq!(board.castling_rights.rook_files, [[6, 4], [6, 4]]);
+        assert_eq!(board.to_fen(), frc);
+        assert_eq!(Board::from_fen(&frc.replace("KQkq", "GEge")).to_fen(), frc);
+
+        let none = "4k3/8/8/8/8/8/8/4K3 w - - 0 1";
+        assert_eq!(round_trip(none), none);
+        assert!(CastlingRights::parse("KX", &Board::from_fen(start)).is_err());
+        assert!(CastlingRights::parse("E", &Board::from_fen(start)).is_err());
+    }
+
+    #[test]
+    fn test_inner_rook_castle() {
+        // The right belongs to the g1 rook, so moving the h1 rook keeps it
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/4K1RR w G - 0 1");
+        let castle = crate::moves::Move::new(
+            Square::from("e1"),
+            Square::from("g1"),
+            crate::moves::MoveKind::Castle,
+        );
+        let legal = board.generate_pseudo_moves::<true, true>();
+        assert!(legal.as_slice().contains(&castle));
+
+        let mut moved = board;
+        moved.make_move(crate::moves::Move::new(
+            Square::from("h1"),
+            Square::from("h2"),
+            crate::moves::MoveKind::Quiet,
+        ));
+        assert!(moved.castling_rights.has(CastlingRights::WK));
+
+        board.make_move(castle);
+        assert_eq!(board.to_fen(), "4k3/8/8/8/8/8/8/5RKR b - - 1 1");
+        assert_eq!(board.hash, crate::zobrist::ZHash::new(&board));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::board::Board;
+use crate::moves::Move;
+use crate::search::INF;
+use crate::tables::{Bound, SearchData, TTEntry, TranspositionTable, MAX_HASH_MB};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 4] = b"OXCP";
+const VERSION: u8 = 2; // Since mate scores start at 32000
+
+/// Analysis to continue when the next search is on the position with `hash`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Resume {
+    pub hash: u64,
+    pub depth: u8, // Last completed iteration
+    pub score: i32,
+    pub best_move: Move,
+}
+
+/// State of an analysis saved to disk so it can be resumed in a later session: the position,
+/// the depth reached with its score and PV, and optionally the transposition table
+pub struct Checkpoint {
+    pub fen: String,
+    pub depth: u8,
+    pub score: i32,
+    pub pv: Vec<Move>,
+    pub tt: Option<(u8, Vec<TTEntry>)>, // Age and entries
+}
+
+impl Checkpoint {
+    /// Takes the state of the last search, which must have been on `board`
+    pub fn capture(board: &Board, data: &SearchData, with_tt: bool) -> Result<Self, String> {
+        let pv: Vec<Move> = data.ply_data[0].pv.as_slice().to_vec();
+        let best_move = pv.first().copied().unwrap_or(data.best_move);
+        if !board.is_pseudo_legal(best_move) || !board.is_legal(best_move) {
+            return Err(String::from("No analysis of the current position to save"));
+        }
+
+        Ok(Self {
+            fen: board.to_fen(),
+            depth: data.depth.saturating_sub(1),
+            score: data.eval,
+            pv,
+            tt: with_tt.then(|| (data.tt.age(), data.tt.tt.clone())),
+        })
+    }
+
+    /// Sets up `data` to continue the analysis and returns its position. Without a saved
+    /// table the PV is stored as move hints that never cause a cutoff. The PV, read from disk,
+    /// is cut at its first move which cannot be played.
+    pub fn restore(self, data: &mut SearchData) -> Result<Board, String> {
+        let board = Board::try_from_fen(&self.fen)?;
+
+        let mut pv = Vec::with_capacity(self.pv.len());
+        let mut pos = board;
+        for &m in &self.pv {
+            if !pos.is_pseudo_legal(m) || !pos.is_legal(m) {
+                break;
+            }
+            pv.push((pos.hash.0, m));
+            pos.make_move(m);
+        }
+
+        match self.tt {
+            Some((age, entries)) => data.tt = TranspositionTable::from_entries(entries, age),
+            None => {
+                for &(hash, m) in &pv {
+                    data.tt.insert(hash, Bound::Lower, m, -INF, 0, false);
+                }
+            }
+        }
+
+        data.resume = Some(Resume {
+            hash: board.hash.0,
+            depth: self.depth,
+            score: self.score,
+            best_move: pv.first().map_or(Move::NULL, |&(_, m)| m),
+        });
+        Ok(board)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+        self.write(&mut BufWriter::new(file))
+            .map_err(|e| format!("{path}: {e}"))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+        let size = file.metadata().map_err(|e| format!("{path}: {e}"))?.len();
+        Self::read(&mut BufReader::new(file), size).map_err(|e| format!("{path}: {e}"))
+    }
+
+    fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&(self.fen.len() as u16).to_le_bytes())?;
+        out.write_all(self.fen.as_bytes())?;
+        out.write_all(&[self.depth])?;
+        out.write_all(&self.score.to_le_bytes())?;
+        out.write_all(&[self.pv.len() as u8])?;
+        for m in &self.pv {
+            out.write_all(&m.0.to_le_bytes())?;
+        }
+
+        let (age, entries) = match &self.tt {
+            Some((age, entries)) => (*age, entries.as_slice()),
+            None => (0, &[][..]),
+        };
+        out.write_all(&[age])?;
+        out.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for e in entries {
+            out.write_all(&e.key.to_le_bytes())?;
+            out.write_all(&e.value.to_le_bytes())?;
+            out.write_all(&e.best_move.0.to_le_bytes())?;
+            out.write_all(&[e.age, e.flags])?;
+        }
+        out.flush()
+    }
+
+    /// Reads a checkpoint of `size` bytes, which bounds what its header may claim
+    fn read(input: &mut impl Read, size: u64) -> Result<Self, String> {
+        let consumed = std::cell::Cell::new(0);
+        let mut bytes = |n: usize| -> Result<Vec<u8>, String> {
+            let mut buf = vec![0; n];
+            input
+                .read_exact(&mut buf)
+                .map_err(|_| String::from("truncated checkpoint"))?;
+            consumed.set(consumed.get() + n as u64);
+            Ok(buf)
+        };
+
+        if bytes(4)? != MAGIC || bytes(1)?[0] != VERSION {
+            return Err(String::from("not a checkpoint of this version"));
+        }
+
+        let fen_len = u16::from_le_bytes(bytes(2)?.try_into().unwrap()) as usize;
+        let fen = String::from_utf8(bytes(fen_len)?).map_err(|_| "invalid FEN")?;
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() != 6 || fields[0].matches('/').count() != 7 {
+            return Err(format!("invalid FEN {fen}"));
+        }
+
+        let depth = bytes(1)?[0];
+        let score = i32::from_le_bytes(bytes(4)?.try_into().unwrap());
+        if score.abs() > INF {
+            return Err(format!("score {score} out of range"));
+        }
+        let pv_len = bytes(1)?[0] as usize;
+        let pv = bytes(2 * pv_len)?
+            .chunks_exact(2)
+            .map(|c| Move(u16::from_le_bytes([c[0], c[1]])))
+            .collect();
+
+        let age = bytes(1)?[0];
+        let len = u64::from_le_bytes(bytes(8)?.try_into().unwrap());
+        let max_len = (MAX_HASH_MB * 1024 * 1024 / std::mem::size_of::<TTEntry>()) as u64;
+        let tt_bytes = len
+            .checked_mul(16)
+            .filter(|&n| len <= max_len && n <= size.saturating_sub(consumed.get()))
+            .ok_or_else(|| format!("TT of {len} entries out of range"))?;
+        let tt = if len == 0 {
+            None
+        } else {
+            let entries = bytes(tt_bytes as usize)?
+                .chunks_exact(16)
+                .map(|c| TTEntry {
+                    key: u64::from_le_bytes(c[0..8].try_into().unwrap()),
+                    value: i32::from_le_bytes(c[8..12].try_into().unwrap()),
+                    best_move: Move(u16::from_le_bytes([c[12], c[13]])),
+                    age: c[14],
+                    flags: c[15],
+                })
+                .collect::<Vec<TTEntry>>();
+            if entries.iter().any(|e| e.value.abs() > INF) {
+                return Err(String::from("TT score out of range"));
+            }
+            Some((age, entries))
+        };
+
+        Ok(Self {
+            fen,
+            depth,
+            score,
+            pv,
+            tt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::SearchLimits;
+    use crate::search::find_best_move;
+
+    #[test]
+    fn test_checkpoint() {
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let search = |data: &mut SearchData, depth| {
+            data.deterministic = true;
+            data.silent = true;
+            find_best_move(&board, &SearchLimits::new().depth(depth), data);
+            data.nodes
+        };
+
+        let mut data = SearchData::new();
+        search(&mut data, 6);
+        let saved = Checkpoint::capture(&board, &data, true).unwrap();
+        assert_eq!(saved.depth, 6);
+        assert!(Checkpoint::capture(&Board::default(), &data, false).is_err());
+
+        let mut bytes = Vec::new();
+        saved.write(&mut bytes).unwrap();
+        let size = bytes.len() as u64;
+        let loaded = Checkpoint::read(&mut bytes.as_slice(), size).unwrap();
+        assert_eq!(loaded.fen, saved.fen);
+        assert_eq!((loaded.depth, loaded.score), (saved.depth, saved.score));
+        assert_eq!(loaded.pv, saved.pv);
+        assert_eq!(loaded.tt.as_ref().unwrap().1.len(), data.tt.tt.len());
+        assert!(Checkpoint::read(&mut &bytes[..bytes.len() - 1], size - 1).is_err());
+
+        // Files of the previous score range, or with scores past the current one, are refused
+        let mut old = bytes.clone();
+        old[4] = 1;
+        assert!(Checkpoint::read(&mut old.as_slice(), size).is_err());
+        let score_at = 8 + saved.fen.len();
+        let mut corrupt = bytes.clone();
+        corrupt[score_at..score_at + 4].copy_from_slice(&(2 * INF).to_le_bytes());
+        assert!(Checkpoint::read(&mut corrupt.as_slice(), size).is_err());
+
+        // Resuming only searches the next iteration, far fewer nodes than starting over
+        let mut resumed = SearchData::new();
+        let restored = loaded.restore(&mut resumed).unwrap();
+        assert_eq!(restored.hash.0, board.hash.0);
+        let resumed_nodes = search(&mut resumed, 7);
+        assert_eq!(resumed.depth, 8);
+        assert!(resumed.resume.is_none());
+        assert!(resumed_nodes < search(&mut SearchData::new(), 7));
+
+        // Without the table the PV is still tried first
+        let mut hinted = SearchData::new();
+        let pv = saved.pv.clone();
+        Checkpoint { tt: None, ..saved }
+            .restore(&mut hinted)
+            .unwrap();
+        let entry = hinted.tt.probe(board.hash.0).unwrap();
+        assert_eq!(entry.best_move, pv[0]);
+    }
+
+    #[test]
+    fn test_corrupt_checkpoint() {
+        let board = Board::default();
+        let e4 = board.find_move("e2e4").unwrap();
+        let checkpoint = |fen: &str, pv: Vec<Move>| Checkpoint {
+            fen: String::from(fen),
+            depth: 3,
+            score: 20,
+            pv,
+            tt: None,
+        };
+
+        // The PV is cut at its first move which cannot be played, whatever its bits
+        let mut data = SearchData::new();
+        let fen = board.to_fen();
+        let restored = checkpoint(&fen, vec![e4, Move(u16::MAX), e4]).restore(&mut data);
+        assert_eq!(restored.unwrap().hash, board.hash);
+        assert_eq!(data.resume.unwrap().best_move, e4);
+        let mut data = SearchData::new();
+        checkpoint(&fen, vec![Move(0xB00F)])
+            .restore(&mut data)
+            .unwrap();
+        assert_eq!(data.resume.unwrap().best_move, Move::NULL);
+
+        // Edited positions are refused rather than set up
+        let empty = checkpoint("8/8/8/8/8/8/8/8 w - - 0 1", Vec::new());
+        assert!(empty.restore(&mut SearchData::new()).is_err());
+
+        // A table size past the file or the largest Hash is refused before allocating it
+        let mut bytes = Vec::new();
+        checkpoint(&fen, vec![e4]).write(&mut bytes).unwrap();
+        let len_at = bytes.len() - 8;
+        let size = bytes.len() as u64;
+        for (len, size) in [
+            (1000, size),
+            (1 << 30, size + (16 << 30)),
+            (u64::MAX, u64::MAX),
+        ] {
+            let mut corrupt = bytes.clone();
+            corrupt[len_at..].copy_from_slice(&len.to_le_bytes());
+            let error = Checkpoint::read(&mut corrupt.as_slice(), size).err();
+            assert!(error.is_some_and(|e| e.contains("out of range")), "{len}");
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::bitboard::BitBoard;
+use crate::rng::Rng;
 
 use super::square::Square;
 
@@ -306,3 +307,153 @@ pub const SIDE_KEY: u64 = {
     let s = xorshift64star(SEED ^ 0x55AA55AA55AA55AA);
     xorshift64star(s)
 };
+
+// Cheap invariants checked at compile time: leapers attack each other both ways, a white pawn
+// attacks the squares from which black pawns attack it, and no ray mask holds its own square
+const _: () = {
+    let mut a = 0;
+    while a < 64 {
+        let mut b = 0;
+        while b < 64 {
+            let (sq_a, sq_b) = (Square::new(a as u8), Square::new(b as u8));
+            assert!(KNIGHT_ATTACKS[a].contains(sq_b) == KNIGHT_ATTACKS[b].contains(sq_a));
+            assert!(KING_ATTACKS[a].contains(sq_b) == KING_ATTACKS[b].contains(sq_a));
+            assert!(PAWN_ATTACKS[0][a].contains(sq_b) == PAWN_ATTACKS[1][b].contains(sq_a));
+            b += 1;
+        }
+
+        let mut dir = 0;
+        while dir < 4 {
+            let mask = MASKS[a][dir];
+            assert!(mask.lower & mask.upper == 0 && mask.line_ex & (1 << a) == 0);
+            dir += 1;
+        }
+        a += 1;
+    }
+};
+
+/// Squares reached from `sq` stepping by each of `steps`, once for leapers or until a piece of
+/// `occ` or the edge for sliders, the slow way every table is checked against
+fn walk(sq: usize, steps: &[(i32, i32)], occ: u64, slides: bool) -> u64 {
+    let mut reached = 0;
+    for &(dr, dc) in steps {
+        let (mut r, mut c) = (sq as i32 / 8 + dr, sq as i32 % 8 + dc);
+        while (0..8).contains(&r) && (0..8).contains(&c) {
+            let bit = 1 << (8 * r + c);
+            reached |= bit;
+            if !slides || occ & bit != 0 {
+                break;
+            }
+            (r, c) = (r + dr, c + dc);
+        }
+    }
+    reached
+}
+
+/// Step from `from` towards `to` if both are on a line, a rank, file or diagonal
+fn line_step(from: usize, to: usize) -> Option<(i32, i32)> {
+    let dr = to as i32 / 8 - from as i32 / 8;
+    let dc = to as i32 % 8 - from as i32 % 8;
+    let aligned = (dr == 0) != (dc == 0) || (dr != 0 && dr.abs() == dc.abs());
+    aligned.then(|| (dr.signum(), dc.signum()))
+}
+
+/// Checks every attack, between and pinned moves table against `walk` for all squares and
+/// square pairs, the sliders with random occupancies, returning how many entries were checked
+/// or the first one which is wrong
+pub fn verify_tables() -> Result<usize, String> {
+    const ORTHOGONAL: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+    const DIAGONAL: [(i32, i32); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+    const KNIGHT: [(i32, i32); 8] = [
+        (1, 2),
+        (2, 1),
+        (2, -1),
+        (1, -2),
+        (-1, -2),
+        (-2, -1),
+        (-2, 1),
+        (-1, 2),
+    ];
+    const OCCUPANCIES: usize = 32;
+
+    let mut checked = 0;
+    let mut check = |table: &str, sq: String, got: BitBoard, expected: u64| {
+        checked += 1;
+        if got.0 == expected {
+            Ok(())
+        } else {
+            Err(format!(
+                "{table}[{sq}] is {:#018x}, expected {expected:#018x}",
+                got.0
+            ))
+        }
+    };
+
+    let king = [ORTHOGONAL, DIAGONAL].concat();
+    let mut rng = Rng::new(0x7AB1E5);
+    for sq in 0..64 {
+        let name = Square::new(sq as u8).to_string();
+        check(
+            "KNIGHT_ATTACKS",
+            name.clone(),
+            KNIGHT_ATTACKS[sq],
+            walk(sq, &KNIGHT, 0, false),
+        )?;
+        check(
+            "KING_ATTACKS",
+            name.clone(),
+            KING_ATTACKS[sq],
+            walk(sq, &king, 0, false),
+        )?;
+        for (side, dr) in [(0, 1), (1, -1)] {
+            let expected = walk(sq, &[(dr, -1), (dr, 1)], 0, false);
+            check(
+                "PAWN_ATTACKS",
+                format!("{side}][{name}"),
+                PAWN_ATTACKS[side][sq],
+                expected,
+            )?;
+        }
+
+        for i in 0..OCCUPANCIES {
+            // Sparse and dense boards, the empty one first
+            let occ = match i {
+                0 => 0,
+                _ => rng.next_u64() & rng.next_u64() & (rng.next_u64() | rng.next_u64()),
+            };
+            let at = format!("{name}, {occ:#018x}");
+            let expected = walk(sq, &ORTHOGONAL, occ, true);
+            check("rook_attacks", at.clone(), rook_attacks(occ, sq), expected)?;
+            let expected = walk(sq, &DIAGONAL, occ, true);
+            check("bishop_attacks", at, bishop_attacks(occ, sq), expected)?;
+        }
+
+        for other in 0..64 {
+            let pair = format!("{name}][{}", Square::new(other as u8));
+            let step = line_step(sq, other).filter(|_| sq != other);
+
+            // Squares strictly between the two, and the line from the king through the pinned
+            // piece to the edge, where it can move
+            let expected = step.map_or(0, |s| walk(sq, &[s], 1 << other, true) & !(1 << other));
+            check("BETWEEN", pair.clone(), BETWEEN[sq][other], expected)?;
+            let expected = step.map_or(0, |s| walk(sq, &[s], 0, true) & !(1 << other));
+            check("PINNED_MOVES", pair, PINNED_MOVES[sq][other], expected)?;
+        }
+    }
+
+    Ok(checked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_tables() {
+        // Attacks and slider occupancies of every square, and both tables of every pair
+        assert_eq!(verify_tables(), Ok(64 * (4 + 2 * 32 + 2 * 64)));
+        assert_eq!(KNIGHT_ATTACKS[0].0, 0x0000_0000_0002_0400); // b3 c2
+        assert_eq!(BETWEEN[0][63].0, 0x0040_2010_0804_0200); // b2 to g7
+        assert_eq!(PINNED_MOVES[0][9].0, 0x8040_2010_0804_0000); // c3 to h8
+    }
+}
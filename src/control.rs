@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Node checks between two polls of the limits at the start of a search. Searches limited by
+/// nodes or deterministic keep it so they stop at the same point on every run, otherwise it is
+/// calibrated so that polls happen every `POLL_PERIOD_US`.
+pub const POLL_INTERVAL: u32 = 1024;
+pub const POLL_PERIOD_US: u64 = 1000;
+pub const MIN_POLL_INTERVAL: u32 = 32;
+pub const MAX_POLL_INTERVAL: u32 = 1 << 16;
+
+/// Control block shared by every thread of a search: stop flag, global node count and
+/// deadlines in milliseconds.
+///
+/// Threads count nodes locally and publish them in batches, so the shared counter is only
+/// touched once every `POLL_INTERVAL` nodes. The main thread starts the search and stopping it
+/// from any thread stops all of them.
+pub struct SearchControl {
+    base: Instant,
+    start: AtomicU64, // Microseconds from `base` to the start of the search
+    stop: AtomicBool,
+    nodes: AtomicU64,
+    node_limit: AtomicU64,
+    soft: AtomicU64, // No new iteration starts after it
+    hard: AtomicU64, // The search is aborted after it
+}
+
+impl SearchControl {
+    pub fn new() -> Self {
+        Self {
+            base: Instant::now(),
+            start: AtomicU64::new(0),
+            stop: AtomicBool::new(false),
+            nodes: AtomicU64::new(0),
+            node_limit: AtomicU64::new(u64::MAX),
+            soft: AtomicU64::new(u64::MAX),
+            hard: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    pub fn start(&self, node_limit: u64, soft: u128, hard: u128) {
+        let now = self.base.elapsed().as_micros() as u64;
+        self.start.store(now, Ordering::Relaxed);
+        self.nodes.store(0, Ordering::Relaxed);
+        self.node_limit.store(node_limit, Ordering::Relaxed);
+        self.soft
+            .store(soft.min(u64::MAX as u128) as u64, Ordering::Relaxed);
+        self.hard
+            .store(hard.min(u64::MAX as u128) as u64, Ordering::Relaxed);
+        self.stop.store(false, Ordering::Release);
+    }
+
+    /// Moves the deadlines later, never earlier
+    pub fn extend(&self, soft: u128, hard: u128) {
+        let clamp = |ms: u128| ms.min(u64::MAX as u128) as u64;
+        self.soft.fetch_max(clamp(soft), Ordering::Relaxed);
+        self.hard.fetch_max(clamp(hard), Ordering::Relaxed);
+    }
+
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Release);
+    }
+
+    #[inline]
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn add_nodes(&self, nodes: u64) {
+        self.nodes.fetch_add(nodes, Ordering::Relaxed);
+    }
+
+    /// Nodes published by every thread so far
+    pub fn nodes(&self) -> u64 {
+        self.nodes.load(Ordering::Relaxed)
+    }
+
+    pub fn node_limit(&self) -> u64 {
+        self.node_limit.load(Ordering::Relaxed)
+    }
+
+    pub fn soft_limit(&self) -> u128 {
+        u128::from(self.soft.load(Ordering::Relaxed))
+    }
+
+    pub fn hard_limit(&self) -> u128 {
+        u128::from(self.hard.load(Ordering::Relaxed))
+    }
+
+    /// Milliseconds since the search started
+    pub fn elapsed(&self) -> u128 {
+        u128::from(self.elapsed_micros() / 1000)
+    }
+
+    pub fn elapsed_micros(&self) -> u64 {
+        let start = self.start.load(Ordering::Relaxed);
+        (self.base.elapsed().as_micros() as u64).saturating_sub(start)
+    }
+}
+
+impl Default for SearchControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control() {
+        let control = SearchControl::new();
+        control.stop();
+        control.start(5000, 100, 200);
+        assert!(!control.is_stopped());
+        assert_eq!(control.nodes(), 0);
+        assert_eq!((control.soft_limit(), control.hard_limit()), (100, 200));
+
+        control.add_nodes(3000);
+        control.add_nodes(2500);
+        assert_eq!(control.nodes(), 5500);
+        assert!(control.nodes() >= control.node_limit());
+
+        control.extend(300, 150);
+        assert_eq!((control.soft_limit(), control.hard_limit()), (300, 200));
+
+        control.start(u64::MAX, u128::MAX, u128::MAX);
+        assert_eq!(control.hard_limit(), u128::from(u64::MAX));
+    }
+}
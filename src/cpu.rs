@@ -0,0 +1,112 @@
+/// SIMD code the network runs on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Avx512,
+    Avx2,
+    Neon,
+    Scalar,
+}
+
+impl Backend {
+    pub const fn name(self) -> &'static str {
+        match self {
+            Backend::Avx512 => "AVX512",
+            Backend::Avx2 => "AVX2",
+            Backend::Neon => "NEON",
+            Backend::Scalar => "scalar",
+        }
+    }
+}
+
+/// Whether the AVX2 code of the network can run. Builds without a `target-cpu` check it at run
+/// time and fall back to scalar code, it is always there when the build enables it.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+pub fn has_avx2() -> bool {
+    cfg!(target_feature = "avx2") || std::arch::is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn has_avx2() -> bool {
+    false
+}
+
+/// Backend the network uses on this CPU, the best one the build was compiled for
+pub fn backend() -> Backend {
+    if cfg!(feature = "safe-only") {
+        Backend::Scalar
+    } else if cfg!(all(target_arch = "x86_64", target_feature = "avx512f")) {
+        Backend::Avx512
+    } else if cfg!(target_arch = "x86_64") && has_avx2() {
+        Backend::Avx2
+    } else if cfg!(target_arch = "aarch64") {
+        Backend::Neon
+    } else {
+        Backend::Scalar
+    }
+}
+
+/// Target features the build was compiled with but the CPU lacks, which is only the case when
+/// a `target-cpu=native` build runs on an older machine. Any instruction may then crash it.
+pub fn missing_features() -> Vec<&'static str> {
+    #[allow(unused_mut)]
+    let mut missing = Vec::new();
+
+    #[cfg(target_arch = "x86_64")]
+    macro_rules! check {
+        ($($feature:tt),*) => {
+            $(
+                if cfg!(target_feature = $feature)
+                    && !std::arch::is_x86_feature_detected!($feature)
+                {
+                    missing.push($feature);
+                }
+            )*
+        };
+    }
+    #[cfg(target_arch = "x86_64")]
+    check!(
+        "popcnt",
+        "bmi2",
+        "avx2",
+        "fma",
+        "avx512f",
+        "avx512bw",
+        "avx512vnni"
+    );
+
+    missing
+}
+
+/// Line reported at startup: the backend in use, or why the build cannot run on this CPU
+pub fn check() -> Result<String, String> {
+    let missing = missing_features();
+    if missing.is_empty() {
+        Ok(format!("using {}", backend().name()))
+    } else {
+        Err(format!(
+            "this CPU lacks {} which the build was compiled for, rebuild without target-cpu=native",
+            missing.join(", ")
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend() {
+        // Tests run where they were built
+        assert!(missing_features().is_empty());
+        assert_eq!(check(), Ok(format!("using {}", backend().name())));
+
+        let simd = !cfg!(feature = "safe-only");
+        match backend() {
+            Backend::Avx512 => assert!(simd && has_avx2()),
+            Backend::Avx2 => assert!(simd && has_avx2() && cfg!(target_arch = "x86_64")),
+            Backend::Neon => assert!(simd && cfg!(target_arch = "aarch64")),
+            Backend::Scalar => assert!(!simd || !has_avx2()),
+        }
+    }
+}
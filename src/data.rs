@@ -0,0 +1,504 @@
+// Training data for the NNUE trainers, both formats are 32 byte records:
+// marlinformat https://github.com/jnlt3/marlinflow (absolute, scores from White's point of view)
+// bulletformat https://github.com/jw1912/bulletformat (relative, board flipped for Black)
+
+use crate::adjudication::GameResult;
+use crate::bitboard::BitBoard;
+use crate::board::Board;
+use crate::castle::CastlingRights;
+use crate::moves::Move;
+use crate::piece::{Colour, Piece, PieceType};
+use crate::square::Square;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+pub const RECORD_SIZE: usize = 32;
+
+/// Marlinformat piece code for a rook that still has castling rights
+const UNMOVED_ROOK: u8 = 6;
+
+/// Corner squares of the rooks with their castling right, other rooks use their file letter
+const ROOK_CORNERS: [(usize, u8); 4] = [
+    (0, CastlingRights::WQ),
+    (7, CastlingRights::WK),
+    (56, CastlingRights::BQ),
+    (63, CastlingRights::BK),
+];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Format {
+    Marlin,
+    Bullet,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "marlin" | "marlinformat" => Some(Format::Marlin),
+            "bullet" | "bulletformat" => Some(Format::Bullet),
+            _ => None,
+        }
+    }
+}
+
+/// Position of a game with its search score (from White's point of view), the best move found
+/// and the final result
+pub struct DataPoint {
+    pub board: Board,
+    pub score: i16,
+    pub result: GameResult,
+    pub best_move: Move,
+}
+
+impl DataPoint {
+    /// Parses the text format `<fen> | <score> | <result> [| <best move>]` with the score in
+    /// centipawns and the result as 1.0, 0.5 or 0.0, both from White's point of view
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = line.split('|').map(str::trim).collect();
+        if fields.len() < 3 || fields[0].split_whitespace().count() != 6 {
+            return Err(format!("Invalid data line: {line}"));
+        }
+
+        let board = Board::try_from_fen(fields[0]).map_err(|e| format!("{e}: {line}"))?;
+        let score = fields[1]
+            .parse()
+            .map_err(|_| format!("Invalid score: {line}"))?;
+        let result = match fields[2] {
+            "1.0" | "1" | "1-0" => GameResult::WhiteWin,
+            "0.5" | "1/2-1/2" => GameResult::Draw,
+            "0.0" | "0" | "0-1" => GameResult::BlackWin,
+            _ => return Err(format!("Invalid result: {line}")),
+        };
+        let best_move = match fields.get(3) {
+            Some(uci) => board
+                .find_move(uci)
+                .map_err(|_| format!("Invalid move: {line}"))?,
+            None => Move::NULL,
+        };
+
+        Ok(Self {
+            board,
+            score,
+            result,
+            best_move,
+        })
+    }
+
+    pub fn ply(&self) -> usize {
+        2 * (self.board.fullmoves.max(1) as usize - 1) + self.board.side as usize
+    }
+
+    /// Captures and promotions, positions where the static evaluation is unreliable
+    pub fn is_noisy(&self) -> bool {
+        let kind = self.best_move.get_type();
+        self.best_move != Move::NULL && (kind.is_capture() || kind.is_promotion())
+    }
+
+    pub fn encode(&self, format: Format) -> [u8; RECORD_SIZE] {
+        match format {
+            Format::Marlin => self.to_marlin(),
+            Format::Bullet => self.to_bullet(),
+        }
+    }
+
+    /// ```text
+    /// occupancy: u64, pieces: [u8; 16], stm | ep: u8, halfmoves: u8, fullmoves: u16,
+    /// score: i16, wdl: u8, extra: u8
+    /// ```
+    pub fn to_marlin(&self) -> [u8; RECORD_SIZE] {
+        let board = &self.board;
+        let mut out = [0; RECORD_SIZE];
+        let mut occ = board.occupancy();
+        out[0..8].copy_from_slice(&occ.0.to_le_bytes());
+
+        let mut i = 0;
+        while occ != BitBoard::EMPTY {
+            let sq = occ.pop_lsb();
+            let piece = board.piece_at(sq);
+            let unmoved = piece.is_rook() && board.castling_rights.is_castling_rook(sq);
+            let kind = if unmoved {
+                UNMOVED_ROOK
+            } else {
+                piece.piece_type() as u8
+            };
+
+            out[8 + i / 2] |= (kind | (piece.colour() as u8) << 3) << (4 * (i & 1));
+            i += 1;
+        }
+
+        let ep = board.en_passant.map_or(64, |sq| sq.index() as u8);
+        out[24] = (board.side as u8) << 7 | ep;
+        out[25] = board.halfmoves;
+        out[26..28].copy_from_slice(&self.board.fullmoves.to_le_bytes());
+        out[28..30].copy_from_slice(&self.score.to_le_bytes());
+        out[30] = (2.0 * self.result.score()) as u8;
+        out
+    }
+
+    /// ```text
+    /// occupancy: u64, pieces: [u8; 16], score: i16, result: u8, king: u8, opponent king: u8,
+    /// extra: [u8; 3]
+    /// ```
+    /// Everything is relative to the side to move, the opponent king is mirrored as seen by it
+    pub fn to_bullet(&self) -> [u8; RECORD_SIZE] {
+        let board = &self.board;
+        let stm = board.side;
+        let flip = |sq: usize| if stm == Colour::Black { sq ^ 56 } else { sq };
+
+        let mut out = [0; RECORD_SIZE];
+        let mut occ = board.occupancy();
+        if stm == Colour::Black {
+            occ = BitBoard(occ.0.swap_bytes());
+        }
+        out[0..8].copy_from_slice(&occ.0.to_le_bytes());
+
+        let mut i = 0;
+        while occ != BitBoard::EMPTY {
+            let sq = Square::new(flip(occ.pop_lsb().index()) as u8);
+            let piece = board.piece_at(sq);
+            let nibble = piece.piece_type() as u8 | ((piece.colour() != stm) as u8) << 3;
+
+            out[8 + i / 2] |= nibble << (4 * (i & 1));
+            i += 1;
+        }
+
+        let (score, result) = match stm {
+            Colour::White => (self.score, self.result.score()),
+            Colour::Black => (self.score.saturating_neg(), 1.0 - self.result.score()),
+        };
+        out[24..26].copy_from_slice(&score.to_le_bytes());
+        out[26] = (2.0 * result) as u8;
+        out[27] = flip(board.king_square(stm as usize).index()) as u8;
+        out[28] = flip(board.king_square(!stm as usize).index()) as u8 ^ 56;
+        out
+    }
+}
+
+/// Record decoded back to text for inspection, for bulletformat the position is the one seen
+/// by the side to move
+pub struct Record {
+    pub fen: String,
+    pub score: i16,
+    pub result: f32,
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} | {} | {:.1}", self.fen, self.score, self.result)
+    }
+}
+
+pub fn decode(bytes: &[u8; RECORD_SIZE], format: Format) -> Record {
+    let occ = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+    let mut squares = [None; Square::COUNT];
+    let mut rights = String::new();
+
+    let mut bb = BitBoard(occ);
+    let mut i = 0;
+    while bb != BitBoard::EMPTY {
+        let sq = bb.pop_lsb().index();
+        let nibble = (bytes[8 + i / 2] >> (4 * (i & 1))) & 0xF;
+        let (mut kind, colour) = ((nibble & 7) as usize, (nibble >> 3) as usize);
+        if format == Format::Marlin && kind == UNMOVED_ROOK as usize {
+            kind = PieceType::Rook.index();
+            if let Some(&(_, right)) = ROOK_CORNERS.iter().find(|&&(corner, _)| corner == sq) {
+                rights.push(match right {
+                    CastlingRights::WK => 'K',
+                    CastlingRights::WQ => 'Q',
+                    CastlingRights::BK => 'k',
+                    _ => 'q',
+                });
+            } else if sq / 8 == 0 || sq / 8 == 7 {
+                // An inner rook holding the right, as an X-FEN file letter
+                let file = (b'a' + (sq % 8) as u8) as char;
+                rights.push(if colour == 0 {
+                    file.to_ascii_uppercase()
+                } else {
+                    file
+                });
+            }
+        }
+
+        squares[sq] = Piece::COLOUR_PIECES
+            .get(colour)
+            .and_then(|p| p.get(kind))
+            .copied();
+        i += 1;
+    }
+
+    let mut placement = String::new();
+    for row in (0..8).rev() {
+        let mut empty = 0;
+        for col in 0..8 {
+            match squares[row * 8 + col] {
+                Some(piece) => {
+                    if empty > 0 {
+                        placement.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    placement.push(piece.to_char());
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            placement.push_str(&empty.to_string());
+        }
+        if row > 0 {
+            placement.push('/');
+        }
+    }
+
+    match format {
+        Format::Marlin => {
+            let side = if bytes[24] >> 7 == 0 { "w" } else { "b" };
+            let ep = match bytes[24] & 0x7F {
+                sq @ 0..=63 => Square::new(sq).to_string(),
+                _ => String::from("-"),
+            };
+            // Castling rights in KQkq order
+            let mut sorted: Vec<char> = rights.chars().collect();
+            sorted.sort_by_key(|c| "KQkq".find(*c));
+            let rights: String = sorted.into_iter().collect();
+            let fullmove = u16::from_le_bytes([bytes[26], bytes[27]]);
+
+            Record {
+                fen: format!(
+                    "{placement} {side} {} {ep} {} {fullmove}",
+                    if rights.is_empty() { "-" } else { &rights },
+                    bytes[25]
+                ),
+                score: i16::from_le_bytes([bytes[28], bytes[29]]),
+                result: bytes[30] as f32 / 2.0,
+            }
+        }
+        Format::Bullet => Record {
+            fen: format!("{placement} w - - 0 1"),
+            score: i16::from_le_bytes([bytes[24], bytes[25]]),
+            result: bytes[26] as f32 / 2.0,
+        },
+    }
+}
+
+/// Buffered writer of binary training data
+pub struct DataWriter {
+    out: BufWriter<File>,
+    format: Format,
+    pub written: u64,
+}
+
+impl DataWriter {
+    pub fn create(path: &str, format: Format) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+        Ok(Self {
+            out: BufWriter::new(file),
+            format,
+            written: 0,
+        })
+    }
+
+    pub fn write(&mut self, point: &DataPoint) -> Result<(), String> {
+        self.out
+            .write_all(&point.encode(self.format))
+            .map_err(|e| e.to_string())?;
+        self.written += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.out.flush().map_err(|e| e.to_string())
+    }
+}
+
+/// Positions kept when converting data for training
+pub struct Filter {
+    pub min_ply: usize,
+    pub max_ply: usize,
+    pub max_score: i16,
+    pub skip_noisy: bool,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            min_ply: 0,
+            max_ply: usize::MAX,
+            max_score: i16::MAX,
+            skip_noisy: false,
+        }
+    }
+}
+
+impl Filter {
+    pub fn accepts(&self, point: &DataPoint) -> bool {
+        (self.min_ply..=self.max_ply).contains(&point.ply())
+            && point.score.unsigned_abs() <= self.max_score.unsigned_abs()
+            && !(self.skip_noisy && point.is_noisy())
+    }
+}
+
+/// Standalone mode for the training pipeline:
+/// `datatool view <marlin|bullet> <file> [count]`
+/// `datatool filter <marlin|bullet> <input.txt> <output> [min-ply N] [max-ply N] [max-score N] [no-noisy]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let format = args
+        .get(1)
+        .and_then(|f| Format::from_name(f))
+        .ok_or("Expected format marlin or bullet")?;
+
+    match args.first().map(String::as_str) {
+        Some("view") => {
+            let path = args.get(2).ok_or("Expected input file")?;
+            let count = match args.get(3) {
+                Some(n) => n.parse().map_err(|_| format!("Invalid count: {n}"))?,
+                None => usize::MAX,
+            };
+
+            let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+            let mut reader = BufReader::new(file);
+            let mut bytes = [0; RECORD_SIZE];
+            for _ in 0..count {
+                if reader.read_exact(&mut bytes).is_err() {
+                    break;
+                }
+                println!("{}", decode(&bytes, format));
+            }
+        }
+        Some("filter") => {
+            let (input, output) = match (args.get(2), args.get(3)) {
+                (Some(input), Some(output)) => (input, output),
+                _ => return Err(String::from("Expected input and output files")),
+            };
+
+            let mut filter = Filter::default();
+            let mut options = args[4..].iter();
+            while let Some(option) = options.next() {
+                let mut value = || {
+                    options
+                        .next()
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .ok_or(format!("Invalid value for {option}"))
+                };
+                match option.as_str() {
+                    "min-ply" => filter.min_ply = value()?,
+                    "max-ply" => filter.max_ply = value()?,
+                    "max-score" => filter.max_score = value()?.min(i16::MAX as usize) as i16,
+                    "no-noisy" => filter.skip_noisy = true,
+                    _ => return Err(format!("Unknown option: {option}")),
+                }
+            }
+
+            let file = File::open(input).map_err(|e| format!("{input}: {e}"))?;
+            let mut writer = DataWriter::create(output, format)?;
+            let (mut total, mut invalid) = (0, 0);
+            for line in BufReader::new(file).lines() {
+                let line = line.map_err(|e| e.to_string())?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                total += 1;
+                // A corrupt line only loses its own position
+                let point = match DataPoint::parse(&line) {
+                    Ok(point) => point,
+                    Err(e) => {
+                        eprintln!("{e}");
+                        invalid += 1;
+                        continue;
+                    }
+                };
+                if filter.accepts(&point) {
+                    writer.write(&point)?;
+                }
+            }
+            writer.flush()?;
+
+            println!(
+                "Kept {} of {total} positions, {invalid} invalid",
+                writer.written
+            );
+        }
+        _ => return Err(String::from("Expected view or filter")),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    #[test]
+    fn test_marlin_reference() {
+        let point = DataPoint::parse(&format!("{START} | 25 | 0.5")).unwrap();
+        let expected: [u8; RECORD_SIZE] = [
+            0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, // Occupancy
+            0x16, 0x42, 0x25, 0x61, 0x00, 0x00, 0x00, 0x00, // White pieces
+            0x88, 0x88, 0x88, 0x88, 0x9E, 0xCA, 0xAD, 0xE9, // Black pieces
+            0x40, 0x00, 0x01, 0x00, 0x19, 0x00, 0x01, 0x00, // Side, ep, clocks, score, wdl
+        ];
+        assert_eq!(point.to_marlin(), expected);
+        assert_eq!(decode(&expected, Format::Marlin).fen, START);
+
+        let fen = "r3k2r/8/8/3pP3/8/8/8/R3K1R1 w Qk d6 0 12";
+        let point = DataPoint::parse(&format!("{fen} | -300 | 0-1 | e5d6")).unwrap();
+        let record = decode(&point.to_marlin(), Format::Marlin);
+        assert_eq!(record.fen, fen);
+        assert_eq!(record.score, -300);
+        assert_eq!(record.result, 0.0);
+        assert!(point.is_noisy());
+        assert_eq!(point.ply(), 22);
+    }
+
+    #[test]
+    fn test_bullet_reference() {
+        let point = DataPoint::parse(&format!("{START} | 25 | 1.0")).unwrap();
+        let expected: [u8; RECORD_SIZE] = [
+            0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, // Occupancy
+            0x13, 0x42, 0x25, 0x31, 0x00, 0x00, 0x00, 0x00, // Side to move pieces
+            0x88, 0x88, 0x88, 0x88, 0x9B, 0xCA, 0xAD, 0xB9, // Opponent pieces
+            0x19, 0x00, 0x02, 0x04, 0x04, 0x00, 0x00, 0x00, // Score, result, kings
+        ];
+        assert_eq!(point.to_bullet(), expected);
+
+        // Black to move is seen from Black's side: scores and results flip
+        let point =
+            DataPoint::parse("4k3/8/8/8/8/8/4P3/1K6 b - - 0 40 | 150 | 1.0 | e8d7").unwrap();
+        let bytes = point.to_bullet();
+        let record = decode(&bytes, Format::Bullet);
+        assert_eq!(record.fen, "1k6/4p3/8/8/8/8/8/4K3 w - - 0 1");
+        assert_eq!(record.score, -150);
+        assert_eq!(record.result, 0.0);
+        assert_eq!((bytes[27], bytes[28]), (4, 1));
+        assert!(!point.is_noisy());
+    }
+
+    #[test]
+    fn test_filter() {
+        let quiet = DataPoint::parse(&format!("{START} | 25 | 0.5 | e2e4")).unwrap();
+        let capture = DataPoint::parse(
+            "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 2 | 40 | 1.0 | e4d5",
+        )
+        .unwrap();
+        let filter = Filter {
+            min_ply: 1,
+            max_score: 30,
+            ..Filter::default()
+        };
+
+        assert!(!filter.accepts(&quiet));
+        assert!(!filter.accepts(&capture));
+        assert!(Filter::default().accepts(&capture));
+        assert!(!Filter {
+            skip_noisy: true,
+            ..Filter::default()
+        }
+        .accepts(&capture));
+
+        // Corrupt positions are errors rather than panics
+        assert!(DataPoint::parse("8/8/8/8/8/8/8/8 w - - 0 1 | 0 | 0.5").is_err());
+        assert!(DataPoint::parse("r3k2r/8/8/8/8/8/8/R3K2R w KQkqX - 0 1 | 0 | 0.5").is_err());
+    }
+}
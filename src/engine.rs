@@ -0,0 +1,357 @@
+use crate::board::Board;
+use crate::limits::SearchLimits;
+use crate::moves::Move;
+use crate::search::find_best_move;
+use crate::tables::SearchData;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+
+/// Cloneable cancellation flag shared between a running search and its owners.
+///
+/// Cancelling is sticky: once `cancel` is called every clone observes it until `reset`.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Outcome of a finished (or cancelled) search
+#[derive(Clone, Copy, Debug)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub score: i32,
+    pub depth: u8,
+    pub nodes: u64,
+}
+
+#[derive(Default)]
+struct HandleState {
+    result: Option<SearchResult>,
+    waker: Option<Waker>,
+}
+
+/// Handle to a search running on its own thread.
+///
+/// It can be joined synchronously with `join` or awaited from any async runtime, as it
+/// implements `Future` without depending on a specific executor.
+pub struct SearchHandle {
+    state: Arc<Mutex<HandleState>>,
+    thread: Option<JoinHandle<()>>,
+    token: CancellationToken,
+}
+
+impl SearchHandle {
+    /// Requests the search to stop, the result of the deepest completed iteration is kept
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().unwrap().result.is_some()
+    }
+
+    /// Blocks until the search finishes and returns its result
+    pub fn join(mut self) -> SearchResult {
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("Search thread panicked");
+        }
+
+        self.state
+            .lock()
+            .unwrap()
+            .result
+            .take()
+            .expect("Search finished without result")
+    }
+}
+
+impl Future for SearchHandle {
+    type Output = SearchResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Change to the settings or the state of an `Engine`, which never touches the tables of a
+/// running search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EngineCommand {
+    SetHash(usize), // MB
+    SetContempt(i32),
+    NewGame,
+}
+
+impl EngineCommand {
+    fn apply(self, data: &mut SearchData) {
+        match self {
+            EngineCommand::SetHash(mb) => data.resize_tt(mb.max(1)),
+            EngineCommand::SetContempt(contempt) => {
+                data.set_contempt(contempt);
+            }
+            EngineCommand::NewGame => data.new_game(),
+        }
+    }
+
+    /// Whether applying `self` makes an earlier `other` pointless, only the last value of a
+    /// setting counts
+    fn supersedes(self, other: EngineCommand) -> bool {
+        std::mem::discriminant(&self) == std::mem::discriminant(&other)
+    }
+}
+
+/// Commands waiting for the running search to end, in the order they were sent
+type CommandQueue = Arc<Mutex<Vec<EngineCommand>>>;
+
+/// Applies the queued commands to `data`, which the caller holds so no search is running
+fn apply_queued(queue: &CommandQueue, data: &mut SearchData) {
+    for command in std::mem::take(&mut *queue.lock().unwrap()) {
+        command.apply(data);
+    }
+}
+
+/// Applies the queued commands unless a search holds the tables, which applies them when it
+/// ends. Checking again once the tables are released catches commands queued meanwhile.
+fn flush(queue: &CommandQueue, data: &Mutex<SearchData>) {
+    while !queue.lock().unwrap().is_empty() {
+        match data.try_lock() {
+            Ok(mut data) => apply_queued(queue, &mut data),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Embeddable engine owning the search tables, so they stay warm between searches.
+///
+/// Only one search runs at a time, starting a new one while another is running blocks the new
+/// search thread until the previous one releases the tables. Commands sent meanwhile are queued
+/// and applied once it does, before any other search starts.
+pub struct Engine {
+    data: Arc<Mutex<SearchData>>,
+    queue: CommandQueue,
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self {
+            data: Arc::new(Mutex::new(SearchData::new())),
+            queue: CommandQueue::default(),
+        }
+    }
+
+    /// Applies `command` right away when no search is running, otherwise as soon as the running
+    /// one finishes, without waiting for it
+    pub fn send(&self, command: EngineCommand) {
+        {
+            let mut queue = self.queue.lock().unwrap();
+            queue.retain(|&queued| !command.supersedes(queued));
+            queue.push(command);
+        }
+        flush(&self.queue, &self.data);
+    }
+
+    /// Commands waiting for the running search to end
+    pub fn queued(&self) -> Vec<EngineCommand> {
+        self.queue.lock().unwrap().clone()
+    }
+
+    pub fn set_hash(&self, mb: usize) {
+        self.send(EngineCommand::SetHash(mb));
+    }
+
+    pub fn set_contempt(&self, contempt: i32) {
+        self.send(EngineCommand::SetContempt(contempt));
+    }
+
+    pub fn new_game(&self) {
+        self.send(EngineCommand::NewGame);
+    }
+
+    /// Starts searching `board` on a new thread up to `max_depth` or `movetime` milliseconds,
+    /// whatever happens first. Cancelling `token` stops the search as soon as possible.
+    pub fn search(
+        &self,
+        board: Board,
+        max_depth: u8,
+        movetime: u128,
+        token: CancellationToken,
+    ) -> SearchHandle {
+        let limits = SearchLimits::new().depth(max_depth).movetime(movetime);
+        self.search_with_limits(board, limits, token)
+    }
+
+    /// Starts searching `board` on a new thread until it reaches any of `limits`
+    pub fn search_with_limits(
+        &self,
+        board: Board,
+        limits: SearchLimits,
+        token: CancellationToken,
+    ) -> SearchHandle {
+        let state = Arc::new(Mutex::new(HandleState::default()));
+        let tables = Arc::clone(&self.data);
+        let queue = Arc::clone(&self.queue);
+        let thread_state = Arc::clone(&state);
+        let thread_token = token.clone();
+
+        let thread = std::thread::spawn(move || {
+            let mut data = tables.lock().unwrap();
+            // Commands sent while an earlier search held the tables
+            apply_queued(&queue, &mut data);
+            data.cancel = thread_token;
+            data.tt.inc_age();
+
+            find_best_move(&board, &limits, &mut data);
+
+            let result = SearchResult {
+                best_move: data.best_move,
+                score: data.eval,
+                depth: data.depth.saturating_sub(1),
+                nodes: data.nodes,
+            };
+            data.cancel = CancellationToken::default();
+            drop(data);
+            // Before the result, so that a joined search leaves no command behind
+            flush(&queue, &tables);
+
+            let mut state = thread_state.lock().unwrap();
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+
+        SearchHandle {
+            state,
+            thread: Some(thread),
+            token,
+        }
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Wake;
+    use std::time::{Duration, Instant};
+
+    /// Wakes the test thread parked on a future
+    struct Unpark(std::thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor polling `future` on the current thread until it is ready
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => std::thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, ignore = "timing only holds in release builds")]
+    fn test_cancel() {
+        let engine = Engine::new();
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+
+        // A search which would run for a minute stops soon after being cancelled
+        let handle = engine.search(board, 60, 60_000, CancellationToken::new());
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!handle.is_finished());
+        let cancelled = Instant::now();
+        handle.cancel();
+        let result = handle.join();
+        let elapsed = cancelled.elapsed();
+        assert!(elapsed < Duration::from_millis(100), "{elapsed:?}");
+        assert!(board.is_pseudo_legal(result.best_move) && board.is_legal(result.best_move));
+        assert!(result.depth >= 1 && result.nodes > 0);
+
+        // Awaited instead of joined, whether it finishes on its own or is cancelled
+        let result = block_on(engine.search(board, 4, 60_000, CancellationToken::new()));
+        assert!(board.is_legal(result.best_move));
+        assert_eq!(result.depth, 4);
+        let handle = engine.search(board, 60, 60_000, CancellationToken::new());
+        std::thread::sleep(Duration::from_millis(20));
+        handle.cancel();
+        let result = block_on(handle);
+        assert!(board.is_pseudo_legal(result.best_move) && board.is_legal(result.best_move));
+    }
+
+    #[test]
+    fn test_command_queue() {
+        let engine = Engine::new();
+        engine.set_contempt(-5);
+        assert!(engine.queued().is_empty());
+        assert_eq!(engine.data.lock().unwrap().contempt, -5);
+
+        let token = CancellationToken::new();
+        let handle = engine.search(Board::default(), 60, 10_000, token.clone());
+        while engine.data.try_lock().is_ok() {
+            std::thread::yield_now();
+        }
+
+        // Sent mid-search, the last value of each setting waits for the search to end
+        engine.set_hash(1);
+        engine.new_game();
+        engine.set_hash(2);
+        engine.set_contempt(500);
+        assert_eq!(
+            engine.queued(),
+            [
+                EngineCommand::NewGame,
+                EngineCommand::SetHash(2),
+                EngineCommand::SetContempt(500)
+            ]
+        );
+
+        token.cancel();
+        let result = handle.join();
+        assert!(result.best_move != Move::NULL);
+        assert!(engine.queued().is_empty());
+        let data = engine.data.lock().unwrap();
+        assert_eq!(data.contempt, 100);
+        assert!(!data.tt.is_allocated());
+    }
+}
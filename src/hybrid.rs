@@ -0,0 +1,97 @@
+use crate::bitboard::BitBoard;
+use crate::board::Board;
+use crate::constants::{bishop_attacks, queen_attacks, rook_attacks, KING_ATTACKS, KNIGHT_ATTACKS};
+use crate::piece::{Colour, PieceType};
+use crate::tunables::{AUX_KING_RING, AUX_MINOR_MOBILITY, AUX_QUEEN_MOBILITY, AUX_ROOK_MOBILITY};
+
+pub const AUX_FEATURES: usize = 4;
+
+/// Weights are in sixteenths of a centipawn per unit of feature
+const AUX_NORM: i32 = 16;
+
+/// Handcrafted features of the position, each as the difference between the side to move and
+/// the opponent: knight and bishop, rook and queen mobility, then attacks on the squares around
+/// the enemy king
+pub fn features(board: &Board) -> [i32; AUX_FEATURES] {
+    let us = side_features(board, board.side);
+    let them = side_features(board, !board.side);
+    std::array::from_fn(|i| us[i] - them[i])
+}
+
+/// Linear layer over the auxiliary features, in centipawns from the side to move. All weights
+/// default to zero so that it only changes the evaluation once they are set through UCI.
+pub fn aux_eval(board: &Board) -> i32 {
+    let weights = [
+        AUX_MINOR_MOBILITY.get(),
+        AUX_ROOK_MOBILITY.get(),
+        AUX_QUEEN_MOBILITY.get(),
+        AUX_KING_RING.get(),
+    ];
+    if weights == [0; AUX_FEATURES] {
+        return 0;
+    }
+
+    let features = features(board);
+    weights
+        .iter()
+        .zip(features)
+        .map(|(w, f)| w * f)
+        .sum::<i32>()
+        / AUX_NORM
+}
+
+/// Features of `side` alone, mobility counting the squares reached which are not occupied by its
+/// own pieces
+fn side_features(board: &Board, side: Colour) -> [i32; AUX_FEATURES] {
+    let occ = board.occupancy().0;
+    let own = board.sides[side as usize];
+    let enemy_king = board.king_square(!side as usize).index();
+    let ring = KING_ATTACKS[enemy_king];
+
+    let mut mobility = [BitBoard::EMPTY; 3];
+    let mut ring_attacks = 0;
+    for (piece, group) in [
+        (PieceType::Knight, 0),
+        (PieceType::Bishop, 0),
+        (PieceType::Rook, 1),
+        (PieceType::Queen, 2),
+    ] {
+        for sq in board.pieces(side, piece) {
+            let reach = match piece {
+                PieceType::Knight => KNIGHT_ATTACKS[sq.index()],
+                PieceType::Bishop => bishop_attacks(occ, sq.index()),
+                PieceType::Rook => rook_attacks(occ, sq.index()),
+                _ => queen_attacks(occ, sq.index()),
+            } & !own;
+            mobility[group] |= reach;
+            ring_attacks += (reach & ring).count_bits() as i32;
+        }
+    }
+
+    [
+        mobility[0].count_bits() as i32,
+        mobility[1].count_bits() as i32,
+        mobility[2].count_bits() as i32,
+        ring_attacks,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_features() {
+        // Symmetric positions have no difference in any feature
+        assert_eq!(features(&Board::default()), [0; AUX_FEATURES]);
+
+        // A rook on an open file next to the enemy king
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3RK3 w - - 0 1");
+        assert_eq!(features(&board), [0, 10, 0, 2]);
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3RK3 b - - 0 1");
+        assert_eq!(features(&board), [0, -10, 0, -2]);
+
+        // Weights start at zero, leaving the network output untouched
+        assert_eq!(aux_eval(&board), 0);
+    }
+}
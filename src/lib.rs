@@ -0,0 +1,33 @@
+pub mod adjudication;
+pub mod analyze;
+pub mod bitboard;
+pub mod board;
+pub mod book;
+pub mod castle;
+pub mod checkpoint;
+pub mod constants;
+pub mod control;
+pub mod cpu;
+pub mod data;
+pub mod engine;
+#[cfg(feature = "hybrid")]
+pub mod hybrid;
+pub mod limits;
+pub mod logging;
+pub mod moves;
+pub mod network;
+pub mod perft;
+pub mod piece;
+pub mod repertoire;
+pub mod rng;
+pub mod search;
+pub mod serve;
+pub mod sprt;
+pub mod square;
+pub mod tables;
+pub mod threads;
+pub mod tunables;
+pub mod uci;
+pub mod zobrist;
+
+pub use engine::{CancellationToken, Engine, EngineCommand, SearchHandle, SearchResult};
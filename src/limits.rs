@@ -0,0 +1,118 @@
+use crate::moves::Move;
+use crate::search::{MATE, MAX_DEPTH};
+
+/// Constraints of a search, which ends as soon as it reaches any of them. The default is an
+/// unbounded search, narrowed down with the builder methods:
+///
+/// ```
+/// use oxide::limits::SearchLimits;
+///
+/// let limits = SearchLimits::new().depth(12).nodes(1_000_000).movetime(500);
+/// assert_eq!((limits.depth, limits.nodes, limits.time), (12, 1_000_000, 500));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchLimits {
+    pub depth: u8,               // Last iteration searched
+    pub nodes: u64,              // Counting the nodes of every thread
+    pub time: u128,              // Milliseconds after which no new iteration starts
+    pub max_time: u128,          // Hard limit when panicking after a root fail low, 0 never extends
+    pub mate: Option<u8>,        // Stops once a mate in at most this many moves is found
+    pub search_moves: Vec<Move>, // Root moves searched, every legal one when empty
+}
+
+impl SearchLimits {
+    pub fn new() -> Self {
+        Self {
+            depth: MAX_DEPTH,
+            nodes: u64::MAX,
+            time: u128::MAX,
+            max_time: 0,
+            mate: None,
+            search_moves: Vec::new(),
+        }
+    }
+
+    pub fn depth(mut self, depth: u8) -> Self {
+        self.depth = depth.clamp(1, MAX_DEPTH);
+        self
+    }
+
+    pub fn nodes(mut self, nodes: u64) -> Self {
+        self.nodes = nodes;
+        self
+    }
+
+    /// Fixed time for the move, never extended
+    pub fn movetime(mut self, ms: u128) -> Self {
+        self.time = ms;
+        self.max_time = 0;
+        self
+    }
+
+    /// Time allocated out of a game clock, which a failing root may extend up to `max_time`
+    pub fn clock(mut self, time: u128, max_time: u128) -> Self {
+        self.time = time;
+        self.max_time = max_time;
+        self
+    }
+
+    pub fn mate(mut self, moves: u8) -> Self {
+        self.mate = Some(moves.max(1));
+        self
+    }
+
+    pub fn search_moves(mut self, moves: Vec<Move>) -> Self {
+        self.search_moves = moves;
+        self
+    }
+
+    /// Whether the root may search `m`
+    #[inline]
+    pub fn allows(&self, m: Move) -> bool {
+        self.search_moves.is_empty() || self.search_moves.contains(&m)
+    }
+
+    /// Whether `score` is a mate for the side to move within the `mate` limit
+    pub fn mate_found(&self, score: i32) -> bool {
+        self.mate
+            .is_some_and(|moves| score >= MATE - (2 * i32::from(moves) - 1))
+    }
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+
+    #[test]
+    fn test_limits() {
+        let board = Board::default();
+        let e2e4 = board.find_move("e2e4").unwrap();
+        let limits = SearchLimits::new()
+            .depth(0)
+            .clock(1000, 3000)
+            .mate(2)
+            .search_moves(vec![e2e4]);
+        assert_eq!(
+            (limits.depth, limits.time, limits.max_time),
+            (1, 1000, 3000)
+        );
+        assert_eq!(limits.clone().movetime(200).max_time, 0);
+
+        assert!(limits.allows(e2e4));
+        assert!(!limits.allows(board.find_move("d2d4").unwrap()));
+        assert!(SearchLimits::default().allows(board.find_move("d2d4").unwrap()));
+
+        // Mate in two moves is three plies away
+        assert!(limits.mate_found(MATE - 3));
+        assert!(!limits.mate_found(MATE - 5));
+        assert!(!limits.mate_found(3 - MATE));
+        assert!(!SearchLimits::new().mate_found(MATE - 1));
+    }
+}
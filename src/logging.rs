@@ -0,0 +1,118 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File mirroring the UCI traffic, set with the `Log File` option
+static LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// Prints a line of UCI output, mirroring it to the log file
+#[macro_export]
+macro_rules! uci_println {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{line}");
+        $crate::logging::output(&line);
+    }};
+}
+
+/// Starts appending the traffic to `path`, or stops logging when it is empty or `<empty>`
+pub fn set_file(path: &str) -> Result<(), String> {
+    let file = match path {
+        "" | "<empty>" => None,
+        path => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("{path}: {e}"))?,
+        ),
+    };
+
+    *LOG.lock().unwrap_or_else(|e| e.into_inner()) = file;
+    Ok(())
+}
+
+/// Command received from the GUI
+pub fn input(line: &str) {
+    write(">>", line);
+}
+
+/// Line sent to the GUI
+pub fn output(line: &str) {
+    write("<<", line);
+}
+
+/// Reports a problem to the GUI as an `info string`, marking it as a warning in the log
+pub fn warn(message: &str) {
+    println!("info string {message}");
+    write("!!", message);
+}
+
+/// Reports an internal error on stderr, where GUIs usually keep it apart from the protocol
+pub fn error(message: &str) {
+    eprintln!("{message}");
+    write("!!", message);
+}
+
+/// Records panics in the log file besides printing them as usual
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        write("!!", &info.to_string().replace('\n', " "));
+        default_hook(info);
+    }));
+}
+
+fn write(direction: &str, line: &str) {
+    let mut log = LOG.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = log.as_mut() {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let _ = writeln!(
+            file,
+            "{}.{:03} {direction} {line}",
+            time.as_secs(),
+            time.subsec_millis()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file() {
+        let path = std::env::temp_dir().join(format!("oxide-log-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = std::fs::remove_file(path);
+
+        set_file(path).unwrap();
+        input("isready");
+        uci_println!("readyok");
+        warn("Unknown option Foo");
+        set_file("<empty>").unwrap();
+        output("not logged");
+
+        let text = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        // Searches of other tests may print info lines meanwhile
+        let entries: Vec<&str> = text
+            .lines()
+            .map(|line| {
+                let (time, entry) = line.split_once(' ').unwrap();
+                assert!(time.parse::<f64>().is_ok(), "{line}");
+                entry
+            })
+            .filter(|entry| !entry.starts_with("<< info"))
+            .collect();
+        assert_eq!(
+            entries,
+            [">> isready", "<< readyok", "!! Unknown option Foo"]
+        );
+
+        assert!(set_file("/nonexistent/dir/log.txt").is_err());
+    }
+}
@@ -1,27 +1,68 @@
-use uci::UCIEngine;
-
-mod bitboard;
-mod board;
-mod castle;
-mod constants;
-mod moves;
-mod network;
-mod perft;
-mod piece;
-mod search;
-mod square;
-mod tables;
-mod uci;
-mod zobrist;
+use oxide::uci::{UCIEngine, SMP_BENCH_DEPTH};
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
+
+    // Before the first search, so that a build for a newer CPU fails with a reason rather than
+    // an illegal instruction
+    let cpu = oxide::cpu::check().unwrap_or_else(|e| {
+        println!("info string {e}");
+        eprintln!("{e}");
+        std::process::exit(1);
+    });
+
     let mut engine = UCIEngine::new();
 
     if args.len() > 1 {
         match args[1].as_str() {
             "bench" => {
-                engine.bench();
+                match (args.get(2).map(String::as_str), args.get(3)) {
+                    (Some("smp"), Some(threads)) => match threads.parse() {
+                        Ok(threads) => {
+                            let depth = args.get(4).and_then(|d| d.parse().ok());
+                            engine.bench_smp(threads, depth.unwrap_or(SMP_BENCH_DEPTH));
+                        }
+                        Err(_) => {
+                            eprintln!("Usage: oxide bench smp <threads> [depth]");
+                            std::process::exit(1);
+                        }
+                    },
+                    _ => engine.bench(),
+                }
+                std::process::exit(0);
+            }
+            "sprt" => {
+                if let Err(e) = oxide::sprt::run(&args[2..]) {
+                    eprintln!("{e}");
+                    eprintln!("Usage: oxide sprt <wins> <losses> <draws> [elo0 elo1 alpha beta]");
+                    eprintln!("       oxide sprt penta <ll> <ld> <dd+wl> <wd> <ww> [elo0 elo1 alpha beta]");
+                    std::process::exit(1);
+                }
+                std::process::exit(0);
+            }
+            "datatool" => {
+                if let Err(e) = oxide::data::run(&args[2..]) {
+                    eprintln!("{e}");
+                    eprintln!("Usage: oxide datatool view <marlin|bullet> <file> [count]");
+                    eprintln!("       oxide datatool filter <marlin|bullet> <input.txt> <output> [min-ply N] [max-ply N] [max-score N] [no-noisy]");
+                    std::process::exit(1);
+                }
+                std::process::exit(0);
+            }
+            "analyze" => {
+                if let Err(e) = oxide::analyze::run(&args[2..]) {
+                    eprintln!("{e}");
+                    eprintln!("Usage: oxide analyze (<fen> | startpos | -) [--depth N] [--nodes N] [--movetime MS] [--json]");
+                    std::process::exit(1);
+                }
+                std::process::exit(0);
+            }
+            "serve" => {
+                if let Err(e) = oxide::serve::run(&args[2..]) {
+                    eprintln!("{e}");
+                    eprintln!("Usage: oxide serve [workers]");
+                    std::process::exit(1);
+                }
                 std::process::exit(0);
             }
             _ => {
@@ -31,5 +72,6 @@ fn main() {
         }
     }
 
+    oxide::uci_println!("info string {cpu}");
     engine.run();
 }
@@ -1,15 +1,19 @@
 use crate::{
-    search::{CAP_SCORE, KILL_SCORE, PROM_SCORE, TT_SCORE},
+    search::{
+        CAP_SCORE, COUNTER_SCORE, ESCAPE_SCORE, KILL_SCORE, PROM_SCORE, PV_SCORE, REPETITION_SCORE,
+        TT_SCORE,
+    },
     square::Square,
     tables::SearchData,
 };
+#[cfg(not(feature = "safe-only"))]
 use std::hint::unreachable_unchecked;
 
 use super::{
     bitboard::BitBoard,
     board::Board,
     constants::{CASTLE, KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS},
-    piece::{Colour, Piece},
+    piece::{Colour, Piece, PieceType},
 };
 
 /// A move needs 16 bits to be stored, the information is contained
@@ -27,7 +31,7 @@ use super::{
 ///    └─ Move type. Indicates whether it is a promotion, en passant,
 ///       capture or castling move.
 ///```
-#[derive(PartialEq, Eq, PartialOrd, Clone, Copy, Debug, Default, Hash, Ord)]
+#[derive(PartialEq, Eq, PartialOrd, Clone, Copy, Default, Hash, Ord)]
 pub struct Move(pub u16);
 
 const SRC: u16 = 0b0000_0000_0011_1111;
@@ -41,6 +45,11 @@ impl Move {
         Self((src.index() as u16) | ((dest.index() as u16) << 6) | ((kind as u16) << 12))
     }
 
+    /// Promotion of the pawn on `src` to `piece`, which must be a knight, bishop, rook or queen
+    pub fn new_promotion(src: Square, dest: Square, piece: PieceType, is_capture: bool) -> Self {
+        Self::new(src, dest, MoveKind::promotion(piece, is_capture))
+    }
+
     pub const fn get_source(self) -> Square {
         Square::new((self.0 & SRC) as u8)
     }
@@ -67,7 +76,10 @@ impl Move {
             0b1110 => MoveKind::RookCapPromo,
             0b1111 => MoveKind::QueenCapPromo,
 
+            #[cfg(not(feature = "safe-only"))]
             _ => unsafe { unreachable_unchecked() },
+            #[cfg(feature = "safe-only")]
+            _ => unreachable!("Invalid move kind {:#06b}", (self.0 >> 12) & 0b1111),
         }
     }
 }
@@ -90,6 +102,21 @@ impl std::fmt::Display for Move {
     }
 }
 
+/// Squares and kind of the move, with the raw type bits when they are not a valid kind (as in
+/// a corrupted hash entry)
+impl std::fmt::Debug for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = f.debug_struct("Move");
+        s.field("src", &format_args!("{}", self.get_source()))
+            .field("dest", &format_args!("{}", self.get_dest()));
+        match self.0 >> 12 {
+            kind @ (0b0011 | 0b1010 | 0b1011) => s.field("kind", &format_args!("{kind:#06b}")),
+            _ => s.field("kind", &self.get_type()),
+        };
+        s.finish()
+    }
+}
+
 /// MoveKind is a 4-bit enum that represents the type of move
 /// Structured as follows:
 ///
@@ -118,10 +145,28 @@ pub enum MoveKind {
 }
 
 impl MoveKind {
+    pub const fn promotion(piece: PieceType, is_capture: bool) -> Self {
+        match (piece, is_capture) {
+            (PieceType::Knight, false) => MoveKind::KnightPromotion,
+            (PieceType::Bishop, false) => MoveKind::BishopPromotion,
+            (PieceType::Rook, false) => MoveKind::RookPromotion,
+            (PieceType::Queen, false) => MoveKind::QueenPromotion,
+            (PieceType::Knight, true) => MoveKind::KnightCapPromo,
+            (PieceType::Bishop, true) => MoveKind::BishopCapPromo,
+            (PieceType::Rook, true) => MoveKind::RookCapPromo,
+            (PieceType::Queen, true) => MoveKind::QueenCapPromo,
+            _ => panic!("Pawns only promote to a knight, bishop, rook or queen"),
+        }
+    }
+
     pub const fn is_promotion(self) -> bool {
         self as usize & 0b0100 != 0
     }
 
+    pub const fn is_underpromotion(self) -> bool {
+        self.is_promotion() && self as usize & 0b0011 != 0b0011
+    }
+
     pub const fn is_capture(self) -> bool {
         self as usize & 0b1000 != 0
     }
@@ -135,10 +180,99 @@ impl MoveKind {
 
         PROMO_PIECES[side as usize][self as usize & PROMO_MASK]
     }
+
+    /// Piece type a promotion promotes to, `None` for other moves
+    pub const fn promotion_type(self) -> Option<PieceType> {
+        if self.is_promotion() {
+            Some(self.get_promotion(Colour::White).piece_type())
+        } else {
+            None
+        }
+    }
 }
 
+/// Order in which promotions are generated, queen first as it is almost always the best
+const PROMOTIONS: [PieceType; 4] = [
+    PieceType::Queen,
+    PieceType::Rook,
+    PieceType::Bishop,
+    PieceType::Knight,
+];
+
 /// Move Generation Logic
 impl Board {
+    /// Finds the pseudo-legal move written in UCI notation, explaining why there is none
+    /// otherwise. Whether it leaves the king in check is left to `is_legal`.
+    pub fn find_move(&self, move_str: &str) -> Result<Move, String> {
+        let square = |s: Option<&str>| {
+            s.filter(|s| matches!(s.as_bytes(), [b'a'..=b'h', b'1'..=b'8']))
+                .map(Square::from)
+        };
+        let (Some(src), Some(dest)) = (square(move_str.get(0..2)), square(move_str.get(2..4)))
+        else {
+            return Err(String::from("malformed move"));
+        };
+        let promotion = match move_str.get(4..) {
+            Some("") => None,
+            Some("q") => Some(PieceType::Queen),
+            Some("r") => Some(PieceType::Rook),
+            Some("b") => Some(PieceType::Bishop),
+            Some("n") => Some(PieceType::Knight),
+            _ => return Err(String::from("malformed promotion")),
+        };
+
+        let piece = self.piece_at(src);
+        if piece == Piece::Empty {
+            return Err(format!("no piece on {src}"));
+        }
+        if piece.colour() != self.side {
+            return Err(format!("{src} holds a piece of the side not to move"));
+        }
+
+        // Castles written as the king taking its own rook, as in Chess960
+        let takes_own_rook =
+            piece.is_king() && self.piece_at(dest) == Piece::new(PieceType::Rook, self.side);
+        self.generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .find(|&m| {
+                let dest_matches = if takes_own_rook {
+                    m.get_type() == MoveKind::Castle
+                        && self.castling_rights.castle_rook(m.get_dest()).0 == dest
+                } else {
+                    m.get_dest() == dest
+                };
+                m.get_source() == src && dest_matches && m.get_type().promotion_type() == promotion
+            })
+            .ok_or_else(|| format!("{} cannot move from {src} to {dest}", piece.to_char()))
+    }
+
+    /// UCI notation of `m`, writing castles as the king taking its rook in `chess960` mode
+    pub fn move_to_uci(&self, m: Move, chess960: bool) -> String {
+        if chess960 && m.get_type() == MoveKind::Castle {
+            let (rook, _) = self.castling_rights.castle_rook(m.get_dest());
+            format!("{}{rook}", m.get_source())
+        } else {
+            m.to_string()
+        }
+    }
+
+    /// UCI notation of the line of `moves` played from this position, each preceded by a space
+    /// as `MoveList` displays them
+    pub fn line_to_uci(&self, moves: &MoveList, chess960: bool) -> String {
+        if !chess960 {
+            return moves.to_string();
+        }
+
+        let mut board = *self;
+        let mut line = String::new();
+        for m in moves {
+            line.push(' ');
+            line.push_str(&board.move_to_uci(m, chess960));
+            board.make_move(m);
+        }
+        line
+    }
+
     pub fn all_slider_moves<const QUIET: bool, const CAP: bool>(
         &self,
         src: Square,
@@ -170,7 +304,7 @@ impl Board {
         occ: u64,
         moves: &mut MoveList,
     ) {
-        let king_bb = self.pieces[Piece::WK.index()] & self.sides[self.side as usize];
+        let king_bb = self.pieces[PieceType::King] & self.sides[self.side as usize];
         let src = king_bb.lsb();
         let attacks = KING_ATTACKS[src.index()];
 
@@ -202,7 +336,7 @@ impl Board {
         occ: BitBoard,
         moves: &mut MoveList,
     ) {
-        let mut knight_bb = self.pieces[Piece::WN.index()] & self.sides[self.side as usize];
+        let mut knight_bb = self.pieces[PieceType::Knight] & self.sides[self.side as usize];
         while knight_bb != BitBoard::EMPTY {
             let src = knight_bb.pop_lsb();
             let attacks = KNIGHT_ATTACKS[src.index()];
@@ -233,7 +367,7 @@ impl Board {
         let colour = self.side;
         let start_rank = BitBoard::START_RANKS[colour as usize];
         let promo_rank = BitBoard::START_RANKS[!colour as usize];
-        let pawns = self.pieces[Piece::WP as usize] & self.sides[colour as usize];
+        let pawns = self.pieces[PieceType::Pawn] & self.sides[colour as usize];
         let opps = self.sides[!colour as usize];
         let empty = !occ;
         let first = empty.shift(colour);
@@ -264,10 +398,9 @@ impl Board {
             while promo != BitBoard::EMPTY {
                 let src = promo.pop_lsb();
                 let dest = src.shift::<8>(colour);
-                moves.push(Move::new(src, dest, MoveKind::QueenPromotion));
-                moves.push(Move::new(src, dest, MoveKind::RookPromotion));
-                moves.push(Move::new(src, dest, MoveKind::BishopPromotion));
-                moves.push(Move::new(src, dest, MoveKind::KnightPromotion));
+                for piece in PROMOTIONS {
+                    moves.push(Move::new_promotion(src, dest, piece, false));
+                }
             }
 
             let mut attackers = pawns & !promo_rank;
@@ -287,10 +420,9 @@ impl Board {
                 let mut attacks = PAWN_ATTACKS[colour as usize][src.index()] & opps;
                 while attacks != BitBoard::EMPTY {
                     let dest = attacks.pop_lsb();
-                    moves.push(Move::new(src, dest, MoveKind::QueenCapPromo));
-                    moves.push(Move::new(src, dest, MoveKind::RookCapPromo));
-                    moves.push(Move::new(src, dest, MoveKind::BishopCapPromo));
-                    moves.push(Move::new(src, dest, MoveKind::KnightCapPromo));
+                    for piece in PROMOTIONS {
+                        moves.push(Move::new_promotion(src, dest, piece, true));
+                    }
                 }
             }
 
@@ -324,8 +456,9 @@ impl Default for MoveList {
 }
 
 impl MoveList {
-    // Pointer width 64
-    pub const SIZE: usize = 252;
+    /// Legal positions have at most 218 moves, the margin covers pseudo-legal ones (pinned
+    /// pieces, king steps into check). Pointer width 64 keeps the list at 520 bytes.
+    pub const SIZE: usize = 256;
 
     pub fn as_slice(&self) -> &[Move] {
         &self.moves[..self.len]
@@ -365,20 +498,58 @@ impl MovePicker {
             let see = board.see(*m, 0);
             self.scores[i] = CAP_SCORE * see as i32;
             if m.get_type().is_capture() {
-                self.scores[i] += data.cap_history.score[board.piece_at(m.get_source()) as usize]
-                    [m.get_dest().index()][board.capture_piece(*m).index()]
-                    as i32;
+                self.scores[i] += data.cap_history.get(board, *m) as i32;
+            }
+        }
+    }
+
+    /// Drops the quiet moves not yet picked, keeping captures, promotions and killers. Pruning
+    /// the quiets late in the list this way still lets the captures behind them be searched.
+    pub fn skip_quiets(&mut self) {
+        let mut len = 0;
+        for i in 0..self.moves.len {
+            let kind = self.moves.moves[i].get_type();
+            if kind.is_capture() || kind.is_promotion() || self.scores[i] >= KILL_SCORE {
+                self.moves.moves[len] = self.moves.moves[i];
+                self.scores[len] = self.scores[i];
+                len += 1;
+            }
+        }
+        self.moves.len = len;
+    }
+
+    /// Sends the moves into a position of the game history to the back, once `board` is on top
+    /// of the stack
+    pub fn demote_repetitions(&mut self, board: &Board, data: &SearchData) {
+        for (i, &m) in self.moves.as_slice().iter().enumerate() {
+            let mut after = *board;
+            after.make_move(m);
+            if data.repetitions(&after) > 0 {
+                self.scores[i] = REPETITION_SCORE;
             }
         }
     }
 
     pub fn score_moves(&mut self, board: &Board, tt_move: Option<Move>, data: &SearchData) {
+        let pv_move = data.last_pv_move();
+        let counter_move = data
+            .previous()
+            .map_or(Move::NULL, |prev| data.counter_moves.get(prev));
+        // Killers come from sibling subtrees: one is only tried early when it is among the moves
+        // here and does not lose material. The TT move keeps its own score.
+        let killer =
+            Some(data.ply_data[data.ply].killer).filter(|&k| k != Move::NULL && Some(k) != tt_move);
         for (i, m) in self.moves.as_slice().iter().enumerate() {
             if Some(*m) == tt_move {
                 self.scores[i] = TT_SCORE;
                 continue;
             }
 
+            if Some(*m) == pv_move {
+                self.scores[i] = PV_SCORE;
+                continue;
+            }
+
             let kind = m.get_type();
 
             if kind == MoveKind::QueenPromotion {
@@ -388,24 +559,32 @@ impl MovePicker {
 
             if kind.is_capture() {
                 let see = board.see(*m, 0);
-                self.scores[i] = CAP_SCORE * see as i32
-                    + data.cap_history.score[board.piece_at(m.get_source()) as usize]
-                        [m.get_dest().index()][board.capture_piece(*m).index()]
-                        as i32;
+                self.scores[i] = CAP_SCORE * see as i32 + data.cap_history.get(board, *m) as i32;
                 continue;
             }
 
-            if *m == data.ply_data[data.ply].killer {
+            if Some(*m) == killer && board.see(*m, 0) {
                 self.scores[i] = KILL_SCORE;
                 continue;
             }
 
-            self.scores[i] = data.history.score[board.side as usize][m.get_source().index()]
-                [m.get_dest().index()] as i32
+            if *m == counter_move {
+                self.scores[i] = COUNTER_SCORE;
+                continue;
+            }
+
+            self.scores[i] = data.history.get(board, *m) as i32;
+            if data.ply_data[data.ply].threat == Some(m.get_source()) {
+                self.scores[i] += ESCAPE_SCORE;
+            }
         }
     }
+}
 
-    pub fn next(&mut self) -> Option<(Move, i32)> {
+impl Iterator for MovePicker {
+    type Item = (Move, i32);
+
+    fn next(&mut self) -> Option<(Move, i32)> {
         if self.moves.len == 0 {
             return None;
         }
@@ -459,3 +638,172 @@ impl std::fmt::Display for MoveList {
         write!(f, "{moves}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_moves() {
+        // Positions with the most legal moves possible, and the first one with colours flipped
+        for fen in [
+            "R6R/3Q4/1Q4Q1/4Q3/2Q4Q/Q4Q2/pp1Q4/kBNN1KB1 w - - 0 1",
+            "3Q4/1Q4Q1/4Q3/2Q4R/Q4Q2/3Q4/1Q4Rp/1K1BBNNk w - - 0 1",
+            "Kbnn1kb1/PP1q4/q4q2/2q4q/4q3/1q4q1/3q4/r6r b - - 0 1",
+        ] {
+            let board = Board::from_fen(fen);
+            let moves = board.generate_pseudo_moves::<true, true>();
+            let legal = moves.into_iter().filter(|&m| board.is_legal(m)).count();
+            assert_eq!(legal, 218, "{fen}");
+            assert!(moves.as_slice().len() <= MoveList::SIZE);
+
+            // The picker returns every move once, best first
+            let mut picker = MovePicker::new::<true>(&board);
+            picker.score_moves(&board, None, &SearchData::new());
+            let picked: Vec<(Move, i32)> = picker.collect();
+            assert!(picked.windows(2).all(|w| w[0].1 >= w[1].1), "{fen}");
+            let mut picked: Vec<u16> = picked.iter().map(|(m, _)| m.0).collect();
+            let mut generated: Vec<u16> = moves.into_iter().map(|m| m.0).collect();
+            picked.sort_unstable();
+            generated.sort_unstable();
+            assert_eq!(picked, generated, "{fen}");
+        }
+
+        // Every pawn promoting four ways, pushing and capturing
+        let board = Board::from_fen("1n1n1n1n/P1P1P1P1/8/8/Q7/8/3Q4/K5k1 w - - 0 1");
+        let moves = board.generate_pseudo_moves::<true, true>();
+        let promotions = moves.into_iter().filter(|m| m.get_type().is_promotion());
+        assert_eq!(promotions.count(), 44);
+    }
+
+    #[test]
+    fn test_find_move() {
+        let board = Board::from_fen("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1");
+        let (a7, a8, b8) = (Square::from("a7"), Square::from("a8"), Square::from("b8"));
+        for (uci, piece, capture) in [
+            ("a7a8q", PieceType::Queen, false),
+            ("a7a8n", PieceType::Knight, false),
+            ("a7b8r", PieceType::Rook, true),
+            ("a7b8b", PieceType::Bishop, true),
+        ] {
+            let dest = if capture { b8 } else { a8 };
+            let expected = Move::new_promotion(a7, dest, piece, capture);
+            assert_eq!(board.find_move(uci), Ok(expected));
+            assert_eq!(expected.to_string(), uci);
+            assert_eq!(expected.get_type().promotion_type(), Some(piece));
+            assert_eq!(expected.get_type().is_capture(), capture);
+        }
+
+        assert_eq!(
+            board.find_move("e1e2").unwrap().get_type().promotion_type(),
+            None
+        );
+        assert!(board.find_move("a7a8").unwrap_err().contains("cannot move"));
+        assert!(board
+            .find_move("e1e2q")
+            .unwrap_err()
+            .contains("cannot move"));
+        assert_eq!(
+            board.find_move("a7a8k"),
+            Err(String::from("malformed promotion"))
+        );
+        assert_eq!(board.find_move("a7i8"), Err(String::from("malformed move")));
+        assert_eq!(board.find_move("a1a2"), Err(String::from("no piece on a1")));
+        assert!(board
+            .find_move("e8e7")
+            .unwrap_err()
+            .contains("side not to move"));
+    }
+
+    #[test]
+    fn test_chess960_notation() {
+        // Castles are read both ways and written as the king taking its rook in Chess960 mode
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        let short = board.find_move("e1g1").unwrap();
+        let long = board.find_move("e1c1").unwrap();
+        assert_eq!(short.get_type(), MoveKind::Castle);
+        assert_eq!(board.find_move("e1h1"), Ok(short));
+        assert_eq!(board.find_move("e1a1"), Ok(long));
+        assert_eq!(board.move_to_uci(short, false), "e1g1");
+        assert_eq!(board.move_to_uci(short, true), "e1h1");
+        assert_eq!(board.move_to_uci(long, true), "e1a1");
+
+        // A king castling onto its rook's square, and the queenside rook on b1
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/1R2K1R1 w GBkq - 0 1");
+        let short = board.find_move("e1g1").unwrap();
+        assert_eq!(short.get_type(), MoveKind::Castle);
+        let long = board.find_move("e1b1").unwrap();
+        assert_eq!(
+            (long.get_type(), long.get_dest()),
+            (MoveKind::Castle, Square::from("c1"))
+        );
+        assert_eq!(board.move_to_uci(long, true), "e1b1");
+
+        // Lines are written move by move from the position they start at
+        let mut line = MoveList::default();
+        line.push(long);
+        let mut after = board;
+        after.make_move(long);
+        line.push(after.find_move("h8h1").unwrap());
+        assert_eq!(board.line_to_uci(&line, true), " e1b1 h8h1");
+        assert_eq!(board.line_to_uci(&line, false), " e1c1 h8h1");
+        assert!(board
+            .find_move("e1d1")
+            .is_ok_and(|m| m.get_type() == MoveKind::Quiet));
+    }
+
+    #[test]
+    fn test_skip_quiets() {
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let mut data = SearchData::new();
+        let killer = board.find_move("a2a3").unwrap();
+        data.ply_data[0].killer = killer;
+
+        let mut picker = MovePicker::new::<true>(&board);
+        picker.score_moves(&board, None, &data);
+        let first = picker.next().unwrap().0;
+        picker.skip_quiets();
+        let rest: Vec<Move> = picker.map(|(m, _)| m).collect();
+
+        // Every noisy move besides the one already picked, and the killer, are still yielded
+        let noisy = |m: &Move| m.get_type().is_capture() || m.get_type().is_promotion();
+        let expected = board
+            .generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .filter(|m| *m != first && (noisy(m) || *m == killer))
+            .count();
+        assert_eq!(rest.len(), expected);
+        assert!(rest.contains(&killer));
+        assert!(rest.iter().all(|m| noisy(m) || *m == killer));
+    }
+
+    #[test]
+    fn test_killer_validation() {
+        let board = Board::from_fen("4k3/8/8/4p3/8/8/4N3/4K3 w - - 0 1");
+        let mut data = SearchData::new();
+        let score_of = |killer: &str, tt_move: Option<Move>, data: &mut SearchData| {
+            let killer = Move::new(
+                Square::from(&killer[..2]),
+                Square::from(&killer[2..]),
+                MoveKind::Quiet,
+            );
+            data.ply_data[0].killer = killer;
+            let mut picker = MovePicker::new::<true>(&board);
+            picker.score_moves(&board, tt_move, data);
+            picker.find(|&(m, _)| m == killer).map(|(_, score)| score)
+        };
+
+        assert_eq!(score_of("e2c3", None, &mut data), Some(KILL_SCORE));
+        // Hanging the knight to the pawn, or a move of another position, get no bonus
+        assert!(score_of("e2f4", None, &mut data).is_some_and(|s| s < KILL_SCORE));
+        assert!(score_of("e2d4", None, &mut data).is_some_and(|s| s < KILL_SCORE));
+        assert_eq!(score_of("b1c3", None, &mut data), None);
+
+        let e2c3 = board.find_move("e2c3").unwrap();
+        assert_eq!(score_of("e2c3", Some(e2c3), &mut data), Some(TT_SCORE));
+        let mut picker = MovePicker::new::<true>(&board);
+        picker.score_moves(&board, Some(e2c3), &data);
+        assert_eq!(picker.filter(|&(_, s)| s >= KILL_SCORE).count(), 1);
+    }
+}
@@ -1,9 +1,17 @@
+use crate::board::Board;
+use crate::moves::{Move, MoveKind};
+use crate::piece::{Colour, Piece, PieceType};
+use crate::search::MAX_EVAL;
+use crate::square::Square;
+#[cfg(all(not(feature = "safe-only"), target_arch = "aarch64"))]
+use std::arch::aarch64::*;
+#[cfg(all(not(feature = "safe-only"), target_arch = "x86_64"))]
 use std::arch::x86_64::*;
 
 // Square: 0-63
 // Piece: Pawn = 0, Knight = 1, Bishop = 2, Rook = 3, Queen = 4, King = 5
 // Side: White = 0, Black = 1
-// Network (768x8 -> 1536)x2 -> 1
+// Network (768x8 -> 1536)x2 -> OUTPUT_BUCKETS x OUTPUT_HEADS
 const INPUT_SIZE: usize = 768;
 pub const HL_SIZE: usize = 1536;
 
@@ -16,6 +24,9 @@ const QAB: i32 = QA * QB;
 const SCALE: i32 = 400;
 pub const NUM_BUCKETS: usize = 8;
 
+/// King bucket of every square, a1 first. Files a-d hold the `NUM_BUCKETS` input buckets, files
+/// e-h mirror them with `NUM_BUCKETS` added: features are mirrored as well with the king there,
+/// so both halves share the weights but each gets its own refresh table entries.
 #[rustfmt::skip]
 pub static BUCKETS: [usize; 64] = [
     0, 1, 2, 3, 11, 10, 9, 8,
@@ -28,23 +39,142 @@ pub static BUCKETS: [usize; 64] = [
     7, 7, 7, 7, 15, 15, 15, 15,
 ];
 
+const _: () = assert!(buckets_are_mirrored(&BUCKETS), "Invalid king bucket layout");
+
+/// Whether every square of files a-d has an input bucket below `NUM_BUCKETS` and its mirror on
+/// files e-h the same bucket plus `NUM_BUCKETS`
+const fn buckets_are_mirrored(buckets: &[usize; 64]) -> bool {
+    let mut sq = 0;
+    while sq < 64 {
+        if sq % 8 < 4
+            && (buckets[sq] >= NUM_BUCKETS || buckets[sq ^ 7] != buckets[sq] + NUM_BUCKETS)
+        {
+            return false;
+        }
+        sq += 1;
+    }
+    true
+}
+
+const NET: &[u8] = include_bytes!("../resources/oxide-v5.bin");
+
+/// Nets may start with a header of `HEADER_SIZE` bytes, keeping the weights after it aligned:
+/// `OXNN` followed by the kind of output, 0 for a single value and 1 for win/draw/loss logits.
+/// Nets without it have a single value output.
+const HEADER_MAGIC: &[u8; 4] = b"OXNN";
+const HEADER_SIZE: usize = 64;
+const HEADER: usize = if has_header(NET) { HEADER_SIZE } else { 0 };
+const NET_SIZE: usize = NET.len() - HEADER;
+
+/// Outputs of each bucket of the embedded net, 1 for a value net and 3 for a WDL one
+pub const OUTPUT_HEADS: usize = match output_heads(NET) {
+    Some(heads) => heads,
+    None => panic!("Unknown network output kind"),
+};
+
+/// Output buckets of the embedded net, detected from its size so any number of material
+/// buckets can be dropped in
+pub const OUTPUT_BUCKETS: usize = match detect_output_buckets(NET_SIZE, OUTPUT_HEADS) {
+    Some(buckets) => buckets,
+    None => panic!("Unknown network architecture"),
+};
+
 pub static NNUE: Network =
-    unsafe { std::mem::transmute(*include_bytes!("../resources/oxide-v5.bin")) };
+    unsafe { std::mem::transmute(*NET.as_ptr().add(HEADER).cast::<[u8; NET_SIZE]>()) };
 
+/// The output weights of each bucket hold, for each head (win, draw and loss in WDL nets), the
+/// weights of the side to move and then those of the opponent
 #[repr(C)]
 pub struct Network {
     pub feature_weights: [Accumulator; INPUT_SIZE * NUM_BUCKETS],
     pub feature_bias: Accumulator,
-    output_weights: [Accumulator; 2],
-    output_bias: i16,
+    output_weights: [[[Accumulator; 2]; OUTPUT_HEADS]; OUTPUT_BUCKETS],
+    output_bias: [[i16; OUTPUT_HEADS]; OUTPUT_BUCKETS],
+}
+
+const fn has_header(net: &[u8]) -> bool {
+    net.len() >= HEADER_SIZE
+        && net[0] == HEADER_MAGIC[0]
+        && net[1] == HEADER_MAGIC[1]
+        && net[2] == HEADER_MAGIC[2]
+        && net[3] == HEADER_MAGIC[3]
+}
+
+/// Outputs per bucket of `net` as given by its header, None for an unknown kind of output
+const fn output_heads(net: &[u8]) -> Option<usize> {
+    if !has_header(net) {
+        return Some(1);
+    }
+    match net[4] {
+        0 => Some(1),
+        1 => Some(3),
+        _ => None,
+    }
+}
+
+/// Size of a net with `buckets` output buckets of `heads` outputs: weights and biases padded to
+/// the alignment of the accumulators
+const fn net_size(buckets: usize, heads: usize) -> usize {
+    let align = std::mem::align_of::<Accumulator>();
+    let acc = std::mem::size_of::<Accumulator>();
+    let outputs = buckets * heads;
+    let size = acc * (INPUT_SIZE * NUM_BUCKETS + 1 + 2 * outputs) + 2 * outputs;
+    size.div_ceil(align) * align
+}
+
+/// Output buckets of a net of `size` bytes, None when it does not match the layout of this
+/// build, such as a net trained with a different number of king buckets
+const fn detect_output_buckets(size: usize, heads: usize) -> Option<usize> {
+    let mut buckets = 1;
+    while buckets <= 16 {
+        if net_size(buckets, heads) == size {
+            return Some(buckets);
+        }
+        buckets += 1;
+    }
+    None
+}
+
+/// Centipawns of win, draw and loss logits, with `QAB` the unit: those whose expected score a
+/// value net, trained against `sigmoid(cp / SCALE)`, would give
+fn wdl_to_cp(logits: &[i32]) -> i32 {
+    let max = logits[0].max(logits[1]).max(logits[2]);
+    let [win, draw, loss] =
+        [0, 1, 2].map(|i| (logits[i].saturating_sub(max) as f32 / QAB as f32).exp());
+    let score = ((win + draw / 2.0) / (win + draw + loss)).clamp(1e-6, 1.0 - 1e-6);
+    (SCALE as f32 * (score / (1.0 - score)).ln()).round() as i32
+}
+
+/// Centipawns of the output of a value net, saturating at `MAX_EVAL` should it spike
+fn value_to_cp(logit: i32) -> i32 {
+    let cp = i64::from(logit) * i64::from(SCALE) / i64::from(QAB);
+    cp.clamp(-i64::from(MAX_EVAL), i64::from(MAX_EVAL)) as i32
+}
+
+/// Output bucket of a position with `pieces` pieces on the board, kings included
+pub const fn material_bucket(pieces: usize, buckets: usize) -> usize {
+    let divisor = 32usize.div_ceil(buckets);
+    let bucket = pieces.saturating_sub(2) / divisor;
+    if bucket < buckets {
+        bucket
+    } else {
+        buckets - 1
+    }
 }
 
 impl Network {
-    pub fn out(boys: &Accumulator, opps: &Accumulator) -> i32 {
-        let weights = &NNUE.output_weights;
-        unsafe {
-            let sum = flatten(boys, &weights[0]) + flatten(opps, &weights[1]);
-            (sum / QA + i32::from(NNUE.output_bias)) * SCALE / QAB
+    pub fn out(board: &Board, boys: &Accumulator, opps: &Accumulator) -> i32 {
+        let bucket = material_bucket(board.material_hash.piece_count(), OUTPUT_BUCKETS);
+        let logits: [i32; OUTPUT_HEADS] = std::array::from_fn(|head| {
+            let weights = &NNUE.output_weights[bucket][head];
+            let sum = flatten(boys, &weights[0]).saturating_add(flatten(opps, &weights[1]));
+            (sum / QA).saturating_add(i32::from(NNUE.output_bias[bucket][head]))
+        });
+
+        if OUTPUT_HEADS == 1 {
+            value_to_cp(logits[0])
+        } else {
+            wdl_to_cp(&logits)
         }
     }
 
@@ -54,6 +184,23 @@ impl Network {
         BUCKETS[if SIDE == 1 { king_sq ^ 0b111000 } else { king_sq }]
     }
 
+    /// Input index of a piece on a square for the white and black perspectives
+    pub fn feature(
+        piece: Piece,
+        sq: usize,
+        white_king_sq: usize,
+        black_king_sq: usize,
+    ) -> [u16; 2] {
+        let (side, pc) = (piece.colour() as usize, piece.piece_type().index());
+        let wflip = if white_king_sq % 8 > 3 { 7 } else { 0 };
+        let bflip = if black_king_sq % 8 > 3 { 7 } else { 0 } ^ 56;
+
+        [
+            (Self::get_base_index::<0>(side, pc, white_king_sq) + (sq ^ wflip)) as u16,
+            (Self::get_base_index::<1>(side, pc, black_king_sq) + (sq ^ bflip)) as u16,
+        ]
+    }
+
     pub const fn get_base_index<const SIDE: usize>(
         side: usize,
         pc: usize,
@@ -78,80 +225,312 @@ pub struct Accumulator {
 }
 
 impl Accumulator {
-    #[cfg(not(target_feature = "avx512f"))]
+    #[cfg(all(
+        not(feature = "safe-only"),
+        target_arch = "x86_64",
+        not(target_feature = "avx512f")
+    ))]
     #[inline]
     pub fn update_multi(&mut self, adds: &[u16], subs: &[u16]) {
+        #[cfg(not(target_feature = "avx2"))]
+        if !crate::cpu::has_avx2() {
+            let prev = *self;
+            return self.apply(&prev, adds, subs);
+        }
+
+        // Safety: AVX2 is either enabled by the build or was just detected
+        unsafe { self.update_multi_avx2(adds, subs) }
+    }
+
+    /// Compiled with AVX2 enabled so that the intrinsics are inlined into the loop
+    #[cfg(all(
+        not(feature = "safe-only"),
+        target_arch = "x86_64",
+        not(target_feature = "avx512f")
+    ))]
+    #[target_feature(enable = "avx2")]
+    unsafe fn update_multi_avx2(&mut self, adds: &[u16], subs: &[u16]) {
         const REGS: usize = 8;
         const PER: usize = 128;
         const ITERATIONS: usize = HL_SIZE / PER;
 
+        for i in 0..ITERATIONS {
+            let offset = i * PER;
+            let mut regs = [_mm256_setzero_si256(); REGS];
+
+            for (j, reg) in regs.iter_mut().enumerate() {
+                *reg = _mm256_load_si256(self.vals.as_ptr().add(offset + j * 16).cast());
+            }
+
+            for &add in adds {
+                let weights = NNUE.feature_weights[add as usize].vals.as_ptr().add(offset);
+                for (j, reg) in regs.iter_mut().enumerate() {
+                    let w = _mm256_load_si256(weights.add(j * 16).cast());
+                    *reg = _mm256_add_epi16(*reg, w);
+                }
+            }
+
+            for &sub in subs {
+                let weights = NNUE.feature_weights[sub as usize].vals.as_ptr().add(offset);
+                for (j, reg) in regs.iter_mut().enumerate() {
+                    let w = _mm256_load_si256(weights.add(j * 16).cast());
+                    *reg = _mm256_sub_epi16(*reg, w);
+                }
+            }
+
+            for (j, reg) in regs.iter().enumerate() {
+                _mm256_store_si256(self.vals.as_mut_ptr().add(offset + j * 16).cast(), *reg);
+            }
+        }
+    }
+
+    #[cfg(all(
+        not(feature = "safe-only"),
+        target_arch = "x86_64",
+        target_feature = "avx512f"
+    ))]
+    #[inline]
+    pub fn update_multi(&mut self, adds: &[u16], subs: &[u16]) {
+        const REGS: usize = 8;
+        const PER: usize = 256;
+        const ITERATIONS: usize = HL_SIZE / PER;
+
         unsafe {
             for i in 0..ITERATIONS {
                 let offset = i * PER;
-                let mut regs = [_mm256_setzero_si256(); REGS];
+                let mut regs = [_mm512_setzero_si512(); REGS];
 
                 for (j, reg) in regs.iter_mut().enumerate() {
-                    *reg = _mm256_load_si256(self.vals.as_ptr().add(offset + j * 16).cast());
+                    *reg = _mm512_load_si512(self.vals.as_ptr().add(offset + j * 32).cast());
                 }
 
                 for &add in adds {
                     let weights = NNUE.feature_weights[add as usize].vals.as_ptr().add(offset);
                     for (j, reg) in regs.iter_mut().enumerate() {
-                        let w = _mm256_load_si256(weights.add(j * 16).cast());
-                        *reg = _mm256_add_epi16(*reg, w);
+                        let w = _mm512_load_si512(weights.add(j * 32).cast());
+                        *reg = _mm512_add_epi16(*reg, w);
                     }
                 }
 
                 for &sub in subs {
                     let weights = NNUE.feature_weights[sub as usize].vals.as_ptr().add(offset);
                     for (j, reg) in regs.iter_mut().enumerate() {
-                        let w = _mm256_load_si256(weights.add(j * 16).cast());
-                        *reg = _mm256_sub_epi16(*reg, w);
+                        let w = _mm512_load_si512(weights.add(j * 32).cast());
+                        *reg = _mm512_sub_epi16(*reg, w);
                     }
                 }
 
                 for (j, reg) in regs.iter().enumerate() {
-                    _mm256_store_si256(self.vals.as_mut_ptr().add(offset + j * 16).cast(), *reg);
+                    _mm512_store_si512(self.vals.as_mut_ptr().add(offset + j * 32).cast(), *reg);
                 }
             }
         }
     }
 
-    #[cfg(target_feature = "avx512f")]
+    #[cfg(all(not(feature = "safe-only"), target_arch = "aarch64"))]
     #[inline]
     pub fn update_multi(&mut self, adds: &[u16], subs: &[u16]) {
-        const REGS: usize = 8;
-        const PER: usize = 256;
+        const REGS: usize = 16;
+        const PER: usize = 128;
         const ITERATIONS: usize = HL_SIZE / PER;
 
         unsafe {
             for i in 0..ITERATIONS {
                 let offset = i * PER;
-                let mut regs = [_mm512_setzero_si512(); REGS];
+                let mut regs = [vdupq_n_s16(0); REGS];
 
                 for (j, reg) in regs.iter_mut().enumerate() {
-                    *reg = _mm512_load_si512(self.vals.as_ptr().add(offset + j * 32).cast());
+                    *reg = vld1q_s16(self.vals.as_ptr().add(offset + j * 8));
                 }
 
                 for &add in adds {
                     let weights = NNUE.feature_weights[add as usize].vals.as_ptr().add(offset);
                     for (j, reg) in regs.iter_mut().enumerate() {
-                        let w = _mm512_load_si512(weights.add(j * 32).cast());
-                        *reg = _mm512_add_epi16(*reg, w);
+                        *reg = vaddq_s16(*reg, vld1q_s16(weights.add(j * 8)));
                     }
                 }
 
                 for &sub in subs {
                     let weights = NNUE.feature_weights[sub as usize].vals.as_ptr().add(offset);
                     for (j, reg) in regs.iter_mut().enumerate() {
-                        let w = _mm512_load_si512(weights.add(j * 32).cast());
-                        *reg = _mm512_sub_epi16(*reg, w);
+                        *reg = vsubq_s16(*reg, vld1q_s16(weights.add(j * 8)));
                     }
                 }
 
                 for (j, reg) in regs.iter().enumerate() {
-                    _mm512_store_si512(self.vals.as_mut_ptr().add(offset + j * 32).cast(), *reg);
+                    vst1q_s16(self.vals.as_mut_ptr().add(offset + j * 8), *reg);
+                }
+            }
+        }
+    }
+}
+
+impl Accumulator {
+    /// Quiet moves and promotions
+    #[inline]
+    pub fn add1sub1(&mut self, prev: &Accumulator, add: u16, sub: u16) {
+        self.update_from(prev, [add], [sub]);
+    }
+
+    /// Captures, en passant and capturing promotions
+    #[inline]
+    pub fn add1sub2(&mut self, prev: &Accumulator, add: u16, sub1: u16, sub2: u16) {
+        self.update_from(prev, [add], [sub1, sub2]);
+    }
+
+    /// Castling
+    #[inline]
+    pub fn add2sub2(&mut self, prev: &Accumulator, adds: [u16; 2], subs: [u16; 2]) {
+        self.update_from(prev, adds, subs);
+    }
+
+    /// Writes `prev` with the features applied in a single pass, without copying it first
+    #[cfg(all(
+        not(feature = "safe-only"),
+        target_arch = "x86_64",
+        not(target_feature = "avx512f")
+    ))]
+    #[inline]
+    fn update_from<const ADDS: usize, const SUBS: usize>(
+        &mut self,
+        prev: &Accumulator,
+        adds: [u16; ADDS],
+        subs: [u16; SUBS],
+    ) {
+        #[cfg(not(target_feature = "avx2"))]
+        if !crate::cpu::has_avx2() {
+            return self.apply(prev, &adds, &subs);
+        }
+
+        // Safety: AVX2 is either enabled by the build or was just detected
+        unsafe { self.update_from_avx2(prev, adds, subs) }
+    }
+
+    #[cfg(all(
+        not(feature = "safe-only"),
+        target_arch = "x86_64",
+        not(target_feature = "avx512f")
+    ))]
+    #[target_feature(enable = "avx2")]
+    unsafe fn update_from_avx2<const ADDS: usize, const SUBS: usize>(
+        &mut self,
+        prev: &Accumulator,
+        adds: [u16; ADDS],
+        subs: [u16; SUBS],
+    ) {
+        const CHUNK: usize = 16;
+
+        for i in (0..HL_SIZE).step_by(CHUNK) {
+            let mut v = _mm256_load_si256(prev.vals.as_ptr().add(i).cast());
+            for add in adds {
+                let w = NNUE.feature_weights[add as usize].vals.as_ptr().add(i);
+                v = _mm256_add_epi16(v, _mm256_load_si256(w.cast()));
+            }
+            for sub in subs {
+                let w = NNUE.feature_weights[sub as usize].vals.as_ptr().add(i);
+                v = _mm256_sub_epi16(v, _mm256_load_si256(w.cast()));
+            }
+            _mm256_store_si256(self.vals.as_mut_ptr().add(i).cast(), v);
+        }
+    }
+
+    #[cfg(all(
+        not(feature = "safe-only"),
+        target_arch = "x86_64",
+        target_feature = "avx512f"
+    ))]
+    #[inline]
+    fn update_from<const ADDS: usize, const SUBS: usize>(
+        &mut self,
+        prev: &Accumulator,
+        adds: [u16; ADDS],
+        subs: [u16; SUBS],
+    ) {
+        const CHUNK: usize = 32;
+
+        unsafe {
+            for i in (0..HL_SIZE).step_by(CHUNK) {
+                let mut v = _mm512_load_si512(prev.vals.as_ptr().add(i).cast());
+                for add in adds {
+                    let w = NNUE.feature_weights[add as usize].vals.as_ptr().add(i);
+                    v = _mm512_add_epi16(v, _mm512_load_si512(w.cast()));
+                }
+                for sub in subs {
+                    let w = NNUE.feature_weights[sub as usize].vals.as_ptr().add(i);
+                    v = _mm512_sub_epi16(v, _mm512_load_si512(w.cast()));
                 }
+                _mm512_store_si512(self.vals.as_mut_ptr().add(i).cast(), v);
+            }
+        }
+    }
+
+    #[cfg(all(not(feature = "safe-only"), target_arch = "aarch64"))]
+    #[inline]
+    fn update_from<const ADDS: usize, const SUBS: usize>(
+        &mut self,
+        prev: &Accumulator,
+        adds: [u16; ADDS],
+        subs: [u16; SUBS],
+    ) {
+        const CHUNK: usize = 8;
+
+        unsafe {
+            for i in (0..HL_SIZE).step_by(CHUNK) {
+                let mut v = vld1q_s16(prev.vals.as_ptr().add(i));
+                for add in adds {
+                    let w = NNUE.feature_weights[add as usize].vals.as_ptr().add(i);
+                    v = vaddq_s16(v, vld1q_s16(w));
+                }
+                for sub in subs {
+                    let w = NNUE.feature_weights[sub as usize].vals.as_ptr().add(i);
+                    v = vsubq_s16(v, vld1q_s16(w));
+                }
+                vst1q_s16(self.vals.as_mut_ptr().add(i), v);
+            }
+        }
+    }
+}
+
+#[cfg(any(
+    feature = "safe-only",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
+impl Accumulator {
+    pub fn update_multi(&mut self, adds: &[u16], subs: &[u16]) {
+        let prev = *self;
+        self.apply(&prev, adds, subs);
+    }
+
+    fn update_from<const ADDS: usize, const SUBS: usize>(
+        &mut self,
+        prev: &Accumulator,
+        adds: [u16; ADDS],
+        subs: [u16; SUBS],
+    ) {
+        self.apply(prev, &adds, &subs);
+    }
+}
+
+/// Scalar code, also run by builds without a `target-cpu` on x86 CPUs without AVX2
+#[cfg(any(
+    feature = "safe-only",
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    all(target_arch = "x86_64", not(target_feature = "avx2"))
+))]
+impl Accumulator {
+    /// Checked scalar update, wrapping like the SIMD additions
+    fn apply(&mut self, prev: &Accumulator, adds: &[u16], subs: &[u16]) {
+        self.vals = prev.vals;
+        for &add in adds {
+            let weights = &NNUE.feature_weights[add as usize].vals;
+            for (v, &w) in self.vals.iter_mut().zip(weights) {
+                *v = v.wrapping_add(w);
+            }
+        }
+        for &sub in subs {
+            let weights = &NNUE.feature_weights[sub as usize].vals;
+            for (v, &w) in self.vals.iter_mut().zip(weights) {
+                *v = v.wrapping_sub(w);
             }
         }
     }
@@ -163,34 +542,357 @@ impl Default for Accumulator {
     }
 }
 
+/// Accumulator of one perspective for one king bucket, with the position it was computed for
 #[derive(Clone, Copy)]
 pub struct EvalEntry {
     pub bbs: [u64; 8], // Bitboards for pieces and sides
-    pub white: Accumulator,
-    pub black: Accumulator,
+    pub acc: Accumulator,
 }
 
+impl Default for EvalEntry {
+    fn default() -> Self {
+        Self {
+            bbs: [0; 8],
+            acc: NNUE.feature_bias,
+        }
+    }
+}
+
+/// How evaluations were served by the cache: unchanged positions, incremental updates from the
+/// cached position and refreshes from an empty accumulator
+#[derive(Clone, Copy, Default)]
+pub struct CacheStats {
+    pub probes: u64,
+    pub hits: u64,
+    pub partial: u64,
+    pub refreshes: u64,
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pct = |n: u64| (100 * n).checked_div(self.probes).unwrap_or(0);
+        write!(
+            f,
+            "eval cache probes {} hits {}% partial {}% refresh {}%",
+            self.probes,
+            pct(self.hits),
+            pct(self.partial),
+            pct(self.refreshes)
+        )
+    }
+}
+
+/// Refresh table ("Finny table"): one cached accumulator per perspective and king bucket,
+/// mirrored buckets included. A king entering a bucket only costs the diff between the current
+/// position and the last one seen with the king in that bucket, never a rebuild from the bias.
+///
+/// Entries are allocated the first time their bucket is used, as most games only ever see a
+/// few of them, and are kept for the whole game.
 pub struct EvalTable {
-    pub table: Box<[[EvalEntry; 2 * NUM_BUCKETS]; 2 * NUM_BUCKETS]>,
+    pub table: [[Option<Box<EvalEntry>>; 2 * NUM_BUCKETS]; 2],
+    pub enabled: bool, // When disabled every evaluation is a full refresh, for diagnostics
+    pub stats: CacheStats,
+    pub capacity: usize, // Entries each perspective may allocate, more reuse an allocated one
 }
 
 impl Default for EvalTable {
     fn default() -> Self {
-        let bias = NNUE.feature_bias;
-        let entry = EvalEntry {
-            bbs: [0; 8],
-            white: bias,
-            black: bias,
+        Self {
+            table: Default::default(),
+            enabled: true,
+            stats: CacheStats::default(),
+            capacity: 2 * NUM_BUCKETS,
+        }
+    }
+}
+
+impl EvalTable {
+    /// Entry of the perspective `SIDE` for the king bucket `bucket`
+    #[inline]
+    pub fn entry<const SIDE: usize>(&mut self, bucket: usize) -> &mut EvalEntry {
+        let entries = &mut self.table[SIDE];
+        if entries[bucket].is_none() && entries.iter().flatten().count() >= self.capacity {
+            // Full: the entry of another bucket is taken over, starting again from empty
+            let mut entry = entries
+                .iter_mut()
+                .find_map(Option::take)
+                .unwrap_or_default();
+            *entry = EvalEntry::default();
+            entries[bucket] = Some(entry);
+        }
+        entries[bucket].get_or_insert_with(Box::default)
+    }
+
+    /// Limits the entries of each perspective, freeing those beyond the new limit
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity.max(1);
+        for entries in &mut self.table {
+            let mut kept = 0;
+            for entry in entries.iter_mut().filter(|e| e.is_some()) {
+                kept += 1;
+                if kept > self.capacity {
+                    *entry = None;
+                }
+            }
+        }
+    }
+
+    /// Number of entries allocated so far
+    pub fn allocated(&self) -> usize {
+        self.table.iter().flatten().flatten().count()
+    }
+
+    /// Empties the entries for a new game, keeping their memory
+    pub fn clear(&mut self) {
+        self.table
+            .iter_mut()
+            .flatten()
+            .flatten()
+            .for_each(|e| **e = EvalEntry::default());
+        self.stats = CacheStats::default();
+    }
+}
+
+/// Features added and removed by a move, as indices for the white and black perspectives
+#[derive(Clone, Copy, Default)]
+struct Delta {
+    adds: [[u16; 2]; 2],
+    subs: [[u16; 2]; 2],
+    num_adds: usize,
+    num_subs: usize,
+}
+
+impl Delta {
+    fn add(&mut self, feature: [u16; 2]) {
+        self.adds[0][self.num_adds] = feature[0];
+        self.adds[1][self.num_adds] = feature[1];
+        self.num_adds += 1;
+    }
+
+    fn sub(&mut self, feature: [u16; 2]) {
+        self.subs[0][self.num_subs] = feature[0];
+        self.subs[1][self.num_subs] = feature[1];
+        self.num_subs += 1;
+    }
+}
+
+/// Accumulators of the positions along the current search line, one per ply. Making a move
+/// only records its features and the accumulators are computed when a position is evaluated,
+/// from the closest computed ancestor with the fused update of each move in between. A king
+/// changing bucket only forces its own perspective through the refresh table.
+#[derive(Clone, Copy)]
+struct StackEntry {
+    key: u64,
+    parent: u64,
+    delta: Delta,
+    computed: [bool; 2],
+    refresh: [bool; 2], // The king of the perspective changed bucket
+    accs: [Accumulator; 2],
+}
+
+pub const ACC_STACK_SIZE: usize = 256;
+
+pub struct AccumulatorStack {
+    entries: Box<[StackEntry]>,
+}
+
+impl Default for AccumulatorStack {
+    fn default() -> Self {
+        Self::with_plies(ACC_STACK_SIZE)
+    }
+}
+
+impl AccumulatorStack {
+    /// Stack for the first `plies` plies of a line, deeper positions are evaluated through the
+    /// refresh table
+    pub fn with_plies(plies: usize) -> Self {
+        let entry = StackEntry {
+            key: 0,
+            parent: 0,
+            delta: Delta::default(),
+            computed: [false; 2],
+            refresh: [true; 2],
+            accs: [NNUE.feature_bias; 2],
         };
+
         Self {
-            table: Box::new([[entry; 2 * NUM_BUCKETS]; 2 * NUM_BUCKETS]),
+            entries: vec![entry; plies.max(1)].into_boxed_slice(),
+        }
+    }
+
+    /// Records `m`, played from `board`, as the move leading to the position at `ply`
+    pub fn push_move(&mut self, ply: usize, board: &Board, m: Move, key: u64) {
+        let Some(entry) = self.entries.get_mut(ply) else {
+            return;
+        };
+
+        let (src, dest) = (m.get_source(), m.get_dest());
+        let piece = board.piece_at(src);
+        let side = piece.colour();
+        let wksq = board.king_square(Colour::White as usize).index();
+        let bksq = board.king_square(Colour::Black as usize).index();
+
+        entry.key = key;
+        entry.parent = board.hash.0;
+        entry.computed = [false; 2];
+        entry.refresh = [false; 2];
+        if piece.piece_type() == PieceType::King {
+            let (old, new) = (src.index(), dest.index());
+            entry.refresh[side as usize] = match side {
+                Colour::White => Network::get_bucket::<0>(old) != Network::get_bucket::<0>(new),
+                Colour::Black => Network::get_bucket::<1>(old) != Network::get_bucket::<1>(new),
+            };
+        }
+
+        // Indices of a refreshed perspective are wrong but never used
+        let mut delta = Delta::default();
+        let feature = |p: Piece, sq: Square| Network::feature(p, sq.index(), wksq, bksq);
+        let kind = m.get_type();
+        match kind {
+            MoveKind::Castle => {
+                let (rook_src, rook_dest) = board.castling_rights.castle_rook(dest);
+                let rook = board.piece_at(rook_src);
+                delta.add(feature(piece, dest));
+                delta.add(feature(rook, rook_dest));
+                delta.sub(feature(piece, src));
+                delta.sub(feature(rook, rook_src));
+            }
+            _ => {
+                let placed = if kind.is_promotion() {
+                    kind.get_promotion(side)
+                } else {
+                    piece
+                };
+                delta.add(feature(placed, dest));
+                delta.sub(feature(piece, src));
+
+                if kind == MoveKind::EnPassant {
+                    let captured = dest.shift::<8>(!side);
+                    delta.sub(feature(board.piece_at(captured), captured));
+                } else if kind.is_capture() {
+                    delta.sub(feature(board.piece_at(dest), dest));
+                }
+            }
+        }
+        entry.delta = delta;
+    }
+
+    /// Evaluation of `board`, the position at `ply` of the current line
+    pub fn evaluate(&mut self, board: &Board, ply: usize, cache: &mut EvalTable) -> i32 {
+        if ply >= self.entries.len() || !cache.enabled {
+            return board.evaluate(cache);
+        }
+
+        // Reached without a recorded move, e.g. the root or after a null move
+        let entry = &mut self.entries[ply];
+        if entry.key != board.hash.0 {
+            entry.key = board.hash.0;
+            entry.computed = [false; 2];
+            entry.refresh = [true; 2];
+        }
+
+        self.update::<0>(board, ply, cache);
+        self.update::<1>(board, ply, cache);
+
+        let [white, black] = &self.entries[ply].accs;
+        let eval = match board.side {
+            Colour::White => Network::out(board, white, black),
+            Colour::Black => Network::out(board, black, white),
+        };
+
+        board.scale(board.blend(board.hybrid(eval)))
+    }
+
+    /// Computes the accumulator of `SIDE` at `ply` incrementally, or from the refresh table if
+    /// the line back to a computed position is broken
+    fn update<const SIDE: usize>(&mut self, board: &Board, ply: usize, cache: &mut EvalTable) {
+        let mut base = ply;
+        while !self.entries[base].computed[SIDE] {
+            let entry = &self.entries[base];
+            if entry.refresh[SIDE] || base == 0 || self.entries[base - 1].key != entry.parent {
+                let entry = &mut self.entries[ply];
+                entry.accs[SIDE] = *board.refresh_accumulator::<SIDE>(cache);
+                entry.computed[SIDE] = true;
+                return;
+            }
+            base -= 1;
+        }
+
+        for ply in base + 1..=ply {
+            let (done, rest) = self.entries.split_at_mut(ply);
+            let (prev, entry) = (&done[ply - 1].accs[SIDE], &mut rest[0]);
+            let Delta {
+                adds,
+                subs,
+                num_adds,
+                num_subs,
+            } = entry.delta;
+            let (adds, subs) = (adds[SIDE], subs[SIDE]);
+            let acc = &mut entry.accs[SIDE];
+            match (num_adds, num_subs) {
+                (1, 1) => acc.add1sub1(prev, adds[0], subs[0]),
+                (1, 2) => acc.add1sub2(prev, adds[0], subs[0], subs[1]),
+                (2, 2) => acc.add2sub2(prev, adds, subs),
+                _ => unreachable!("No move changes these many features"),
+            }
+            entry.computed[SIDE] = true;
         }
     }
 }
 
-#[cfg(not(target_feature = "avx512vnni"))]
+// The aligned SIMD loads rely on this
+const _: () = assert!(std::mem::align_of::<Accumulator>() >= 64);
+
+#[cfg(all(
+    not(feature = "safe-only"),
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
 #[inline]
-pub unsafe fn flatten(acc: &Accumulator, weights: &Accumulator) -> i32 {
+fn flatten(acc: &Accumulator, weights: &Accumulator) -> i32 {
+    #[cfg(all(target_arch = "x86_64", not(target_feature = "avx2")))]
+    if !crate::cpu::has_avx2() {
+        return flatten_scalar(acc, weights);
+    }
+
+    // Safety: accumulators are aligned to 64 bytes and hold a multiple of the vector width, and
+    // x86 builds only get here with AVX2
+    unsafe { flatten_simd(acc, weights) }
+}
+
+#[cfg(any(
+    feature = "safe-only",
+    not(any(target_arch = "x86_64", target_arch = "aarch64"))
+))]
+#[inline]
+fn flatten(acc: &Accumulator, weights: &Accumulator) -> i32 {
+    flatten_scalar(acc, weights)
+}
+
+/// Checked scalar version with the same wrapping arithmetic as the SIMD one
+#[cfg(any(
+    feature = "safe-only",
+    not(any(target_arch = "x86_64", target_arch = "aarch64")),
+    all(target_arch = "x86_64", not(target_feature = "avx2"))
+))]
+fn flatten_scalar(acc: &Accumulator, weights: &Accumulator) -> i32 {
+    acc.vals
+        .iter()
+        .zip(weights.vals)
+        .map(|(&v, w)| {
+            let v = v.clamp(0, QA as i16);
+            i32::from(v) * i32::from(v.wrapping_mul(w))
+        })
+        .sum()
+}
+
+#[cfg(all(
+    not(feature = "safe-only"),
+    target_arch = "x86_64",
+    not(target_feature = "avx512vnni")
+))]
+#[inline]
+#[target_feature(enable = "avx2")]
+unsafe fn flatten_simd(acc: &Accumulator, weights: &Accumulator) -> i32 {
     const CHUNK: usize = 16;
     const NUM_ITERS: usize = HL_SIZE / CHUNK;
 
@@ -209,14 +911,26 @@ pub unsafe fn flatten(acc: &Accumulator, weights: &Accumulator) -> i32 {
     horizontal_sum_i32(sum)
 }
 
-#[cfg(not(target_feature = "avx512vnni"))]
+#[cfg(all(
+    not(feature = "safe-only"),
+    target_arch = "x86_64",
+    not(target_feature = "avx512vnni")
+))]
 #[inline]
+#[target_feature(enable = "avx2")]
 unsafe fn load_i16s(acc: &Accumulator, start_idx: usize) -> __m256i {
-    _mm256_load_si256(acc.vals.as_ptr().add(start_idx).cast())
+    let ptr = acc.vals.as_ptr().add(start_idx);
+    debug_assert_eq!(ptr as usize % 32, 0, "Unaligned accumulator load");
+    _mm256_load_si256(ptr.cast())
 }
 
-#[cfg(not(target_feature = "avx512vnni"))]
+#[cfg(all(
+    not(feature = "safe-only"),
+    target_arch = "x86_64",
+    not(target_feature = "avx512vnni")
+))]
 #[inline]
+#[target_feature(enable = "avx2")]
 unsafe fn horizontal_sum_i32(sum: __m256i) -> i32 {
     let upper_128 = _mm256_extracti128_si256::<1>(sum);
     let lower_128 = _mm256_castsi256_si128(sum);
@@ -229,9 +943,13 @@ unsafe fn horizontal_sum_i32(sum: __m256i) -> i32 {
     _mm_cvtsi128_si32(sum_32)
 }
 
-#[cfg(target_feature = "avx512vnni")]
+#[cfg(all(
+    not(feature = "safe-only"),
+    target_arch = "x86_64",
+    target_feature = "avx512vnni"
+))]
 #[inline]
-pub unsafe fn flatten(acc: &Accumulator, weights: &Accumulator) -> i32 {
+unsafe fn flatten_simd(acc: &Accumulator, weights: &Accumulator) -> i32 {
     const CHUNK: usize = 32;
     const UNROLL: usize = 4;
     const NUM_ITERS: usize = HL_SIZE / (CHUNK * UNROLL);
@@ -281,3 +999,248 @@ pub unsafe fn flatten(acc: &Accumulator, weights: &Accumulator) -> i32 {
 
     _mm512_reduce_add_epi32(final_sum)
 }
+
+#[cfg(all(not(feature = "safe-only"), target_arch = "aarch64"))]
+#[inline]
+unsafe fn flatten_simd(acc: &Accumulator, weights: &Accumulator) -> i32 {
+    const CHUNK: usize = 8;
+
+    // Two accumulators give each lane as many products as the x86 versions
+    let mut sum_low = vdupq_n_s32(0);
+    let mut sum_high = vdupq_n_s32(0);
+    let min = vdupq_n_s16(0);
+    let max = vdupq_n_s16(QA as i16);
+
+    for i in (0..HL_SIZE).step_by(CHUNK) {
+        let mut v = vld1q_s16(acc.vals.as_ptr().add(i));
+        v = vminq_s16(vmaxq_s16(v, min), max);
+        let w = vld1q_s16(weights.vals.as_ptr().add(i));
+        let product = vmulq_s16(v, w);
+        sum_low = vmlal_s16(sum_low, vget_low_s16(v), vget_low_s16(product));
+        sum_high = vmlal_high_s16(sum_high, v, product);
+    }
+
+    vaddvq_s32(vaddq_s32(sum_low, sum_high))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn test_output_buckets() {
+        assert_eq!((OUTPUT_BUCKETS, OUTPUT_HEADS), (1, 1));
+        assert_eq!(std::mem::size_of::<Network>(), NET_SIZE);
+        assert_eq!(detect_output_buckets(net_size(8, 1), 1), Some(8));
+        assert_eq!(detect_output_buckets(net_size(8, 3), 3), Some(8));
+
+        // Nets with another number of king buckets are rejected
+        let acc = std::mem::size_of::<Accumulator>();
+        for king_buckets in [4, 16] {
+            let size = acc * (INPUT_SIZE * king_buckets + 3) + 2;
+            let size = size.div_ceil(64) * 64;
+            assert_eq!(detect_output_buckets(size, 1), None);
+        }
+
+        let buckets: Vec<usize> = [2, 5, 6, 17, 31, 32]
+            .iter()
+            .map(|&pieces| material_bucket(pieces, 8))
+            .collect();
+        assert_eq!(buckets, [0, 0, 1, 3, 7, 7]);
+        assert_eq!(material_bucket(32, 1), 0);
+    }
+
+    #[test]
+    fn test_wdl_output() {
+        // Headerless nets and those marked as value nets have one output, WDL ones three
+        let mut header = [0; HEADER_SIZE];
+        header[..4].copy_from_slice(HEADER_MAGIC);
+        assert_eq!(output_heads(&header), Some(1));
+        header[4] = 1;
+        assert_eq!(output_heads(&header), Some(3));
+        header[4] = 2;
+        assert_eq!(output_heads(&header), None);
+        assert_eq!(output_heads(&[0; HEADER_SIZE]), Some(1));
+        assert!(!has_header(NET));
+
+        // Even odds are level, and a 75% expected score is SCALE * ln(3) centipawns
+        assert_eq!(wdl_to_cp(&[0, 5 * QAB, 0]), 0);
+        let ln3 = (3f32.ln() * QAB as f32) as i32;
+        let no_draw = -20 * QAB;
+        let cp = (SCALE as f32 * 3f32.ln()).round() as i32;
+        assert!((wdl_to_cp(&[ln3, no_draw, 0]) - cp).abs() <= 1);
+        assert_eq!(
+            wdl_to_cp(&[0, no_draw, ln3]),
+            -wdl_to_cp(&[ln3, no_draw, 0])
+        );
+        assert!(wdl_to_cp(&[100 * QAB, 0, 0]) < crate::search::MATE / 2);
+    }
+
+    #[test]
+    fn test_output_saturation() {
+        // Spiking outputs saturate below the mate scores instead of overflowing into them
+        assert_eq!(value_to_cp(i32::MAX), MAX_EVAL);
+        assert_eq!(value_to_cp(i32::MIN), -MAX_EVAL);
+        assert_eq!(value_to_cp(QAB), SCALE);
+
+        let mut rng = Rng::new(7);
+        for _ in 0..1000 {
+            let logits = [0; 3].map(|_| match rng.below(3) {
+                0 => i32::MIN,
+                1 => i32::MAX,
+                _ => rng.next_u64() as i32,
+            });
+            assert!(value_to_cp(logits[0]).abs() <= MAX_EVAL);
+            assert!(wdl_to_cp(&logits).abs() <= MAX_EVAL, "{logits:?}");
+        }
+
+        // A position with every accumulator value at the clipping bound
+        let mut acc = NNUE.feature_bias;
+        acc.vals.fill(QA as i16);
+        let board = Board::from_fen("qqqqkqqq/qqqqqqqq/8/8/8/8/QQQQQQQQ/QQQQKQQQ w - - 0 1");
+        assert!(Network::out(&board, &acc, &acc).abs() <= MAX_EVAL);
+    }
+
+    #[test]
+    fn test_king_buckets() {
+        assert!(buckets_are_mirrored(&BUCKETS));
+        let mut broken = BUCKETS;
+        broken[7] = 0;
+        assert!(!buckets_are_mirrored(&broken));
+        broken[7] = 2 * NUM_BUCKETS;
+        broken[0] = NUM_BUCKETS;
+        assert!(!buckets_are_mirrored(&broken));
+
+        // Kings on files e-h use the weights of the mirrored bucket
+        for (sq, bucket) in BUCKETS.iter().enumerate() {
+            let base = Network::get_base_index::<0>(0, 0, sq);
+            assert_eq!(base, INPUT_SIZE * (bucket % NUM_BUCKETS));
+        }
+    }
+
+    #[test]
+    fn test_simd_matches_scalar() {
+        // Values past the clipping bounds and products wrapping around as i16
+        let mut rng = Rng::new(11);
+        let mut random = || {
+            let mut acc = NNUE.feature_bias;
+            acc.vals
+                .iter_mut()
+                .for_each(|v| *v = rng.next_u64() as i16 % 600);
+            acc
+        };
+        let (acc, weights) = (random(), random());
+
+        let expected: i32 = acc
+            .vals
+            .iter()
+            .zip(weights.vals)
+            .map(|(&v, w)| {
+                let v = v.clamp(0, QA as i16);
+                i32::from(v) * i32::from(v.wrapping_mul(w))
+            })
+            .sum();
+        assert_eq!(flatten(&acc, &weights), expected);
+
+        let (adds, subs) = ([3, 900], [77, 5000]);
+        let mut expected = acc;
+        for (i, v) in expected.vals.iter_mut().enumerate() {
+            for add in adds {
+                *v = v.wrapping_add(NNUE.feature_weights[add as usize].vals[i]);
+            }
+            for sub in subs {
+                *v = v.wrapping_sub(NNUE.feature_weights[sub as usize].vals[i]);
+            }
+        }
+        let mut multi = acc;
+        multi.update_multi(&adds, &subs);
+        assert_eq!(multi.vals, expected.vals);
+        let mut single = random();
+        single.add2sub2(&acc, adds, subs);
+        assert_eq!(single.vals, expected.vals);
+    }
+
+    #[test]
+    fn test_refresh_table() {
+        // The king leaves its bucket and comes back, only new buckets are built from scratch
+        let mut cache = EvalTable::default();
+        assert_eq!(cache.allocated(), 0);
+        for (fen, refreshes) in [
+            ("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1", 2),
+            ("4k3/pppppppp/8/8/8/8/PPPPPPPP/3K4 b - - 1 1", 3),
+            ("4k3/pppppppp/8/8/8/4P3/PPPP1PPP/4K3 b - - 0 1", 3),
+        ] {
+            let board = Board::from_fen(fen);
+            let expected = board.evaluate(&mut EvalTable::default());
+            assert_eq!(board.evaluate(&mut cache), expected);
+            assert_eq!(cache.stats.refreshes, refreshes);
+            assert_eq!(cache.allocated(), refreshes as usize);
+        }
+        assert_eq!(cache.stats.partial, 3);
+
+        // A new game empties the entries but keeps them allocated
+        cache.clear();
+        let board = Board::default();
+        assert_eq!(board.evaluate(&mut cache), board.evaluate_fresh());
+        assert_eq!((cache.stats.refreshes, cache.allocated()), (2, 3));
+
+        // With one entry per perspective a king changing bucket takes over its side's entry
+        cache.set_capacity(1);
+        assert_eq!(cache.allocated(), 2);
+        for fen in [
+            "4k3/pppppppp/8/8/8/8/PPPPPPPP/3K4 b - - 1 1",
+            "4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1",
+        ] {
+            let board = Board::from_fen(fen);
+            assert_eq!(board.evaluate(&mut cache), board.evaluate_fresh());
+            assert_eq!(cache.allocated(), 2);
+        }
+    }
+
+    #[test]
+    fn test_incremental_updates() {
+        // Castling both ways, en passant and promotions with and without captures
+        let fens = [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ];
+        let mut rng = Rng::new(7);
+
+        for fen in fens {
+            for _ in 0..8 {
+                let mut stack = AccumulatorStack::default();
+                let mut cache = EvalTable::default();
+                let mut board = Board::from_fen(fen);
+                assert_eq!(
+                    stack.evaluate(&board, 0, &mut cache),
+                    board.evaluate(&mut cache)
+                );
+
+                for ply in 1..40 {
+                    let moves: Vec<Move> = board
+                        .generate_pseudo_moves::<true, true>()
+                        .into_iter()
+                        .filter(|&m| board.is_legal(m))
+                        .collect();
+                    if moves.is_empty() {
+                        break;
+                    }
+
+                    let m = moves[rng.below(moves.len())];
+                    let mut next = board;
+                    next.make_move(m);
+                    stack.push_move(ply, &board, m, next.hash.0);
+                    board = next;
+
+                    // Skip some plies so updates are also applied across several moves
+                    if rng.below(3) == 0 {
+                        let expected = board.evaluate(&mut EvalTable::default());
+                        assert_eq!(stack.evaluate(&board, ply, &mut cache), expected);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,6 +1,34 @@
 use crate::board::Board;
+use crate::moves::{Move, MoveKind};
+use crate::uci_println;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
 
+/// Leaves of a perft by kind of move, as in the tables of the Chess Programming Wiki: captures
+/// include en passant, checks include checkmates
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct PerftDetail {
+    pub nodes: usize,
+    pub captures: usize,
+    pub en_passant: usize,
+    pub castles: usize,
+    pub promotions: usize,
+    pub checks: usize,
+    pub checkmates: usize,
+}
+
+impl std::ops::AddAssign for PerftDetail {
+    fn add_assign(&mut self, other: Self) {
+        self.nodes += other.nodes;
+        self.captures += other.captures;
+        self.en_passant += other.en_passant;
+        self.castles += other.castles;
+        self.promotions += other.promotions;
+        self.checks += other.checks;
+        self.checkmates += other.checkmates;
+    }
+}
+
 impl Board {
     fn non_bulk_perft<const ROOT: bool>(&self, depth: usize) -> usize {
         if depth == 0 {
@@ -24,7 +52,7 @@ impl Board {
                 total += count;
 
                 if ROOT {
-                    println!("{m}: {count}")
+                    uci_println!("{m}: {count}")
                 }
             }
         }
@@ -32,12 +60,127 @@ impl Board {
         total
     }
 
+    /// Perft counting the leaves by kind of move, to tell which kind a move generation bug is in
+    pub fn perft_detail(&self, depth: usize) -> PerftDetail {
+        let mut detail = PerftDetail::default();
+        if depth == 0 {
+            detail.nodes = 1;
+            return detail;
+        }
+
+        for m in &self.generate_pseudo_moves::<true, true>() {
+            if !self.is_legal(m) {
+                continue;
+            }
+
+            let mut new = *self;
+            new.make_move(m);
+            if depth > 1 {
+                detail += new.perft_detail(depth - 1);
+                continue;
+            }
+
+            let kind = m.get_type();
+            detail.nodes += 1;
+            detail.captures += usize::from(kind.is_capture());
+            detail.en_passant += usize::from(kind == MoveKind::EnPassant);
+            detail.castles += usize::from(kind == MoveKind::Castle);
+            detail.promotions += usize::from(kind.is_promotion());
+            if new.in_check() {
+                detail.checks += 1;
+                detail.checkmates += usize::from(!new.has_legal_move());
+            }
+        }
+
+        detail
+    }
+
+    /// Prints the detailed perft of every depth up to `depth`
+    pub fn perft_detail_table(&self, depth: usize) {
+        uci_println!(
+            "{:>5} {:>12} {:>10} {:>8} {:>8} {:>10} {:>9} {:>10}",
+            "depth",
+            "nodes",
+            "captures",
+            "e.p.",
+            "castles",
+            "promotions",
+            "checks",
+            "checkmates"
+        );
+        for d in 1..=depth {
+            let p = self.perft_detail(d);
+            uci_println!(
+                "{d:>5} {:>12} {:>10} {:>8} {:>8} {:>10} {:>9} {:>10}",
+                p.nodes,
+                p.captures,
+                p.en_passant,
+                p.castles,
+                p.promotions,
+                p.checks,
+                p.checkmates
+            );
+        }
+    }
+
     pub fn perft(&self, depth: usize) -> usize {
         let start = Instant::now();
         let total_nodes = self.non_bulk_perft::<true>(depth);
         let duration = start.elapsed().as_millis() as usize;
         let perft = total_nodes / duration.max(1) / 1_000;
-        println!("\n{total_nodes} nodes in {duration:?} - {perft} Mn/s");
+        uci_println!("\n{total_nodes} nodes in {duration:?} - {perft} Mn/s");
+
+        total_nodes
+    }
+
+    /// Perft of each legal root move, in generation order, with the moves handed out to
+    /// `threads` threads which take the next one left as soon as they finish theirs
+    pub fn perft_root_moves(&self, depth: usize, threads: usize) -> Vec<(Move, usize)> {
+        let moves: Vec<Move> = self
+            .generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .filter(|&m| self.is_legal(m))
+            .collect();
+        let counts: Vec<AtomicUsize> = moves.iter().map(|_| AtomicUsize::new(0)).collect();
+        let next = AtomicUsize::new(0);
+
+        std::thread::scope(|s| {
+            for _ in 0..threads.clamp(1, moves.len().max(1)) {
+                s.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(&m) = moves.get(i) else { break };
+                    let mut new = *self;
+                    new.make_move(m);
+                    let count = new.non_bulk_perft::<false>(depth.saturating_sub(1));
+                    counts[i].store(count, Ordering::Relaxed);
+                });
+            }
+        });
+
+        moves
+            .into_iter()
+            .zip(counts.into_iter().map(AtomicUsize::into_inner))
+            .collect()
+    }
+
+    /// `perft` searching the root moves on `threads` threads
+    pub fn perft_threads(&self, depth: usize, threads: usize) -> usize {
+        let start = Instant::now();
+        let counts = self.perft_root_moves(depth, threads);
+        let total_nodes = if depth == 0 {
+            1
+        } else {
+            counts.iter().map(|&(_, count)| count).sum()
+        };
+        if depth > 1 {
+            for (m, count) in counts {
+                uci_println!("{m}: {count}");
+            }
+        }
+
+        let duration = start.elapsed().as_millis() as usize;
+        let perft = total_nodes / duration.max(1) / 1_000;
+        uci_println!("\n{total_nodes} nodes in {duration:?} - {perft} Mn/s");
 
         total_nodes
     }
@@ -47,6 +190,50 @@ impl Board {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_perft_detail() {
+        // Nodes, captures, e.p., castles, promotions, checks and checkmates of the CPW tables
+        let detail = |n, c, e, ca, p, ch, m| PerftDetail {
+            nodes: n,
+            captures: c,
+            en_passant: e,
+            castles: ca,
+            promotions: p,
+            checks: ch,
+            checkmates: m,
+        };
+        for (fen, depth, expected) in [
+            (
+                "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+                4,
+                detail(197281, 1576, 0, 0, 0, 469, 8),
+            ),
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                3,
+                detail(97862, 17102, 45, 3162, 0, 993, 1),
+            ),
+            (
+                "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+                3,
+                detail(2812, 209, 2, 0, 0, 267, 0),
+            ),
+        ] {
+            assert_eq!(Board::from_fen(fen).perft_detail(depth), expected, "{fen}");
+        }
+    }
+
+    #[test]
+    fn test_perft_threads() {
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let single = board.perft_root_moves(3, 1);
+        assert_eq!(single.len(), 48);
+        assert_eq!(single.iter().map(|m| m.1).sum::<usize>(), 97862);
+        assert_eq!(board.perft_root_moves(3, 5), single);
+        assert_eq!(board.perft_threads(2, 64), 2039);
+    }
+
     #[test]
     fn test_perft_suite() {
         #[rustfmt::skip]
@@ -7,7 +7,7 @@
 /// # Examples
 ///
 /// ```
-/// use crate::Piece;
+/// use oxide::piece::{Colour, Piece};
 /// let white_pawn = Piece::WP;
 /// assert_eq!(white_pawn.colour(), Colour::White);
 /// assert!(white_pawn.is_pawn());
@@ -96,9 +96,14 @@ impl Piece {
         )
     }
 
-    /// Returns the piece type index, ignoring color (e.g., both WP and BP return 0).
-    pub const fn index(self) -> usize {
-        self as usize / 2
+    /// Creates the piece of a given type and colour.
+    pub const fn new(piece_type: PieceType, colour: Colour) -> Self {
+        Self::from(2 * piece_type as usize + colour as usize)
+    }
+
+    /// Returns the type of the piece, ignoring its colour (e.g., both WP and BP are pawns).
+    pub const fn piece_type(self) -> PieceType {
+        PieceType::ALL[self as usize / 2]
     }
 
     pub fn to_char(self) -> char {
@@ -130,6 +135,33 @@ impl Piece {
     }
 }
 
+/// Type of a piece regardless of its colour, used to index the piece bitboards of the board
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum PieceType {
+    Pawn = 0,
+    Knight = 1,
+    Bishop = 2,
+    Rook = 3,
+    Queen = 4,
+    King = 5,
+}
+
+impl PieceType {
+    pub const ALL: [Self; 6] = [
+        PieceType::Pawn,
+        PieceType::Knight,
+        PieceType::Bishop,
+        PieceType::Rook,
+        PieceType::Queen,
+        PieceType::King,
+    ];
+
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+}
+
 /// Represents both possible piece colours black and white in chess
 #[repr(u8)]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Debug, Hash)]
@@ -146,18 +178,34 @@ impl Colour {
         Self::FORWARD[self as usize]
     }
 
+    #[cfg(not(feature = "safe-only"))]
     #[inline]
     pub const fn from_u8(value: u8) -> Self {
         // Safety: Value in [0,1]
         unsafe { std::mem::transmute(value & 1) }
     }
+
+    #[cfg(feature = "safe-only")]
+    #[inline]
+    pub const fn from_u8(value: u8) -> Self {
+        match value & 1 {
+            0 => Colour::White,
+            _ => Colour::Black,
+        }
+    }
 }
 
 impl std::ops::Not for Colour {
     type Output = Colour;
 
+    #[cfg(not(feature = "safe-only"))]
     fn not(self) -> Self {
         // Colour is either 0 or 1
         unsafe { std::mem::transmute(self as u8 ^ 1) }
     }
+
+    #[cfg(feature = "safe-only")]
+    fn not(self) -> Self {
+        Colour::from_u8(self as u8 ^ 1)
+    }
 }
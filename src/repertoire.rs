@@ -0,0 +1,170 @@
+use crate::board::Board;
+use crate::moves::Move;
+use std::collections::BTreeMap;
+
+/// Move chosen in a position of the repertoire, with how many times it was chosen and the
+/// score of the last search that chose it
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RepertoireMove {
+    pub mv: Move,
+    pub count: u32,
+    pub score: i32,
+}
+
+/// Moves chosen by the engine during analysis, keyed by the Zobrist hash of the position and
+/// kept on disk as one `<hash> <move> <count> <score>` line per move, with the hash and the
+/// raw move in hex
+pub struct Repertoire {
+    path: String,
+    positions: BTreeMap<u64, Vec<RepertoireMove>>,
+}
+
+impl Repertoire {
+    /// Opens the repertoire stored at `path`, which starts empty if the file does not exist
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("{path}: {e}")),
+        };
+
+        Ok(Self {
+            path: String::from(path),
+            positions: parse(&text).map_err(|e| format!("{path}: {e}"))?,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Counts `mv` as chosen in the position with `hash`, keeping `score` as its latest score
+    pub fn record(&mut self, hash: u64, mv: Move, score: i32) {
+        let moves = self.positions.entry(hash).or_default();
+        match moves.iter_mut().find(|e| e.mv == mv) {
+            Some(entry) => {
+                entry.count += 1;
+                entry.score = score;
+            }
+            None => moves.push(RepertoireMove {
+                mv,
+                count: 1,
+                score,
+            }),
+        }
+    }
+
+    /// Moves stored for the position, most chosen first. Moves which are not legal in it are
+    /// left out, as they can only come from a hash collision.
+    pub fn probe(&self, board: &Board) -> Vec<RepertoireMove> {
+        let mut moves: Vec<RepertoireMove> = self
+            .positions
+            .get(&board.hash.0)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|e| board.is_pseudo_legal(e.mv) && board.is_legal(e.mv))
+            .collect();
+        moves.sort_by_key(|e| std::cmp::Reverse(e.count));
+        moves
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        std::fs::write(&self.path, self.to_text()).map_err(|e| format!("{}: {e}", self.path))
+    }
+
+    fn to_text(&self) -> String {
+        self.positions
+            .iter()
+            .flat_map(|(hash, moves)| {
+                moves
+                    .iter()
+                    .map(move |e| format!("{hash:016X} {:04X} {} {}\n", e.mv.0, e.count, e.score))
+            })
+            .collect()
+    }
+}
+
+fn parse(text: &str) -> Result<BTreeMap<u64, Vec<RepertoireMove>>, String> {
+    let mut positions: BTreeMap<u64, Vec<RepertoireMove>> = BTreeMap::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() {
+            continue;
+        }
+
+        let Some((hash, entry)) = parse_entry(&fields) else {
+            return Err(format!("invalid entry on line {}", i + 1));
+        };
+        positions.entry(hash).or_default().push(entry);
+    }
+
+    Ok(positions)
+}
+
+fn parse_entry(fields: &[&str]) -> Option<(u64, RepertoireMove)> {
+    let [hash, mv, count, score] = fields else {
+        return None;
+    };
+
+    let entry = RepertoireMove {
+        mv: Move(u16::from_str_radix(mv, 16).ok()?),
+        count: count.parse().ok()?,
+        score: score.parse().ok()?,
+    };
+    Some((u64::from_str_radix(hash, 16).ok()?, entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moves::MoveKind;
+    use crate::square::Square;
+
+    #[test]
+    fn test_repertoire() {
+        let board = Board::default();
+        let find = |s: &str| board.find_move(s).unwrap();
+
+        let mut rep = Repertoire {
+            path: String::new(),
+            positions: BTreeMap::new(),
+        };
+        rep.record(board.hash.0, find("e2e4"), 30);
+        rep.record(board.hash.0, find("d2d4"), 25);
+        rep.record(board.hash.0, find("d2d4"), 20);
+        rep.record(board.hash.0 ^ 1, Move(0xFFFF), 0);
+
+        let moves = rep.probe(&board);
+        assert_eq!(moves.len(), 2);
+        assert_eq!(
+            (moves[0].mv.to_string(), moves[0].count),
+            ("d2d4".into(), 2)
+        );
+        assert_eq!(moves[0].score, 20);
+        assert_eq!(
+            (moves[1].mv.to_string(), moves[1].count),
+            ("e2e4".into(), 1)
+        );
+
+        // The text form reads back the same repertoire
+        let positions = parse(&rep.to_text()).unwrap();
+        assert_eq!(positions, rep.positions);
+        assert!(parse("0123 45 6").is_err());
+        assert!(parse("").unwrap().is_empty());
+
+        // Moves which are illegal in the probed position are dropped
+        let e2e5 = Move::new(Square::from("e2"), Square::from("e5"), MoveKind::Quiet);
+        rep.record(board.hash.0, e2e5, 0);
+        assert_eq!(rep.probe(&board).len(), 2);
+    }
+}
@@ -1,22 +1,60 @@
+use crate::constants::PIECE_VALUES;
+use crate::limits::SearchLimits;
 use crate::moves::MovePicker;
-use crate::tables::{history_bonus, Bound, SearchData};
+use crate::tables::{history_bonus, Bound, Heuristic, PlyData, SearchData, MAX_PLY};
+use crate::tunables::{
+    ASP_GROWTH, ASP_LIMIT, DELTA_MARGIN, HP_THRESHOLD, LMR_CUT_NODE, LMR_EVAL, LMR_HISTORY,
+    LMR_IMPROVING, MAX_HISTORY, NMP_KING_DANGER,
+};
+use crate::uci_println;
 use crate::{board::Board, moves::Move};
 
-pub const INF: i32 = 2 << 16;
-pub const MATE: i32 = INF >> 2;
+// Scores, every one of them fits an i16 so that TT entries can store them packed
+pub const MATE: i32 = 32_000;
+pub const INF: i32 = MATE + 1; // Bounds every search score
+pub const SCORE_NONE: i32 = INF + 1; // Not a score, for moves or searches without one yet
 pub const DRAW: i32 = 0;
 pub const MAX_DEPTH: u8 = 64;
 
+/// Scores at least this far from zero are mates, which are at most `MAX_PLY` plies away
+pub const MATE_BOUND: i32 = MATE - MAX_PLY as i32;
+
+/// Whether the score is a mate for either side
+pub fn is_mate(score: i32) -> bool {
+    score.abs() >= MATE_BOUND
+}
+
+/// Static evals saturate below the mate scores, however far the network output goes
+pub const MAX_EVAL: i32 = MATE_BOUND - 1;
+
+const _: () = assert!(SCORE_NONE <= i16::MAX as i32);
+
+// Extensions can take the search past the nominal depth, never past the ply stack
+const _: () = assert!((MAX_DEPTH as usize) < MAX_PLY);
+
 // Move Scores
 pub const TT_SCORE: i32 = 10_000_000;
+pub const PV_SCORE: i32 = 5_000_000; // Move of the last iteration's PV, when not the TT move
 pub const PROM_SCORE: i32 = 80_000;
 pub const CAP_SCORE: i32 = 90_000;
 pub const KILL_SCORE: i32 = 70_000;
+pub const COUNTER_SCORE: i32 = 60_000; // Quiet which refuted the previous move, below killers
+pub const ESCAPE_SCORE: i32 = 2048; // Bonus of quiets moving the piece under threat
+pub const REPETITION_SCORE: i32 = -TT_SCORE; // Root moves back into a position of the game
+
+/// Root moves repeating a position are searched last once the last iteration scored this much
+const REPETITION_AVOID_SCORE: i32 = 50;
 
 // Search Parameters
-const ASPIRATION_DELTA: i32 = 45;
-const ASPIRATION_DELTA_LIMIT: i32 = 500;
 const QS_SEE: i32 = -100;
+const DELTA_MIN_PIECES: u32 = 7; // With fewer pieces quiescence searches every capture
+const UNDERPROMO_DEPTH: u8 = 3;
+const PANIC_DEPTH: u8 = 6;
+const BOUND_INFO_TIME: u128 = 2000; // Milliseconds before root fails are reported
+
+// Explosion guard: a late root move may search this many times the first move's nodes
+const ROOT_NODE_FACTOR: u64 = 8;
+const ROOT_NODE_MIN: u64 = 1 << 20;
 
 const NMP_MIN_DEPTH: u8 = 2;
 const NMP_BASE_REDUCTION: u8 = 6;
@@ -27,78 +65,302 @@ const RFP_IMPROVING: i32 = 35;
 const RFP_MARGIN: i32 = 75;
 pub const LMR_DIV: f64 = 1.8;
 pub const LMR_BASE: f64 = 0.88;
+const LMR_EVAL_CAP: i32 = 400; // Largest gap below alpha which still lowers the reduction
 
 const RAZOR_DEPTH: u8 = 4;
 const RAZOR_MARGIN: i32 = 450;
 const HP_DEPTH: u8 = 2;
-const HP_THRESHOLD: i32 = -3550;
+const PROBCUT_DEPTH: u8 = 5;
+const PROBCUT_REDUCTION: u8 = 4;
+const PROBCUT_MARGIN: i32 = 200;
+const SEE_QUIET_DEPTH: u8 = 5;
+const SEE_QUIET_MARGIN: i32 = 50;
+const TT_FAIL_LOW_MARGIN: i32 = 150; // TT moves failing this far below alpha get a malus
 
-pub const HISTORY_MAX_BONUS: i16 = 1700;
-pub const HISTORY_FACTOR: i16 = 353;
-pub const HISTORY_OFFSET: i16 = 343;
 pub const MAX_CAP_HISTORY: i32 = 16384;
-pub const MAX_HISTORY: i32 = 8192;
 
-pub fn find_best_move(board: &Board, max_depth: u8, data: &mut SearchData) {
+/// Invariant of the search checked in debug builds, failing with the line from the root so that
+/// the node can be reproduced with a single `position` command
+macro_rules! search_assert {
+    ($data:expr, $cond:expr, $($msg:tt)*) => {
+        #[cfg(debug_assertions)]
+        if !$cond {
+            panic!("{} at {}", format!($($msg)*), $data.crash_dump());
+        }
+    };
+}
+
+pub fn find_best_move(board: &Board, limits: &SearchLimits, data: &mut SearchData) {
+    let max_depth = limits.depth.min(MAX_DEPTH);
+    data.limits = limits.clone();
+    // Before the clock starts, in case the GUI never sent `isready`
+    data.tt.allocate();
     data.start_search();
+    data.root_side = board.side;
+    data.root = *board;
+    data.tt.set_contempt_side(data.contempt, board.side);
+    data.start_easy_move(board);
+    // Continue a checkpointed analysis of this position after the depth it had reached
+    if let Some(resume) = data.resume.take() {
+        if resume.hash == board.hash.0 && resume.depth < max_depth {
+            data.depth = resume.depth + 1;
+            data.eval = resume.score;
+            data.best_move = resume.best_move;
+            data.last_pv.push(resume.best_move);
+        }
+    }
+    // The game is already drawn, any legal move keeps it so
+    if data.is_claimable_draw(board) {
+        negamax(board, 1, -INF, INF, false, data);
+        data.eval = data.draw_score(board.side);
+        data.best_move = legal_best_move(board, data);
+        data.predict(board);
+        if !data.silent {
+            uci_println!("{data}");
+        }
+        return;
+    }
 
-    while data.depth <= max_depth && !data.stop {
-        data.eval = if data.depth < 5 {
-            negamax(board, data.depth, -INF, INF, data)
+    let (mut total, mut iter_nodes) = (0, 0); // Nodes before and during the previous iteration
+
+    let mut done = false;
+    let mut previous = None; // Best move and score of the last completed iteration
+    while data.depth <= max_depth && !done {
+        let iter_start = data.control.elapsed();
+        data.best_move_changes = 0;
+        let score = if data.depth < 5 || data.is_disabled(Heuristic::Aspiration) {
+            negamax(board, data.depth, -INF, INF, false, data)
         } else {
             aspiration_window(board, data.depth, data.eval, data)
         };
 
-        if data.stop {
+        if data.control.is_stopped() {
             break;
-        } else if data.timing.elapsed().as_millis() * 5 / 4 > data.time_tp
-            || data.eval.abs() >= MATE - i32::from(MAX_DEPTH)
-        {
-            data.stop = true;
         }
 
-        println!("{data}");
+        data.eval = score;
+        data.publish_nodes();
+        data.end_iteration(previous);
+        previous = Some((data.best_move, data.eval));
+
+        // Effective branching factor: growth of the tree from one iteration to the next
+        let nodes = data.nodes - total;
+        total = data.nodes;
+        let ebf = (iter_nodes > 0).then(|| nodes as f64 / iter_nodes as f64);
+
+        let control = &data.control;
+        let out_of_time = !data.deterministic && {
+            let elapsed = control.elapsed();
+            let soft = data.stability_soft_limit();
+            // The next iteration should take about this one grown by the branching factor
+            let predicted = ebf.map_or(0, |ebf| ((elapsed - iter_start) as f64 * ebf) as u128);
+            elapsed * 5 / 4 > soft || data.skip_iteration(elapsed, predicted, soft)
+        };
+        done = out_of_time
+            || control.nodes() >= control.node_limit()
+            || data.limits.mate_found(data.eval)
+            || (!data.analyse_mode && is_mate(data.eval));
+
+        if data.analyse_mode {
+            data.extend_pv(board, data.depth as usize);
+        }
+        if is_mate(data.eval) {
+            complete_mate_pv(board, data);
+        }
+
+        if !data.silent {
+            uci_println!("{data}");
+            if let Some(ebf) = ebf {
+                uci_println!("info string ebf {ebf:.2}");
+            }
+            if data.root_moves_report {
+                for line in data.root_moves_info() {
+                    uci_println!("{line}");
+                }
+            }
+            data.mark_reported();
+        }
+        iter_nodes = nodes;
         data.depth += 1;
     }
+
+    data.best_move = legal_best_move(board, data);
+    data.predict(board);
+}
+
+/// The best move if it is legal, which it may not be when the search stopped before the root
+/// searched any move. The TT move is the fallback, after checking it as it may come from a
+/// colliding entry, and then the first legal move. Moves left out by `searchmoves` are skipped.
+pub fn legal_best_move(board: &Board, data: &SearchData) -> Move {
+    let legal = |m: Move| board.is_pseudo_legal(m) && board.is_legal(m) && data.limits.allows(m);
+    if legal(data.best_move) {
+        return data.best_move;
+    }
+
+    data.tt
+        .probe(board.hash.0)
+        .map(|entry| entry.best_move)
+        .filter(|&m| legal(m))
+        .or_else(|| {
+            board
+                .generate_pseudo_moves::<true, true>()
+                .into_iter()
+                .find(|&m| legal(m))
+        })
+        .unwrap_or(Move::NULL)
+}
+
+/// Makes the PV of a mate score reach the mate, first from the TT and otherwise with a
+/// verification search from the end of the truncated line
+fn complete_mate_pv(board: &Board, data: &mut SearchData) {
+    let plies = (MATE - data.eval.abs()) as usize;
+    data.extend_pv(board, plies);
+
+    let root_pv = data.ply_data[0].pv;
+    let missing = plies.saturating_sub(root_pv.as_slice().len());
+    if missing == 0 {
+        return;
+    }
+
+    let mut end = *board;
+    let stack_len = data.stack.len();
+    for m in &root_pv {
+        data.stack.push(end.hash.0);
+        end.make_move(m);
+    }
+
+    let best_move = data.best_move;
+    negamax(&end, missing as u8, -INF, INF, false, data);
+
+    let mut pv = root_pv;
+    if !data.control.is_stopped() {
+        for m in &data.ply_data[0].pv {
+            pv.push(m);
+        }
+    }
+
+    data.ply_data[0].pv = pv;
+    data.stack.truncate(stack_len);
+    data.best_move = best_move;
 }
 
-fn aspiration_window(board: &Board, max_depth: u8, estimate: i32, data: &mut SearchData) -> i32 {
-    let mut delta = ASPIRATION_DELTA;
-    let mut alpha = estimate - delta;
-    let mut beta = estimate + delta;
-    let mut depth = max_depth;
+/// Searches the root within a window around the score `estimate` of the last iteration. Every
+/// fail widens the window on the side it failed at the same depth, a fail low also pulling the
+/// upper bound down, until the delta is so large that the window opens fully.
+fn aspiration_window(board: &Board, depth: u8, estimate: i32, data: &mut SearchData) -> i32 {
+    let mut delta = data.aspiration_delta(depth);
+    let mut alpha = (-INF).max(estimate - delta);
+    let mut beta = INF.min(estimate + delta);
 
     loop {
-        let score = negamax(board, depth, alpha, beta, data);
-        if data.stop {
+        let score = negamax(board, depth, alpha, beta, false, data);
+        if data.control.is_stopped() {
             return 0;
         }
 
-        if score <= alpha {
-            beta = (alpha + beta) / 2;
-            alpha = (-INF).max(alpha - delta);
-            depth = max_depth;
+        let bound = if score <= alpha {
+            Bound::Upper
         } else if score >= beta {
-            beta = INF.min(beta + delta);
-            if depth > 1 {
-                depth -= 1;
-            }
+            Bound::Lower
         } else {
             return score;
+        };
+
+        // Long re-searches let the GUI know the score is moving, but never as an exact one
+        if !data.silent && data.control.elapsed() >= BOUND_INFO_TIME {
+            uci_println!("{}", data.info_line(score, bound));
         }
 
-        delta += 2 * delta / 5;
-        if delta > ASPIRATION_DELTA_LIMIT {
+        if bound == Bound::Upper {
+            // Failing low after half the time is gone means the best move is about to change
+            let control = &data.control;
+            if depth >= PANIC_DEPTH && 2 * control.elapsed() >= control.soft_limit() {
+                data.panic_time();
+            }
+
+            beta = (alpha + beta) / 2;
+            alpha = (-INF).max(alpha - delta);
+        } else {
+            beta = INF.min(beta + delta);
+        }
+
+        delta += delta * ASP_GROWTH.get() / 100;
+        if delta > ASP_LIMIT.get() {
             alpha = -INF;
             beta = INF;
         }
     }
 }
 
-fn quiescence(board: &Board, mut alpha: i32, beta: i32, data: &mut SearchData) -> i32 {
+/// Both searches fail soft: a score at most `alpha` is an upper bound of the real one and a
+/// score at least `beta` a lower bound, and either may lie beyond the window, which makes the
+/// bounds stored in the TT and the re-searches of the aspiration windows tighter. TT scores only
+/// cut in non-PV nodes when their bound proves the same, so a score strictly inside the window
+/// is always exact. With `fail_hard` every score is clamped into its window instead.
+#[inline]
+fn quiescence(board: &Board, alpha: i32, beta: i32, data: &mut SearchData) -> i32 {
+    let score = quiescence_node(board, alpha, beta, data);
+    within_window(score, alpha, beta, data)
+}
+
+#[inline]
+fn negamax(
+    board: &Board,
+    depth: u8,
+    alpha: i32,
+    beta: i32,
+    cut_node: bool,
+    data: &mut SearchData,
+) -> i32 {
+    let score = negamax_node(board, depth, alpha, beta, cut_node, data);
+    within_window(score, alpha, beta, data)
+}
+
+/// Mate scores count the plies from the root, in the TT they count them from the node instead so
+/// that they still hold when the position comes up at another ply
+#[inline]
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+#[inline]
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[inline]
+fn within_window(score: i32, alpha: i32, beta: i32, data: &SearchData) -> i32 {
+    if data.fail_hard {
+        score.clamp(alpha, beta)
+    } else {
+        score
+    }
+}
+
+fn quiescence_node(board: &Board, mut alpha: i32, beta: i32, data: &mut SearchData) -> i32 {
+    if data.should_stop() {
+        return 0;
+    }
+
+    if data.ply >= MAX_PLY - 1 {
+        return data.evaluate(board);
+    }
+
     let key = board.hash.0;
     if let Some(entry) = data.tt.probe(key) {
-        let tt_score = entry.value;
+        let tt_score = score_from_tt(entry.value, data.ply);
         match entry.bound() {
             Bound::Exact => return tt_score,
             Bound::Lower if tt_score >= beta => return tt_score,
@@ -107,40 +369,68 @@ fn quiescence(board: &Board, mut alpha: i32, beta: i32, data: &mut SearchData) -
         }
     }
 
-    let mut best_eval = board.evaluate(&mut data.cache);
+    // In check there is no standing pat: every evasion is searched and having none is mate
+    let in_check = board.in_check();
+    let mut best_eval = if in_check {
+        data.ply as i32 - MATE
+    } else if !board.has_non_pawn_material(board.side) && !board.has_legal_move() {
+        // Only pawns and a king may well be stalemated, which the static eval knows nothing of
+        return data.draw_score(board.side);
+    } else {
+        data.evaluate(board)
+    };
     if best_eval >= beta {
         return best_eval;
     }
 
     alpha = alpha.max(best_eval);
 
-    let mut picker = MovePicker::new::<false>(board);
+    let mut picker = if in_check {
+        MovePicker::new::<true>(board)
+    } else {
+        MovePicker::new::<false>(board)
+    };
     picker.score_caps(board, data);
 
     let mut best_move = Move::NULL;
     let mut bound = Bound::Upper;
+    let delta_pruning = !in_check
+        && board.occupancy().count_bits() >= DELTA_MIN_PIECES
+        && !data.is_disabled(Heuristic::DeltaPruning);
+    let underpromotion_pruning = !data.is_disabled(Heuristic::UnderpromotionPruning);
 
     data.ply += 1;
+    data.seldepth = data.seldepth.max(data.ply);
 
-    while let Some((m, _)) = picker.next() {
+    for (m, _) in picker {
         if !board.is_legal(m) {
             continue;
         }
 
+        // Underpromotions only matter in the main search
+        if m.get_type().is_underpromotion() && underpromotion_pruning {
+            continue;
+        }
+
         // Quiescence SEE pruning
-        if best_eval > -MATE
-            && m.get_type().is_capture()
-            && !board.in_check()
-            && !board.see(m, QS_SEE)
-        {
+        if best_eval > -MATE && m.get_type().is_capture() && !in_check && !board.see(m, QS_SEE) {
             break;
         }
 
-        let mut new_board = *board;
-        new_board.make_move(m);
-
-        data.nodes += 1;
+        // Delta pruning: not even winning the captured piece for free reaches alpha. Promotions
+        // gain a piece on top, so they are always searched
+        if delta_pruning
+            && !m.get_type().is_promotion()
+            && best_eval
+                + PIECE_VALUES[board.capture_piece(m).piece_type().index()]
+                + DELTA_MARGIN.get()
+                <= alpha
+        {
+            continue;
+        }
 
+        data.ply_data[data.ply - 1].record(board, m);
+        let new_board = data.make_move(board, m);
         let score = -quiescence(&new_board, -beta, -alpha, data);
 
         if score > best_eval {
@@ -157,31 +447,58 @@ fn quiescence(board: &Board, mut alpha: i32, beta: i32, data: &mut SearchData) -
 
     data.ply -= 1;
 
+    if data.aborted() {
+        return 0;
+    }
+
     if best_eval > alpha {
         bound = Bound::Exact;
     }
 
-    data.tt.insert(key, bound, best_move, best_eval, 0, false);
+    let tt_score = score_to_tt(best_eval, data.ply);
+    data.tt.insert(key, bound, best_move, tt_score, 0, false);
 
     best_eval
 }
 
-fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut SearchData) -> i32 {
-    if data.stop || (data.nodes & 4095 == 0 && !data.continue_search()) {
-        data.stop = true;
+/// `cut_node` marks zero window nodes expected to fail high, where the first move should already
+/// refute the position
+fn negamax_node(
+    board: &Board,
+    mut depth: u8,
+    mut alpha: i32,
+    beta: i32,
+    cut_node: bool,
+    data: &mut SearchData,
+) -> i32 {
+    if data.should_stop() {
         return 0;
     }
 
+    // Only long chains of check extensions get this far, the ply stack ends here
+    if data.ply >= MAX_PLY - 1 {
+        return data.evaluate(board);
+    }
+
     let in_check = board.in_check();
     let key = board.hash.0;
+    search_assert!(data, alpha < beta, "Empty window [{alpha}, {beta}]");
+    search_assert!(
+        data,
+        board.hash == crate::zobrist::ZHash::new(board),
+        "Incremental hash {key:016X} differs from the full one of {board:?}"
+    );
     data.ply_data[data.ply].pv.clear();
+    data.ply_data[data.ply].threat = None;
 
-    if data.ply > 0 && depth < MAX_DEPTH {
+    if data.ply > 0 {
         if board.is_draw() || data.is_repetition(board, key, false) {
-            return DRAW;
+            return data.draw_score(board.side);
+        }
+        // Never drop into quiescence while in check
+        if in_check && depth == 0 {
+            depth = 1;
         }
-        // Check Extensions
-        depth += u8::from(in_check);
     }
 
     if depth == 0 {
@@ -192,30 +509,43 @@ fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut S
     let mut tt_move = None;
     if let Some(entry) = data.tt.probe(key) {
         tt_move = Some(entry.best_move);
-        if entry.depth() >= depth && !pv_node {
+        // Never cut at the root, which must always produce its own move, and only trust exact
+        // scores in PV nodes
+        let tt_score = score_from_tt(entry.value, data.ply);
+        if entry.depth() >= depth && data.ply > 0 {
             match entry.bound() {
-                Bound::Exact => return entry.value,
-                Bound::Lower if entry.value >= beta => return entry.value,
-                Bound::Upper if entry.value <= alpha => return entry.value,
+                Bound::Exact => return tt_score,
+                Bound::Lower if !pv_node && tt_score >= beta => return tt_score,
+                Bound::Upper if !pv_node && tt_score <= alpha => return tt_score,
                 _ => {}
             }
         }
     }
 
+    // Static evaluation, meaningless while in check
+    let static_eval = if in_check { -INF } else { data.evaluate(board) };
+    let improving = !in_check && data.ply >= 2 && static_eval > data.ply_data[data.ply - 2].eval;
+    if !in_check {
+        data.ply_data[data.ply].eval = static_eval;
+    }
+
     let can_prune = !pv_node && !in_check;
     if can_prune {
         // Reverse Futility pruning
-        let static_eval = board.evaluate(&mut data.cache);
-        data.ply_data[data.ply].eval = static_eval;
-        let improving = data.ply >= 2 && static_eval > data.ply_data[data.ply - 2].eval;
         let rfp_margin = RFP_MARGIN * depth as i32 - RFP_IMPROVING * improving as i32;
 
-        if depth <= RFP_DEPTH && static_eval - rfp_margin >= beta {
+        if depth <= RFP_DEPTH
+            && static_eval - rfp_margin >= beta
+            && !data.is_disabled(Heuristic::Rfp)
+        {
             return (static_eval + beta) / 2;
         }
 
         // Razoring
-        if depth < RAZOR_DEPTH && static_eval + RAZOR_MARGIN * (depth as i32) < alpha {
+        if depth < RAZOR_DEPTH
+            && static_eval + RAZOR_MARGIN * (depth as i32) < alpha
+            && !data.is_disabled(Heuristic::Razoring)
+        {
             let qeval = quiescence(board, alpha, beta, data);
             if qeval < alpha {
                 return qeval;
@@ -223,19 +553,51 @@ fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut S
         }
 
         // Null Move Pruning
-        if depth >= NMP_MIN_DEPTH && !board.is_king_pawn() {
+        if depth >= NMP_MIN_DEPTH
+            && board.has_non_pawn_material(board.side)
+            && board.king_danger() < NMP_KING_DANGER.get() as u32
+            && !data.previous().is_some_and(PlyData::is_null)
+            && !data.is_disabled(Heuristic::Nmp)
+        {
             let mut null_board = *board;
             null_board.make_null_move();
             let r = (NMP_BASE_REDUCTION + depth / NMP_DIVISOR).min(depth);
-            let null_score = -negamax(&null_board, depth - r, -beta, -beta + 1, data);
+            data.ply_data[data.ply].record_null();
+            data.push(key);
+            let null_score = -negamax(&null_board, depth - r, -beta, -beta + 1, !cut_node, data);
+            data.pop();
             if null_score >= beta {
                 return null_score;
             }
+            // Passing loses, most likely a piece: moves saving it are tried earlier
+            data.ply_data[data.ply].threat = board.biggest_threat(board.side);
+        }
+
+        // ProbCut: in nodes expected to fail high, a good capture beating beta by a margin in a
+        // reduced search is trusted to beat beta in the full one as well
+        let pc_beta = beta + PROBCUT_MARGIN;
+        if cut_node
+            && depth >= PROBCUT_DEPTH
+            && !is_mate(beta)
+            && !data.is_disabled(Heuristic::ProbCut)
+        {
+            if let Some((m, score)) = probcut(board, depth, pc_beta, static_eval, cut_node, data) {
+                data.tt.insert(
+                    key,
+                    Bound::Lower,
+                    m,
+                    score_to_tt(score, data.ply),
+                    depth - PROBCUT_REDUCTION + 1,
+                    false,
+                );
+                return score;
+            }
         }
     }
 
-    // Internal Iterative Reduction
-    if depth >= 2 && tt_move.is_none() {
+    // Internal Iterative Reduction, nodes expected to be refuted anyway are left alone
+    if depth >= 2 && tt_move.is_none() && (pv_node || cut_node) && !data.is_disabled(Heuristic::Iir)
+    {
         depth -= 1;
     }
 
@@ -243,31 +605,99 @@ fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut S
     picker.score_moves(board, tt_move, data);
 
     let old_alpha = alpha;
-    let lmr_ready = depth > 1 && !in_check;
+    let lmr_ready = depth > 1 && !in_check && !data.is_disabled(Heuristic::Lmr);
     let mut best_move = Move::NULL;
     let mut best_score = -INF;
     let mut move_idx = 0;
     let mut quiets_tried = Vec::with_capacity(32);
     let mut caps_tried = Vec::with_capacity(32);
+    let prev_move = data.previous().map_or(Move::NULL, |prev| prev.played);
+    let root = data.ply == 0;
+    let mut first_move_nodes = 0;
+    let (root_nodes, mut best_move_nodes) = (data.nodes, 0);
     data.push(key);
 
+    // A better position is not thrown away by shuffling back into an earlier one, even when a
+    // stale hash move suggests it
+    if root && data.depth > 1 && data.eval >= REPETITION_AVOID_SCORE {
+        picker.demote_repetitions(board, data);
+    }
+
     while let Some((m, ms)) = picker.next() {
         if can_prune && best_score.abs() < MATE {
-            // History pruning
-            if depth <= HP_DEPTH && ms < HP_THRESHOLD {
-                break;
+            let (src, kind) = (m.get_source(), m.get_type());
+            let quiet = !kind.is_capture() && !kind.is_promotion();
+
+            // History pruning: the quiets left are all worse, but the captures are still tried
+            if quiet
+                && depth <= HP_DEPTH
+                && ms < HP_THRESHOLD.get()
+                && !data.is_disabled(Heuristic::HistoryPruning)
+            {
+                picker.skip_quiets();
+                continue;
+            }
+
+            // SEE pruning: quiets giving a piece away for nothing, unless it was attacked anyway
+            if depth <= SEE_QUIET_DEPTH
+                && quiet
+                && board
+                    .cheapest_attacker(
+                        m.get_dest(),
+                        !board.side,
+                        board.occupancy() ^ src.to_board(),
+                    )
+                    .is_some()
+                && board
+                    .cheapest_attacker(src, !board.side, board.occupancy())
+                    .is_none()
+                && !board.see(m, -SEE_QUIET_MARGIN * i32::from(depth))
+                && !data.is_disabled(Heuristic::SeePruning)
+            {
+                continue;
             }
         }
 
-        if !board.is_legal(m) {
+        if !board.is_legal(m) || (root && !data.limits.allows(m)) {
             continue;
         }
 
-        let mut new_board = *board;
-        new_board.make_move(m);
+        // Underpromotion pruning: near the leaves only checking ones can beat the queen
+        if m.get_type().is_underpromotion()
+            && depth <= UNDERPROMO_DEPTH
+            && Some(m) != tt_move
+            && !data.is_disabled(Heuristic::UnderpromotionPruning)
+        {
+            let mut promoted = *board;
+            promoted.make_move(m);
+            if !promoted.in_check() {
+                continue;
+            }
+        }
 
+        let move_nodes = data.nodes;
+        let new_board = data.make_move(board, m);
         move_idx += 1;
-        data.nodes += 1;
+        if root {
+            data.currmove = m;
+            data.currmovenumber = move_idx;
+        }
+
+        // Extensions: non-losing checks and TT move recaptures, bounded by a per-ply budget
+        let ply = data.ply - 1;
+        let within_budget = data.ply_data[ply].extensions as usize * 2 <= ply;
+        let recapture = prev_move.get_type().is_capture()
+            && m.get_type().is_capture()
+            && m.get_dest() == prev_move.get_dest();
+        let extension = u8::from(
+            within_budget
+                && ((new_board.in_check() && board.see(m, 0)) || (Some(m) == tt_move && recapture))
+                && !data.is_disabled(Heuristic::Extensions),
+        );
+
+        data.ply_data[ply].record(board, m);
+        data.ply_data[ply + 1].extensions = data.ply_data[ply].extensions + extension;
+        let new_depth = depth - 1 + extension;
 
         let mut reduction = 0;
 
@@ -276,30 +706,73 @@ fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut S
             reduction = data.lmr_table.base[depth as usize][move_idx];
             reduction -= i16::from(pv_node);
             reduction -= i16::from(new_board.in_check());
-            if ms <= MAX_HISTORY {
-                reduction -= (ms / MAX_HISTORY) as i16;
-            }
+
+            // Adjustments in 1024ths of a ply: good history and a static eval far below alpha
+            // (the position may hold resources the eval misses) reduce less
+            let max_history = MAX_HISTORY.get();
+            let eval_gap = (alpha - static_eval).clamp(0, LMR_EVAL_CAP);
+            let adjustment = LMR_HISTORY.get() * ms.clamp(-max_history, max_history) / max_history
+                + LMR_EVAL.get() * eval_gap / 100
+                - LMR_CUT_NODE.get() * i32::from(cut_node)
+                + LMR_IMPROVING.get() * i32::from(improving);
+            reduction -= (adjustment / 1024) as i16;
             reduction = reduction.clamp(0, depth as i16 - 1);
         }
 
+        // Principal Variation Search
         let score = if move_idx == 1 {
-            -negamax(&new_board, depth - 1, -beta, -alpha, data)
+            let nodes = data.nodes;
+            let score = if pv_node {
+                search_pv(&new_board, new_depth, alpha, beta, data)
+            } else {
+                search_zw(&new_board, new_depth, alpha, !cut_node, data)
+            };
+            first_move_nodes = data.nodes - nodes;
+            if Some(m) == tt_move && score + TT_FAIL_LOW_MARGIN <= alpha && !data.aborted() {
+                punish_tt_move(board, &new_board, m, depth, data);
+            }
+            score
         } else {
-            let mut zw_search = -negamax(
-                &new_board,
-                depth - 1 - reduction as u8,
-                -alpha - 1,
-                -alpha,
-                data,
-            );
-
-            if zw_search > alpha && (pv_node || reduction > 0) {
-                zw_search = -negamax(&new_board, depth - 1, -beta, -alpha, data);
+            if root {
+                data.set_root_node_cap((ROOT_NODE_FACTOR * first_move_nodes).max(ROOT_NODE_MIN));
+            }
+            let score = search_zw(&new_board, new_depth - reduction as u8, alpha, true, data);
+            if root {
+                // A runaway subtree is dropped rather than trusted
+                let capped = data.aborted() && !data.control.is_stopped();
+                data.clear_root_node_cap();
+                if capped {
+                    data.record_root_move(m, data.nodes - move_nodes, None);
+                    continue;
+                }
+            }
+
+            if score > alpha && pv_node {
+                search_pv(&new_board, new_depth, alpha, beta, data)
+            } else if score > alpha && reduction > 0 {
+                search_zw(&new_board, new_depth, alpha, !cut_node, data)
+            } else {
+                score
             }
-            zw_search
         };
 
+        if root {
+            let bound = if score <= alpha {
+                Bound::Upper
+            } else if score >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            let searched = (!data.aborted()).then_some((score, bound));
+            data.record_root_move(m, data.nodes - move_nodes, searched);
+        }
+
         if score > best_score {
+            if root && move_idx > 1 && score > alpha {
+                data.best_move_changes += 1;
+            }
+            best_move_nodes = data.nodes - move_nodes;
             alpha = alpha.max(score);
             best_score = score;
             best_move = m;
@@ -315,13 +788,7 @@ fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut S
             if !m.get_type().is_capture() {
                 data.ply_data[data.ply].killer = m;
 
-                data.history.update(
-                    board.side,
-                    m.get_source().index(),
-                    m.get_dest().index(),
-                    history_bonus,
-                    &quiets_tried,
-                );
+                data.history.update(board, m, history_bonus, &quiets_tried);
             }
             data.cap_history
                 .update(board, m, history_bonus, &caps_tried);
@@ -338,12 +805,17 @@ fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut S
 
     data.pop();
 
-    if data.stop {
+    if data.aborted() {
         return 0;
     }
 
+    // No legal move: mated, counting the plies from the root, or stalemated
     if move_idx == 0 {
-        return i32::from(in_check) * (data.ply as i32 - MATE);
+        return if in_check {
+            data.ply as i32 - MATE
+        } else {
+            data.draw_score(board.side)
+        };
     }
 
     let bound = if best_score <= old_alpha {
@@ -354,12 +826,692 @@ fn negamax(board: &Board, mut depth: u8, mut alpha: i32, beta: i32, data: &mut S
         Bound::Exact
     };
 
-    data.tt
-        .insert(key, bound, best_move, best_score, depth, pv_node);
-
-    if data.ply == 0 {
-        data.best_move = best_move
+    search_assert!(
+        data,
+        best_score.abs() < INF,
+        "Score {best_score} of {best_move:?} out of bounds"
+    );
+    data.tt.insert(
+        key,
+        bound,
+        best_move,
+        score_to_tt(best_score, data.ply),
+        depth,
+        pv_node,
+    );
+
+    // A root failing low only bounds every move, the previous best move is kept
+    if data.ply == 0 && (bound != Bound::Upper || data.best_move == Move::NULL) {
+        data.best_move = best_move;
+        data.best_move_share = 100 * best_move_nodes / (data.nodes - root_nodes).max(1);
     }
 
     best_score
 }
+
+/// A TT move failing low badly was a poor guess: a quiet one gets a history malus, and the
+/// quiet reply which refuted it, the best move of `child` in the TT, becomes its countermove
+fn punish_tt_move(board: &Board, child: &Board, m: Move, depth: u8, data: &mut SearchData) {
+    let is_quiet = |m: Move| !m.get_type().is_capture() && !m.get_type().is_promotion();
+    if is_quiet(m) {
+        data.history.update(board, m, -history_bonus(depth), &[]);
+    }
+
+    let refutation = data.tt.probe(child.hash.0).map(|entry| entry.best_move);
+    if let Some(refutation) = refutation.filter(|&r| child.is_pseudo_legal(r) && is_quiet(r)) {
+        data.counter_moves
+            .set(&data.ply_data[data.ply - 1], refutation);
+    }
+}
+
+/// Captures winning enough material to beat `pc_beta` in a quiescence search and then in a search
+/// reduced by `PROBCUT_REDUCTION`, returning the first one found with its score
+fn probcut(
+    board: &Board,
+    depth: u8,
+    pc_beta: i32,
+    static_eval: i32,
+    cut_node: bool,
+    data: &mut SearchData,
+) -> Option<(Move, i32)> {
+    let mut picker = MovePicker::new::<false>(board);
+    picker.score_caps(board, data);
+    data.push(board.hash.0);
+
+    let mut cut = None;
+    for (m, _) in picker {
+        if !board.see(m, pc_beta - static_eval) || !board.is_legal(m) {
+            continue;
+        }
+
+        let new_board = data.make_move(board, m);
+        let ply = data.ply - 1;
+        data.ply_data[ply].record(board, m);
+        data.ply_data[ply + 1].extensions = data.ply_data[ply].extensions;
+
+        let mut score = -quiescence(&new_board, -pc_beta, -pc_beta + 1, data);
+        if score >= pc_beta {
+            let depth = depth - PROBCUT_REDUCTION;
+            score = -negamax(&new_board, depth, -pc_beta, -pc_beta + 1, !cut_node, data);
+        }
+        if score >= pc_beta {
+            cut = Some((m, score));
+            break;
+        }
+    }
+
+    data.pop();
+    cut.filter(|_| !data.aborted())
+}
+
+/// Full window search of a child node, used for the first move and for re-searches of moves
+/// which beat alpha in the zero window search
+#[inline]
+fn search_pv(board: &Board, depth: u8, alpha: i32, beta: i32, data: &mut SearchData) -> i32 {
+    -negamax(board, depth, -beta, -alpha, false, data)
+}
+
+/// Zero window search of a child node, only proves whether the move can raise alpha
+#[inline]
+fn search_zw(board: &Board, depth: u8, alpha: i32, cut_node: bool, data: &mut SearchData) -> i32 {
+    -negamax(board, depth, -alpha - 1, -alpha, cut_node, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tables::RootMove;
+
+    fn search(fen: &str, nodes: u64) -> (String, i32) {
+        let board = Board::from_fen(fen);
+        let mut data = SearchData::new();
+        find_best_move(&board, &SearchLimits::new().nodes(nodes), &mut data);
+
+        (data.best_move.to_string(), data.eval)
+    }
+
+    #[test]
+    fn test_root_fail_low() {
+        let board = Board::default();
+        let mut data = SearchData::new();
+        data.deterministic = true;
+        data.silent = true;
+        find_best_move(&board, &SearchLimits::new().depth(4), &mut data);
+
+        // No move reaches the window, so none of them is known to be better than the last one
+        let previous = board.find_move("a2a3").unwrap();
+        data.best_move = previous;
+        let score = negamax(&board, 4, 500, 501, false, &mut data);
+        assert!(score <= 500);
+        assert_eq!(data.best_move, previous);
+
+        // Within the window the best move is replaced as usual
+        negamax(&board, 4, -INF, INF, false, &mut data);
+        assert_ne!(data.best_move, previous);
+    }
+
+    #[test]
+    fn test_max_ply() {
+        // Both queens give check on almost every move, feeding long chains of extensions
+        let board = Board::from_fen("3k4/8/8/2q5/8/8/8/4K2Q w - - 0 1");
+
+        // A search starting deep in the ply stack, as if at the end of a long line
+        let mut data = SearchData::new();
+        data.deterministic = true;
+        data.silent = true;
+        data.control.start(100_000, u128::MAX, u128::MAX);
+        data.ply = MAX_PLY - 12;
+        negamax(&board, 30, -INF, INF, false, &mut data);
+        assert_eq!(data.ply, MAX_PLY - 12);
+        assert!(data.seldepth < MAX_PLY);
+
+        // Depths past the maximum are clamped
+        let mut data = SearchData::new();
+        data.deterministic = true;
+        data.silent = true;
+        let limits = SearchLimits::new().depth(u8::MAX).nodes(100_000);
+        assert_eq!(limits.depth, MAX_DEPTH);
+        find_best_move(&board, &limits, &mut data);
+        assert!(data.depth <= MAX_DEPTH + 1);
+        assert!(board.is_legal(data.best_move) && data.seldepth < MAX_PLY);
+    }
+
+    #[test]
+    fn test_tt_collisions() {
+        use crate::moves::MoveKind;
+        use crate::square::Square;
+
+        let board = Board::default();
+        let bogus = Move::new(Square::from("a3"), Square::from("h6"), MoveKind::Capture);
+        let run = |data: &mut SearchData| {
+            data.deterministic = true;
+            data.silent = true;
+            find_best_move(&board, &SearchLimits::new().depth(4), data);
+            assert!(board.is_pseudo_legal(data.best_move) && board.is_legal(data.best_move));
+
+            let mut pos = board;
+            for m in &data.ply_data[0].pv {
+                assert!(pos.is_pseudo_legal(m) && pos.is_legal(m), "{m}");
+                pos.make_move(m);
+            }
+        };
+
+        // A deep exact entry with an illegal move at the root is never a cutoff
+        let mut data = SearchData::new();
+        data.tt
+            .insert(board.hash.0, Bound::Exact, bogus, 5000, 63, true);
+        run(&mut data);
+        assert!(data.eval.abs() < 5000);
+
+        // Exact entries of the children cut even in PV nodes, but their illegal moves never
+        // reach the PV
+        let mut data = SearchData::new();
+        for m in board.generate_pseudo_moves::<true, true>() {
+            let mut child = board;
+            child.make_move(m);
+            data.tt
+                .insert(child.hash.0, Bound::Exact, bogus, 0, 63, true);
+        }
+        run(&mut data);
+        assert_eq!((data.eval, data.ply_data[0].pv.as_slice().len()), (0, 1));
+
+        // The fallback skips an illegal TT move for the first legal one
+        data.best_move = bogus;
+        data.tt.clear();
+        data.tt
+            .insert(board.hash.0, Bound::Exact, bogus, 0, 63, true);
+        let fallback = legal_best_move(&board, &data);
+        assert!(board.is_pseudo_legal(fallback) && board.is_legal(fallback));
+    }
+
+    #[test]
+    fn test_mates() {
+        #[rustfmt::skip]
+        const MATES: [(&str, &str); 4] = [
+            ("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4", "h5f7"),
+            ("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1", "d1d8"),
+            ("r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - 0 1", "f6a6"),
+            ("2r3k1/p4p2/3Rp2p/1p2P1pK/8/1P4P1/P3Q2P/1q6 b - - 0 1", "b1g6"),
+        ];
+
+        for (fen, best) in MATES {
+            let (m, score) = search(fen, 200_000);
+            assert_eq!(m, best, "{fen}");
+            assert!(score >= MATE_BOUND, "{fen}: {score}");
+        }
+    }
+
+    #[test]
+    fn test_score_range() {
+        let mut rng = crate::rng::Rng::new(3);
+        let extremes = [0, 1, MAX_EVAL, MATE_BOUND, MATE - 1, MATE];
+        let random = (0..1000).map(|_| rng.below(2 * MATE as usize + 1) as i32 - MATE);
+        let scores: Vec<i32> = extremes
+            .iter()
+            .flat_map(|&s| [s, -s])
+            .chain(random)
+            .collect();
+
+        // Any score at any ply goes into the TT as an i16 and comes back the same, mates as long
+        // as they are within reach of the ply
+        for ply in 0..MAX_PLY {
+            for &score in &scores {
+                let stored = score_to_tt(score, ply);
+                assert!(i16::try_from(stored).is_ok(), "{score} at ply {ply}");
+                if score.abs() < MATE_BOUND || score.abs() <= MATE - ply as i32 {
+                    assert_eq!(score_from_tt(stored, ply), score, "{score} at ply {ply}");
+                }
+            }
+        }
+        assert!(i16::try_from(-SCORE_NONE).is_ok());
+    }
+
+    #[test]
+    fn test_deep_mates() {
+        // Mates deeper than the nominal depth, reached through extensions, are still mates
+        for ply in [1, 64, 65, 100, MAX_PLY as i32] {
+            assert!(is_mate(MATE - ply) && is_mate(ply - MATE), "{ply}");
+        }
+        assert!(!is_mate(MAX_EVAL) && !is_mate(-MAX_EVAL));
+
+        let data = SearchData::new();
+        assert_eq!(data.uci_score(MATE - 101, Bound::Exact), "mate 51");
+        assert_eq!(data.uci_score(100 - MATE, Bound::Exact), "mate -50");
+        assert_eq!(
+            data.uci_score(MAX_EVAL, Bound::Exact),
+            format!("cp {MAX_EVAL}")
+        );
+    }
+
+    #[test]
+    fn test_mate_pv() {
+        let mut board = Board::from_fen("r5rk/5p1p/5R2/4B3/8/8/7P/7K w - - 0 1");
+        let mut data = SearchData::new();
+        data.silent = true;
+        find_best_move(&board, &SearchLimits::new().nodes(200_000), &mut data);
+
+        // The line must reach the mate announced by the score
+        let pv = data.ply_data[0].pv;
+        assert_eq!(pv.as_slice().len(), (MATE - data.eval) as usize);
+        for m in &pv {
+            assert!(board.is_legal(m));
+            board.make_move(m);
+        }
+        let replies = board.generate_pseudo_moves::<true, true>();
+        assert!(board.in_check() && !replies.into_iter().any(|m| board.is_legal(m)));
+    }
+
+    #[test]
+    fn test_punish_tt_move() {
+        let board = Board::default();
+        let g1f3 = board.find_move("g1f3").unwrap();
+        let mut child = board;
+        child.make_move(g1f3);
+        let refutation = child.find_move("e7e5").unwrap();
+
+        let mut data = SearchData::new();
+        data.tt.allocate();
+        data.tt
+            .insert(child.hash.0, Bound::Lower, refutation, 300, 4, false);
+        data.ply_data[0].record(&board, g1f3);
+        data.ply = 1;
+        punish_tt_move(&board, &child, g1f3, 6, &mut data);
+
+        assert!(data.history.get(&board, g1f3) < 0);
+        assert_eq!(data.counter_moves.get(&data.ply_data[0]), refutation);
+
+        // Without captures or a killer, the refutation is the first reply tried
+        let mut picker = MovePicker::new::<true>(&child);
+        picker.score_moves(&child, None, &data);
+        assert_eq!(picker.next(), Some((refutation, COUNTER_SCORE)));
+    }
+
+    #[test]
+    fn test_delta_pruning() {
+        let mut data = SearchData::new();
+        data.silent = true;
+        let mut nodes_searched = |fen: &str, margin: i32| {
+            let board = Board::from_fen(fen);
+            let alpha = data.evaluate(&board) + margin;
+            let nodes = data.nodes;
+            data.tt.clear();
+            quiescence(&board, alpha, alpha + 1, &mut data);
+            data.nodes - nodes
+        };
+
+        // Winning the pawn cannot make up for the queen and rook down
+        let fen = "rq2k3/8/8/3p4/4P3/8/8/4K1N1 w - - 0 1";
+        assert_eq!(nodes_searched(fen, 400), 0);
+        assert!(nodes_searched(fen, 100) > 0);
+
+        // Promotions and positions with few pieces left are always searched
+        assert!(nodes_searched("rq2k3/6P1/8/3p4/4P3/8/8/4K1N1 w - - 0 1", 1000) > 0);
+        assert!(nodes_searched("4k3/8/8/3p4/4P3/8/8/4K1N1 w - - 0 1", 400) > 0);
+    }
+
+    #[test]
+    fn test_search_limits() {
+        let mut data = SearchData::new();
+        data.silent = true;
+
+        // Stops as soon as the mate is found, without going on to prove it is the shortest
+        let board = Board::from_fen("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1");
+        find_best_move(&board, &SearchLimits::new().mate(1), &mut data);
+        assert_eq!(data.best_move.to_string(), "d1d8");
+        assert_eq!(data.eval, MATE - 1);
+        assert!(data.depth <= 3, "depth {}", data.depth);
+
+        // Only the given root moves are searched, however bad they are
+        let g1f1 = board.find_move("g1f1").unwrap();
+        let h2h3 = board.find_move("h2h3").unwrap();
+        data.clear();
+        let limits = SearchLimits::new().depth(5).search_moves(vec![g1f1, h2h3]);
+        find_best_move(&board, &limits, &mut data);
+        assert!([g1f1, h2h3].contains(&data.best_move));
+        assert!(data.eval < MATE_BOUND);
+        assert!(data.last_pv.as_slice().first() != Some(&board.find_move("d1d8").unwrap()));
+    }
+
+    #[test]
+    fn test_terminal_positions() {
+        let mut data = SearchData::new();
+        data.silent = true;
+
+        // Quiescence knows mates and, with only pawns left, stalemates
+        let mated = Board::from_fen("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1");
+        assert_eq!(quiescence(&mated, -INF, INF, &mut data), -MATE);
+        let stalemated = Board::from_fen("k7/p1K5/P7/8/8/8/8/8 b - - 0 1");
+        assert_eq!(quiescence(&stalemated, -INF, INF, &mut data), DRAW);
+        let evasion = Board::from_fen("R5k1/5pp1/8/8/8/8/8/6K1 b - - 0 1");
+        assert!(quiescence(&evasion, -INF, INF, &mut data) > -MATE_BOUND);
+
+        // Winning the queen stalemates, which a search at the horizon used to take for a rook up
+        let board = Board::from_fen("k7/p1K5/P7/8/8/7q/8/7R w - - 0 1");
+        for depth in 1..=3 {
+            data.clear();
+            find_best_move(&board, &SearchLimits::new().depth(depth), &mut data);
+            assert_eq!(data.best_move.to_string(), "h1h3", "depth {depth}");
+            assert_eq!(data.eval, DRAW, "depth {depth}");
+        }
+
+        // Mates keep their distance from the root when they come from the TT at another ply
+        assert_eq!(score_from_tt(score_to_tt(MATE - 7, 3), 5), MATE - 9);
+        assert_eq!(score_from_tt(score_to_tt(4 - MATE, 4), 2), 2 - MATE);
+        assert_eq!(score_from_tt(score_to_tt(150, 4), 2), 150);
+        data.clear();
+        let board = Board::from_fen("k7/8/2K5/8/8/8/8/7R w - - 0 1");
+        let mut evals = Vec::new();
+        for depth in 4..=8 {
+            find_best_move(&board, &SearchLimits::new().depth(depth), &mut data);
+            evals.push(data.eval);
+        }
+        assert!(
+            evals
+                .iter()
+                .all(|&eval| eval == evals[0] && eval > MATE_BOUND),
+            "{evals:?}"
+        );
+    }
+
+    #[test]
+    fn test_claimable_draw() {
+        // White is a queen up, but the position already appeared twice
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1");
+        let mut data = SearchData::new();
+        data.silent = true;
+        for m in ["d1d2", "e8d8", "d2d1", "d8e8"].repeat(2) {
+            data.stack.push(board.hash.0);
+            let m = board.find_move(m).unwrap();
+            board.make_move(m);
+        }
+
+        assert_eq!(data.repetitions(&board), 2);
+        find_best_move(&board, &SearchLimits::new(), &mut data);
+        assert_eq!(data.eval, DRAW);
+        assert!(board.is_legal(data.best_move));
+    }
+
+    #[test]
+    fn test_disabled_heuristics() {
+        // A middlegame deep enough for ProbCut, and pawns about to promote
+        let positions = [
+            (
+                "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+                7,
+            ),
+            (
+                "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+                4,
+            ),
+        ];
+        let search = |disabled: Option<Heuristic>| {
+            positions.map(|(fen, depth)| {
+                let board = Board::from_fen(fen);
+                let mut data = SearchData::new();
+                data.silent = true;
+                data.deterministic = true;
+                if let Some(heuristic) = disabled {
+                    data.set_disabled(heuristic, true);
+                }
+                // Histories of a long game, low enough for history pruning
+                data.history.score = [[-4000; 64]; 12];
+                find_best_move(&board, &SearchLimits::new().depth(depth), &mut data);
+                assert!(board.is_legal(data.best_move));
+                data.nodes
+            })
+        };
+
+        // Every option changes the tree searched, but only in dev builds
+        let nodes = search(None);
+        for heuristic in Heuristic::ALL {
+            let option = heuristic.option().to_ascii_lowercase();
+            assert_eq!(Heuristic::from_option(&option), Some(heuristic));
+            let changed = search(Some(heuristic)) != nodes;
+            assert_eq!(changed, cfg!(feature = "dev"), "{heuristic:?}");
+        }
+        assert_eq!(Heuristic::from_option("Disable_Everything"), None);
+    }
+
+    #[test]
+    fn test_fail_hard() {
+        let root_score = |fen: &str, depth, alpha, beta, fail_hard| {
+            let mut data = SearchData::new();
+            data.deterministic = true;
+            data.silent = true;
+            data.fail_hard = fail_hard;
+            data.control.start(u64::MAX, u128::MAX, u128::MAX);
+            negamax(&Board::from_fen(fen), depth, alpha, beta, false, &mut data)
+        };
+
+        // A window only a mate beats, with a mate in one
+        let mate = "4k3/8/4K3/8/8/8/8/R7 w - - 0 1";
+        assert_eq!(root_score(mate, 3, MATE - 200, MATE - 100, false), MATE - 1);
+        assert_eq!(
+            root_score(mate, 3, MATE - 200, MATE - 100, true),
+            MATE - 100
+        );
+
+        // Failing hard never leaves the window, failing soft does as soon as the root fails
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let exact = root_score(fen, 5, -INF, INF, false);
+        let mut outside = 0;
+        for (alpha, beta) in [(exact - 300, exact - 200), (exact + 200, exact + 300)] {
+            let hard = root_score(fen, 5, alpha, beta, true);
+            assert!((alpha..=beta).contains(&hard), "[{alpha}, {beta}] {hard}");
+            let soft = root_score(fen, 5, alpha, beta, false);
+            outside += usize::from(!(alpha..=beta).contains(&soft));
+        }
+        assert!(outside > 0);
+
+        // Reported as bounds when the root fails, flipped for black from white's point of view
+        let mut data = SearchData::new();
+        data.depth = 7;
+        assert!(data
+            .info_line(35, Bound::Lower)
+            .contains(" score cp 35 lowerbound "));
+        assert!(data
+            .info_line(35, Bound::Exact)
+            .contains(" score cp 35 time "));
+        data.white_pov = true;
+        data.root_side = crate::piece::Colour::Black;
+        assert!(data
+            .info_line(35, Bound::Lower)
+            .contains(" score cp -35 upperbound "));
+        assert!(data
+            .info_line(MATE - 3, Bound::Upper)
+            .contains(" score mate -2 lowerbound "));
+    }
+
+    #[test]
+    fn test_root_moves() {
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let mut data = SearchData::new();
+        data.deterministic = true;
+        data.silent = true;
+        find_best_move(&board, &SearchLimits::new().depth(7), &mut data);
+
+        // Every legal move gets its share of the nodes, and only the best one an exact score
+        let legal = board
+            .generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .filter(|&m| board.is_legal(m))
+            .count();
+        assert_eq!(data.root_moves.len(), legal);
+        let nodes: u64 = data.root_moves.iter().map(|rm| rm.nodes).sum();
+        assert_eq!(nodes, data.nodes);
+        let exact: Vec<&RootMove> = data
+            .root_moves
+            .iter()
+            .filter(|rm| rm.depth == 7 && rm.bound == Bound::Exact)
+            .collect();
+        assert_eq!(exact.len(), 1);
+        assert_eq!((exact[0].m, exact[0].score), (data.best_move, data.eval));
+
+        let info = data.root_moves_info();
+        assert_eq!(info.len(), legal);
+        assert!(info[0].starts_with(&format!("info string rootmoves {} depth 7", data.best_move)));
+        assert!(info[1..].iter().all(|line| line.contains(" upperbound ")));
+    }
+
+    #[test]
+    fn test_repetition_order() {
+        // White is a queen up and d2d1 goes back to a position already played
+        let mut board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1");
+        let mut data = SearchData::new();
+        data.silent = true;
+        for m in ["d1d2", "e8f8", "d2d1", "f8e8", "d1d2", "e8f8"] {
+            data.stack.push(board.hash.0);
+            board.make_move(board.find_move(m).unwrap());
+        }
+
+        let tt_move = board.find_move("d2d1").unwrap();
+        let mut picker = MovePicker::new::<true>(&board);
+        picker.score_moves(&board, Some(tt_move), &data);
+        data.stack.push(board.hash.0);
+        picker.demote_repetitions(&board, &data);
+        data.stack.pop();
+        let picked: Vec<Move> = picker.map(|(m, _)| m).collect();
+        assert_eq!(picked.last(), Some(&tt_move));
+        assert_eq!(picked.iter().filter(|&&m| m == tt_move).count(), 1);
+
+        data.tt
+            .insert(board.hash.0, Bound::Lower, tt_move, 0, 1, false);
+        find_best_move(&board, &SearchLimits::new().depth(6), &mut data);
+        assert!(data.eval >= REPETITION_AVOID_SCORE);
+        assert_ne!(data.best_move, tt_move);
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let board =
+            Board::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+        let run = || {
+            let mut data = SearchData::new();
+            data.deterministic = true;
+            data.silent = true;
+            let limits = SearchLimits::new().movetime(0).nodes(30_000); // Would stop at once if the clock was read
+            find_best_move(&board, &limits, &mut data);
+            (data.best_move, data.eval, data.nodes, data.depth)
+        };
+
+        let first = run();
+        assert!(first.2 >= 30_000);
+        assert_eq!(run(), first);
+    }
+
+    #[test]
+    fn test_contempt() {
+        // Every move reaches insufficient material
+        let board = Board::from_fen("8/8/8/4k3/8/8/8/3nK3 w - - 0 1");
+        for contempt in [0, 30, -30] {
+            let mut data = SearchData::new();
+            data.silent = true;
+            data.contempt = contempt;
+            find_best_move(&board, &SearchLimits::new().depth(4), &mut data);
+            assert_eq!(data.eval, -contempt);
+        }
+
+        // A repetition is avoided when it is worse than the alternative
+        let mut board = Board::default();
+        let mut data = SearchData::new();
+        data.silent = true;
+        data.contempt = 100;
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1"] {
+            data.stack.push(board.hash.0);
+            let m = board.find_move(m).unwrap();
+            board.make_move(m);
+        }
+        find_best_move(&board, &SearchLimits::new().depth(4), &mut data);
+        assert_ne!(data.best_move.to_string(), "f6g8");
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, ignore = "timing only holds in release builds")]
+    fn test_movetime() {
+        // Tactical position with long quiescence searches, the limit must still be kept
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let mut data = SearchData::new();
+        data.silent = true;
+        find_best_move(&board, &SearchLimits::new().movetime(100), &mut data);
+
+        // Stopped by the clock rather than the depth, within the poll and stop latency
+        assert!(data.depth < MAX_DEPTH);
+        let elapsed = data.control.elapsed();
+        assert!(elapsed < 250, "{elapsed}ms");
+        assert!(board.is_legal(data.best_move));
+    }
+
+    #[test]
+    fn test_new_game_reproducible() {
+        let start = Board::default();
+        let other =
+            Board::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3");
+        let mut data = SearchData::new();
+        data.silent = true;
+
+        find_best_move(&start, &SearchLimits::new().depth(7), &mut data);
+        let first = (data.best_move, data.eval, data.nodes);
+
+        data.new_game();
+        find_best_move(&other, &SearchLimits::new().depth(7), &mut data);
+        data.new_game();
+        find_best_move(&start, &SearchLimits::new().depth(7), &mut data);
+        assert_eq!((data.best_move, data.eval, data.nodes), first);
+    }
+
+    #[test]
+    fn test_zugzwang() {
+        #[rustfmt::skip]
+        const ZUGZWANG: [(&str, &str); 2] = [
+            ("8/8/p1p5/1p5p/1P5p/8/PPP2K1p/4R1rk w - - 0 1", "e1f1"),
+            ("1q1k4/2Rr4/8/2Q3K1/8/8/8/8 w - - 0 1", "g5h6"),
+        ];
+
+        for (fen, best) in ZUGZWANG {
+            let (m, _) = search(fen, 200_000);
+            assert_eq!(m, best, "{fen}");
+        }
+    }
+
+    #[cfg(feature = "tt-fuzz")]
+    #[test]
+    fn test_tt_fuzz() {
+        // Hash moves, scores and bounds may be garbage, still only legal moves come out
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+        ];
+        let legal = |board: &Board, m: Move| board.is_pseudo_legal(m) && board.is_legal(m);
+
+        for (i, fen) in fens.iter().enumerate() {
+            let board = Board::from_fen(fen);
+            for seed in 0..4 {
+                let mut data = SearchData::new();
+                data.silent = true;
+                data.analyse_mode = seed % 2 == 0;
+                data.tt.fuzz = Some(crate::rng::Rng::new((i * 4 + seed) as u64));
+                find_best_move(&board, &SearchLimits::new().nodes(30_000), &mut data);
+
+                assert!(legal(&board, data.best_move), "{fen} seed {seed}");
+                let mut after = board;
+                after.make_move(data.best_move);
+                let ponder = data.ponder_move;
+                assert!(
+                    ponder == Move::NULL || legal(&after, ponder),
+                    "{fen} seed {seed}"
+                );
+
+                let mut pos = board;
+                for m in &data.ply_data[0].pv {
+                    assert!(legal(&pos, m), "{fen} seed {seed} pv {m}");
+                    pos.make_move(m);
+                }
+            }
+        }
+    }
+}
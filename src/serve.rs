@@ -0,0 +1,186 @@
+use crate::board::Board;
+use crate::limits::SearchLimits;
+use crate::search::{find_best_move, MAX_DEPTH};
+use crate::tables::{Bound, SearchData};
+use std::io::{BufRead, Write};
+use std::sync::mpsc;
+use std::sync::Mutex;
+
+/// Independent analysis request, limits which are not given are unbounded
+struct Job {
+    id: String,
+    board: Board,
+    depth: u8,
+    nodes: u64,
+    movetime: u128,
+}
+
+/// Parses `job <id> [depth <n>] [nodes <n>] [movetime <ms>] (startpos | fen <fen>)`, which
+/// needs at least one limit
+fn parse_job(line: &str) -> Result<Job, (String, String)> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let id = match parts[..] {
+        ["job", id, ..] => String::from(id),
+        _ => return Err((String::from("-"), String::from("expected job <id>"))),
+    };
+    let fail = |e: String| (id.clone(), e);
+
+    let mut job = Job {
+        id: id.clone(),
+        board: Board::default(),
+        depth: MAX_DEPTH,
+        nodes: u64::MAX,
+        movetime: u128::MAX,
+    };
+    let mut limited = false;
+    let mut i = 2;
+    loop {
+        match parts.get(i..i + 2) {
+            Some(["depth", n]) => {
+                job.depth = n
+                    .parse::<u8>()
+                    .map_err(|_| fail(format!("invalid depth {n}")))?;
+                job.depth = job.depth.clamp(1, MAX_DEPTH);
+            }
+            Some(["nodes", n]) => {
+                job.nodes = n.parse().map_err(|_| fail(format!("invalid nodes {n}")))?;
+            }
+            Some(["movetime", n]) => {
+                job.movetime = n
+                    .parse()
+                    .map_err(|_| fail(format!("invalid movetime {n}")))?;
+            }
+            _ => break,
+        }
+        limited = true;
+        i += 2;
+    }
+    if !limited {
+        return Err(fail(String::from(
+            "expected a depth, nodes or movetime limit",
+        )));
+    }
+
+    match parts.get(i..) {
+        Some(["startpos"]) => {}
+        Some(["fen", fen @ ..]) if fen.len() == 6 => {
+            job.board = Board::try_from_fen(&fen.join(" ")).map_err(fail)?;
+        }
+        _ => return Err(fail(String::from("expected startpos or fen <fen>"))),
+    }
+
+    Ok(job)
+}
+
+fn analyse(data: &mut SearchData, job: &Job) -> String {
+    data.clear();
+    data.tt.inc_age();
+    let limits = SearchLimits::new()
+        .depth(job.depth)
+        .nodes(job.nodes)
+        .movetime(job.movetime);
+    find_best_move(&job.board, &limits, data);
+
+    format!(
+        "result {} bestmove {} score {} depth {} nodes {}",
+        job.id,
+        data.best_move,
+        data.uci_score(data.eval, Bound::Exact),
+        data.depth.saturating_sub(1),
+        data.nodes
+    )
+}
+
+/// Runs the jobs read from `input` on `workers` threads, each with its own tables, and writes
+/// every result as soon as it is ready, so results may come out of order. Returns once the
+/// input ends or `quit` is read and every job has finished.
+pub fn serve<R: BufRead, W: Write + Send>(input: R, output: W, workers: usize) {
+    let output = Mutex::new(output);
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let receiver = Mutex::new(receiver);
+
+    std::thread::scope(|s| {
+        for _ in 0..workers.max(1) {
+            s.spawn(|| {
+                let mut data = SearchData::new();
+                data.silent = true;
+                loop {
+                    // The lock is released as soon as a job is taken
+                    let Ok(job) = receiver.lock().unwrap().recv() else {
+                        break;
+                    };
+                    let result = analyse(&mut data, &job);
+                    let mut out = output.lock().unwrap();
+                    let _ = writeln!(out, "{result}");
+                    let _ = out.flush();
+                }
+            });
+        }
+
+        for line in input.lines() {
+            let Ok(line) = line else { break };
+            match line.trim() {
+                "" => continue,
+                "quit" => break,
+                line => match parse_job(line) {
+                    Ok(job) => sender.send(job).unwrap(),
+                    Err((id, e)) => {
+                        let mut out = output.lock().unwrap();
+                        let _ = writeln!(out, "error {id} {e}");
+                        let _ = out.flush();
+                    }
+                },
+            }
+        }
+        drop(sender);
+    });
+}
+
+/// `oxide serve [workers]`, reading jobs from stdin, with one worker per core by default
+pub fn run(args: &[String]) -> Result<(), String> {
+    let workers = match args.first() {
+        Some(n) => n
+            .parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("Invalid number of workers: {n}"))?,
+        None => crate::threads::default_threads(),
+    };
+
+    serve(std::io::stdin().lock(), std::io::stdout(), workers);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve() {
+        let input = "job a depth 3 startpos\n\
+                     job b nodes 2000 fen 6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1\n\
+                     job c startpos\n\
+                     job d depth 2 fen 8/8 w - - 0 1\n\
+                     job f depth 1 fen 8/8/8/8/8/8/8/8 w - - 0 1\n\
+                     job g depth 1 fen r3k2r/8/8/8/8/8/8/R3K2R w KQkqX - 0 1\n\
+                     job h depth 2 startpos\n\
+                     quit\n\
+                     job e depth 1 startpos\n";
+        let mut output = Vec::new();
+        serve(input.as_bytes(), &mut output, 2);
+
+        let output = String::from_utf8(output).unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.sort_by_key(|l| l.split_whitespace().nth(1));
+        assert_eq!(lines.len(), 7, "{output}");
+        assert!(lines[0].starts_with("result a bestmove "));
+        assert!(lines[0].contains(" depth 3 "));
+        assert!(lines[1].starts_with("result b bestmove d1d8 score mate 1 "));
+        assert!(lines[2].starts_with("error c expected a depth"));
+        // Invalid positions are answered without bringing the server down
+        assert!(lines[3].starts_with("error d Invalid FEN, expected 8 ranks"));
+        assert!(lines[4].starts_with("error f Invalid FEN, 0 white kings"));
+        assert!(lines[5].starts_with("error g Invalid CastlingRights"));
+        assert!(lines[6].starts_with("result h bestmove "));
+    }
+}
@@ -0,0 +1,266 @@
+// SPRT and match statistics, using the normal approximation of the GSPRT as in fishtest.
+// https://www.chessprogramming.org/Sequential_Probability_Ratio_Test
+
+/// Default SPRT bounds and error rates for engine patches
+const ELO0: f64 = 0.0;
+const ELO1: f64 = 5.0;
+const ALPHA: f64 = 0.05;
+const BETA: f64 = 0.05;
+
+/// Quantile of the normal distribution for 95% confidence intervals
+const Z95: f64 = 1.959964;
+
+pub struct SprtParams {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Default for SprtParams {
+    fn default() -> Self {
+        Self {
+            elo0: ELO0,
+            elo1: ELO1,
+            alpha: ALPHA,
+            beta: BETA,
+        }
+    }
+}
+
+impl SprtParams {
+    /// Lower and upper LLR bounds for accepting H0 and H1 respectively
+    pub fn bounds(&self) -> (f64, f64) {
+        (
+            (self.beta / (1.0 - self.alpha)).ln(),
+            ((1.0 - self.beta) / self.alpha).ln(),
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SprtResult {
+    H0,
+    H1,
+    Continue,
+}
+
+/// Score distribution of a match: probabilities of every possible outcome with its score
+pub struct Outcomes {
+    samples: f64,
+    probs: Vec<(f64, f64)>, // (score, probability)
+}
+
+impl Outcomes {
+    /// Game results as wins, losses and draws
+    pub fn trinomial(wins: u64, losses: u64, draws: u64) -> Self {
+        Self::from_counts(&[(0.0, losses), (0.5, draws), (1.0, wins)])
+    }
+
+    /// Game pair results ordered from 0 to 2 points (LL, LD, WL+DD, WD, WW)
+    pub fn pentanomial(pairs: [u64; 5]) -> Self {
+        let counts: Vec<(f64, u64)> = pairs
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| (i as f64 / 4.0, n))
+            .collect();
+        Self::from_counts(&counts)
+    }
+
+    fn from_counts(counts: &[(f64, u64)]) -> Self {
+        let samples = counts.iter().map(|&(_, n)| n).sum::<u64>() as f64;
+        let probs = counts
+            .iter()
+            .map(|&(score, n)| (score, n as f64 / samples.max(1.0)))
+            .collect();
+
+        Self { samples, probs }
+    }
+
+    pub fn score(&self) -> f64 {
+        self.probs.iter().map(|&(s, p)| s * p).sum()
+    }
+
+    /// Variance of a single sample
+    pub fn variance(&self) -> f64 {
+        let mean = self.score();
+        self.probs
+            .iter()
+            .map(|&(s, p)| p * (s - mean).powi(2))
+            .sum()
+    }
+
+    /// Elo difference and the half width of its 95% confidence interval
+    pub fn elo(&self) -> (f64, f64) {
+        let score = self.score();
+        let error = Z95 * (self.variance() / self.samples).sqrt();
+        let lower = score_to_elo(score - error);
+        let upper = score_to_elo(score + error);
+
+        (score_to_elo(score), (upper - lower) / 2.0)
+    }
+
+    /// Likelihood of superiority
+    pub fn los(&self) -> f64 {
+        let stderr = (self.variance() / self.samples).sqrt();
+        if stderr == 0.0 {
+            return 0.5;
+        }
+
+        0.5 * (1.0 + erf((self.score() - 0.5) / (stderr * std::f64::consts::SQRT_2)))
+    }
+
+    /// Log likelihood ratio of H1 (elo1) against H0 (elo0)
+    pub fn llr(&self, params: &SprtParams) -> f64 {
+        let var = self.variance();
+        if var == 0.0 {
+            return 0.0;
+        }
+
+        let s0 = elo_to_score(params.elo0);
+        let s1 = elo_to_score(params.elo1);
+        self.samples * (s1 - s0) * (2.0 * self.score() - s0 - s1) / (2.0 * var)
+    }
+
+    pub fn sprt(&self, params: &SprtParams) -> SprtResult {
+        let (lower, upper) = params.bounds();
+        let llr = self.llr(params);
+        if llr >= upper {
+            SprtResult::H1
+        } else if llr <= lower {
+            SprtResult::H0
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+pub fn score_to_elo(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+pub fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+/// Error function approximation (Abramowitz and Stegun 7.1.26), max error 1.5e-7
+fn erf(x: f64) -> f64 {
+    const A: [f64; 5] = [
+        0.254829592,
+        -0.284496736,
+        1.421413741,
+        -1.453152027,
+        1.061405429,
+    ];
+    const P: f64 = 0.3275911;
+
+    let sign = x.signum();
+    let x = x.abs();
+    let t = 1.0 / (1.0 + P * x);
+    let poly = A.iter().rev().fold(0.0, |acc, &a| acc * t + a) * t;
+
+    sign * (1.0 - poly * (-x * x).exp())
+}
+
+pub fn report(outcomes: &Outcomes, params: &SprtParams) {
+    let (elo, error) = outcomes.elo();
+    let (lower, upper) = params.bounds();
+    let result = match outcomes.sprt(params) {
+        SprtResult::H0 => "H0 accepted",
+        SprtResult::H1 => "H1 accepted",
+        SprtResult::Continue => "continue",
+    };
+
+    println!("Elo: {elo:.2} +/- {error:.2} (95%)");
+    println!("LOS: {:.2}%", outcomes.los() * 100.0);
+    println!(
+        "LLR: {:.2} ({lower:.2}, {upper:.2}) [{:.2}, {:.2}] {result}",
+        outcomes.llr(params),
+        params.elo0,
+        params.elo1
+    );
+}
+
+/// Standalone mode for scripts:
+/// `sprt <wins> <losses> <draws> [elo0 elo1 alpha beta]`
+/// `sprt penta <ll> <ld> <dd+wl> <wd> <ww> [elo0 elo1 alpha beta]`
+pub fn run(args: &[String]) -> Result<(), String> {
+    let (outcomes, rest) = if args.first().map(String::as_str) == Some("penta") {
+        let counts = parse::<u64>(&args[1..], 5)?;
+        (
+            Outcomes::pentanomial([counts[0], counts[1], counts[2], counts[3], counts[4]]),
+            &args[6..],
+        )
+    } else {
+        let counts = parse::<u64>(args, 3)?;
+        println!(
+            "Games: {} W: {} L: {} D: {}",
+            counts[0] + counts[1] + counts[2],
+            counts[0],
+            counts[1],
+            counts[2]
+        );
+        (
+            Outcomes::trinomial(counts[0], counts[1], counts[2]),
+            &args[3..],
+        )
+    };
+
+    let params = if rest.is_empty() {
+        SprtParams::default()
+    } else {
+        let values = parse::<f64>(rest, 4)?;
+        SprtParams {
+            elo0: values[0],
+            elo1: values[1],
+            alpha: values[2],
+            beta: values[3],
+        }
+    };
+
+    report(&outcomes, &params);
+    Ok(())
+}
+
+fn parse<T: std::str::FromStr>(args: &[String], count: usize) -> Result<Vec<T>, String> {
+    if args.len() < count {
+        return Err(format!("Expected {count} values"));
+    }
+
+    args[..count]
+        .iter()
+        .map(|a| a.parse().map_err(|_| format!("Invalid value: {a}")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elo_conversions() {
+        assert!(score_to_elo(0.5).abs() < 1e-9);
+        assert!((elo_to_score(score_to_elo(0.64)) - 0.64).abs() < 1e-9);
+        assert!((erf(1.0) - 0.842700793).abs() < 1e-6);
+        assert!((erf(-0.5) + 0.520499878).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_sprt() {
+        let params = SprtParams::default();
+        let even = Outcomes::trinomial(1000, 1000, 2000);
+        assert!(even.elo().0.abs() < 1e-9);
+        assert!((even.los() - 0.5).abs() < 1e-9);
+
+        let winning = Outcomes::trinomial(6000, 5000, 9000);
+        assert_eq!(winning.sprt(&params), SprtResult::H1);
+        assert!(winning.los() > 0.99);
+
+        let losing = Outcomes::trinomial(5000, 5400, 9000);
+        assert_eq!(losing.sprt(&params), SprtResult::H0);
+
+        let penta = Outcomes::pentanomial([100, 400, 1000, 450, 120]);
+        assert!(penta.elo().0 > 0.0);
+    }
+}
@@ -1,13 +1,24 @@
 use crate::board::Board;
+use crate::checkpoint::Resume;
+use crate::control::{
+    SearchControl, MAX_POLL_INTERVAL, MIN_POLL_INTERVAL, POLL_INTERVAL, POLL_PERIOD_US,
+};
+use crate::engine::CancellationToken;
+use crate::limits::SearchLimits;
 use crate::moves::{Move, MoveList};
-use crate::piece::Colour;
-use crate::search::{
-    HISTORY_FACTOR, HISTORY_MAX_BONUS, HISTORY_OFFSET, INF, LMR_BASE, LMR_DIV, MATE, MAX_DEPTH,
-    MAX_HISTORY,
+use crate::piece::{Colour, Piece, PieceType};
+#[cfg(feature = "tt-fuzz")]
+use crate::rng::Rng;
+use crate::search::{is_mate, INF, LMR_BASE, LMR_DIV, MATE, MAX_DEPTH, SCORE_NONE};
+use crate::square::Square;
+use crate::tunables::{
+    ASP_DELTA_MIN, ASP_DELTA_SCALE, ASP_LIMIT, HISTORY_AGING, HISTORY_FACTOR, HISTORY_MAX_BONUS,
+    HISTORY_OFFSET, MAX_HISTORY,
 };
-use std::time::Instant;
+use crate::uci_println;
+use std::sync::Arc;
 
-use super::network::EvalTable;
+use super::network::{AccumulatorStack, EvalTable, NUM_BUCKETS};
 use super::search::MAX_CAP_HISTORY;
 
 /// Transposition Table
@@ -49,30 +60,115 @@ impl TTEntry {
     }
 }
 
+/// Soft limit after panicking, as a multiple of the allocated time
+const PANIC_SOFT_SCALE: u128 = 2;
+
+/// Bounds in percent of the soft limit scaling of clock games by the stability of the PV
+const MIN_SOFT_SCALE: u128 = 50;
+const MAX_SOFT_SCALE: u128 = 200;
+
+/// Easy move: the opponent played the predicted reply and the move expected from the last
+/// search took this percent of the root nodes from this depth on, so the soft limit of clock
+/// games drops to `EASY_MOVE_SCALE` percent
+const EASY_MOVE_SHARE: u64 = 85;
+const EASY_MOVE_DEPTH: u8 = 8;
+const EASY_MOVE_SCALE: u128 = 30;
+
+/// Average score change into every depth before any game has been searched
+const INITIAL_SCORE_DELTA: i32 = 45;
+
+/// Iterations predicted to end after twice the soft limit are not started, as the hard limit
+/// would most likely cut them before they complete
+pub const DEFAULT_ITERATION_GATE: u128 = 200;
+
+/// Largest `Hash` the UCI option accepts
+pub const MAX_HASH_MB: usize = 4096;
+
+/// Table sizes of the low memory mode, which keeps the heap of a search within a few MB for
+/// mobile GUIs and wasm builds
+pub const DEFAULT_HASH_MB: usize = 32;
+pub const LOW_MEMORY_HASH_MB: usize = 1;
+pub const LOW_MEMORY_MAX_HASH_MB: usize = 4;
+const LOW_MEMORY_EVAL_ENTRIES: usize = 1; // Refresh table entries of each perspective
+const LOW_MEMORY_ACC_PLIES: usize = 48;
+
+/// Longest time without output before the main thread reports progress within an iteration
+const HEARTBEAT_PERIOD_US: u64 = 1_000_000;
+
+/// Changes every key while Black is the root side and contempt is on, as draw scores stored
+/// for one root side are wrong for the other
+const CONTEMPT_SALT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// The entries are only allocated on `isready` or when first used, so resizing the table before
+/// that costs nothing
 pub struct TranspositionTable {
-    pub tt: Vec<TTEntry>,
+    pub tt: Vec<TTEntry>, // Empty until allocated
+    len: usize,
     age: u8,
+    salt: u64,
+    #[cfg(feature = "tt-fuzz")]
+    pub fuzz: Option<Rng>, // Corrupts written entries when set
 }
 
 impl TranspositionTable {
     pub fn with_size_mb(mb: usize) -> Self {
         let bytes = mb * 1_048_576;
         let entry_sz = std::mem::size_of::<TTEntry>();
-        let len = (bytes / entry_sz).next_power_of_two();
         Self {
-            tt: vec![TTEntry::default(); len],
+            tt: Vec::new(),
+            len: (bytes / entry_sz).next_power_of_two(),
             age: 0,
+            salt: 0,
+            #[cfg(feature = "tt-fuzz")]
+            fuzz: None,
         }
     }
 
+    /// Table holding the entries of a saved one, which may have a different size
+    pub fn from_entries(tt: Vec<TTEntry>, age: u8) -> Self {
+        Self {
+            len: tt.len(),
+            tt,
+            age,
+            salt: 0,
+            #[cfg(feature = "tt-fuzz")]
+            fuzz: None,
+        }
+    }
+
+    /// Allocates the entries if they are not yet
+    #[inline]
+    pub fn allocate(&mut self) {
+        if self.tt.is_empty() {
+            self.tt = zeroed_entries(self.len);
+        }
+    }
+
+    pub fn is_allocated(&self) -> bool {
+        !self.tt.is_empty()
+    }
+
+    pub fn age(&self) -> u8 {
+        self.age
+    }
+
     fn idx(&self, hash: u64) -> usize {
         // (Read Lemire Blog for explanation | Carp)
         ((hash as u128 * self.tt.len() as u128) >> 64) as usize
     }
 
+    pub fn set_contempt_side(&mut self, contempt: i32, root_side: Colour) {
+        self.salt = if contempt != 0 && root_side == Colour::Black {
+            CONTEMPT_SALT
+        } else {
+            0
+        };
+    }
+
     pub fn probe(&self, hash: u64) -> Option<&TTEntry> {
-        let e = &self.tt[self.idx(hash)];
-        (e.key == hash).then_some(e)
+        let hash = hash ^ self.salt;
+        // Nothing is found before the table is allocated, when `idx` is always 0
+        self.tt.get(self.idx(hash)).filter(|e| e.key == hash)
     }
 
     pub fn clear(&mut self) {
@@ -93,6 +189,9 @@ impl TranspositionTable {
         depth: u8,
         pv: bool,
     ) {
+        debug_assert!(value.abs() <= INF, "TT score {value} out of range");
+        self.allocate();
+        let hash = hash ^ self.salt;
         let idx = self.idx(hash);
         let slot = &mut self.tt[idx];
         let same = slot.key == hash;
@@ -114,42 +213,103 @@ impl TranspositionTable {
                 flags: TTEntry::make_flags(depth, bound),
             };
         }
+
+        #[cfg(feature = "tt-fuzz")]
+        self.corrupt(idx);
+    }
+
+    /// Now and then garbles the entry at `idx` as a torn write would: the key is kept, but the
+    /// move, score and flags may be any bits, the move kind included
+    #[cfg(feature = "tt-fuzz")]
+    fn corrupt(&mut self, idx: usize) {
+        let Some(rng) = self.fuzz.as_mut() else {
+            return;
+        };
+        if rng.below(4) != 0 {
+            return;
+        }
+
+        let slot = &mut self.tt[idx];
+        slot.best_move = Move(rng.next_u64() as u16);
+        slot.value = rng.below(2 * MATE as usize + 1) as i32 - MATE;
+        slot.flags = rng.next_u64() as u8;
+    }
+}
+
+/// Zeroed memory is requested from the system, which only touches each page when first written,
+/// so even large tables are ready at once
+#[cfg(not(feature = "safe-only"))]
+fn zeroed_entries(len: usize) -> Vec<TTEntry> {
+    let layout = std::alloc::Layout::array::<TTEntry>(len).unwrap();
+    // SAFETY: all zero bytes are a valid empty `TTEntry`, and the layout is the one of a `Vec`
+    // of `len` entries
+    unsafe {
+        let ptr = std::alloc::alloc_zeroed(layout).cast::<TTEntry>();
+        if ptr.is_null() {
+            std::alloc::handle_alloc_error(layout);
+        }
+        Vec::from_raw_parts(ptr, len, len)
     }
 }
 
+#[cfg(feature = "safe-only")]
+fn zeroed_entries(len: usize) -> Vec<TTEntry> {
+    vec![TTEntry::default(); len]
+}
+
 /// History Gravity bonus
 /// https://www.chessprogramming.org/History_Heuristic
 pub fn history_bonus(depth: u8) -> i16 {
-    HISTORY_MAX_BONUS.min(HISTORY_FACTOR * depth as i16 - HISTORY_OFFSET)
+    HISTORY_MAX_BONUS
+        .get()
+        .min(HISTORY_FACTOR.get() * depth as i32 - HISTORY_OFFSET.get()) as i16
 }
 
-/// Taper history so it clamps to MAX
+/// Taper history so it clamps to max
 /// From Carp, which in turn is from talkchess
-const fn taper_bonus<const MAX: i32>(bonus: i16, old: i16) -> i16 {
+const fn taper_bonus(bonus: i16, old: i16, max: i32) -> i16 {
     let o = old as i32;
     let b = bonus as i32;
 
-    (o + b - (o * b.abs()) / MAX) as i16
+    (o + b - (o * b.abs()) / max) as i16
 }
 
+/// Quiet move history indexed by the moving piece, whose colour gives the side, and the
+/// destination square
 pub struct HistoryTable {
-    pub score: [[[i16; 64]; 64]; 2], // [side][src][dest]
+    pub score: [[i16; 64]; 12], // [piece][dest]
 }
 
 impl HistoryTable {
+    /// Score of the quiet move `m` of `board`
+    #[inline]
+    pub fn get(&self, board: &Board, m: Move) -> i16 {
+        self.score[board.piece_at(m.get_source()) as usize][m.get_dest().index()]
+    }
+
     /// Updating history values, for the cutoff move a bonus and for the rest of the quiets tried,
     /// a history maluse, using history gravity formula
-    pub fn update(&mut self, side: Colour, src: usize, dest: usize, bonus: i16, quiets: &[Move]) {
-        let c_bonus = bonus.clamp(-MAX_HISTORY as i16, MAX_HISTORY as i16);
+    pub fn update(&mut self, board: &Board, m: Move, bonus: i16, quiets: &[Move]) {
+        let max = MAX_HISTORY.get();
+        let c_bonus = bonus.clamp(-max as i16, max as i16);
 
         // Update the current best move with positive bonus
-        let old_score = &mut self.score[side as usize][src][dest];
-        *old_score = taper_bonus::<MAX_HISTORY>(c_bonus, *old_score);
+        let old_score =
+            &mut self.score[board.piece_at(m.get_source()) as usize][m.get_dest().index()];
+        *old_score = taper_bonus(c_bonus, *old_score, max);
 
         // Update all other quiet moves with negative bonus
-        for m in quiets {
-            let old = &mut self.score[side as usize][m.get_source().index()][m.get_dest().index()];
-            *old = taper_bonus::<MAX_HISTORY>(-c_bonus, *old);
+        for q in quiets {
+            let old =
+                &mut self.score[board.piece_at(q.get_source()) as usize][q.get_dest().index()];
+            *old = taper_bonus(-c_bonus, *old, max);
+        }
+    }
+
+    /// Scales every score by `keep` out of 1024
+    pub fn age(&mut self, keep: i32) {
+        for score in self.score.as_flattened_mut() {
+            *score = (i32::from(*score) * keep / 1024) as i16;
         }
     }
 }
@@ -157,39 +317,102 @@ impl HistoryTable {
 impl Default for HistoryTable {
     fn default() -> Self {
         Self {
-            score: [[[0; 64]; 64]; 2],
+            score: [[0; 64]; 12],
+        }
+    }
+}
+
+/// Quiet reply which refuted a move, indexed like the history by the piece which made it and
+/// its destination
+pub struct CounterMoveTable {
+    pub moves: [[Move; 64]; 12], // [piece][dest]
+}
+
+impl CounterMoveTable {
+    /// Refutation of the move played at `prev`, NULL after a null move or when none is known
+    #[inline]
+    pub fn get(&self, prev: &PlyData) -> Move {
+        if prev.is_null() {
+            return Move::NULL;
+        }
+        self.moves[prev.moved as usize][prev.played.get_dest().index()]
+    }
+
+    pub fn set(&mut self, prev: &PlyData, m: Move) {
+        if !prev.is_null() {
+            self.moves[prev.moved as usize][prev.played.get_dest().index()] = m;
+        }
+    }
+}
+
+impl Default for CounterMoveTable {
+    fn default() -> Self {
+        Self {
+            moves: [[Move::NULL; 64]; 12],
         }
     }
 }
 
+/// Number of piece types that can be captured, every type but the king
+const CAPTURED_TYPES: usize = 5;
+
 pub struct CaptureHistoryTable {
-    pub score: [[[i16; 5]; 64]; 12], // [capturing_piece][dest][captured]
+    pub score: [[[i16; CAPTURED_TYPES]; 64]; 6], // [capturing_type][dest][captured_type]
 }
 
 impl CaptureHistoryTable {
+    /// Score of the capture `m`, which must be a capture in `board`
+    #[inline]
+    pub fn get(&self, board: &Board, m: Move) -> i16 {
+        let (piece, dest, captured) = Self::index(board, m);
+        self.score[piece][dest][captured]
+    }
+
     pub fn update(&mut self, board: &Board, m: Move, bonus: i16, captures: &[Move]) {
         let c_bonus = bonus.clamp(-MAX_CAP_HISTORY as i16, MAX_CAP_HISTORY as i16);
 
         // Update the best move with a positive bonus
         if m.get_type().is_capture() {
-            let old_score = &mut self.score[board.piece_at(m.get_source()) as usize]
-                [m.get_dest().index()][board.capture_piece(m).index()];
-            *old_score = taper_bonus::<MAX_CAP_HISTORY>(c_bonus, *old_score);
+            let (piece, dest, captured) = Self::index(board, m);
+            let old_score = &mut self.score[piece][dest][captured];
+            *old_score = taper_bonus(c_bonus, *old_score, MAX_CAP_HISTORY);
         }
 
         // Update all other capture moves with negative bonus
         for mov in captures {
-            let old = &mut self.score[board.piece_at(mov.get_source()) as usize]
-                [mov.get_dest().index()][board.capture_piece(*mov).index()];
-            *old = taper_bonus::<MAX_CAP_HISTORY>(-c_bonus, *old);
+            let (piece, dest, captured) = Self::index(board, *mov);
+            let old = &mut self.score[piece][dest][captured];
+            *old = taper_bonus(-c_bonus, *old, MAX_CAP_HISTORY);
         }
     }
+
+    /// Scales every score by `keep` out of 1024
+    pub fn age(&mut self, keep: i32) {
+        for score in self.score.as_flattened_mut().as_flattened_mut() {
+            *score = (i32::from(*score) * keep / 1024) as i16;
+        }
+    }
+
+    /// En passant captures a pawn on a square other than the destination, and a capturing
+    /// promotion is keyed by the pawn that moves, not by the piece it becomes
+    #[inline]
+    fn index(board: &Board, m: Move) -> (usize, usize, usize) {
+        debug_assert!(m.get_type().is_capture());
+        let captured = board.capture_piece(m).piece_type();
+        debug_assert!(captured != PieceType::King);
+
+        (
+            board.piece_at(m.get_source()).piece_type().index(),
+            m.get_dest().index(),
+            captured.index(),
+        )
+    }
 }
 
 impl Default for CaptureHistoryTable {
     fn default() -> Self {
         Self {
-            score: [[[0; 5]; 64]; 12],
+            score: [[[0; CAPTURED_TYPES]; 64]; 6],
         }
     }
 }
@@ -224,25 +447,178 @@ impl LmrTable {
     }
 }
 
+impl Default for LmrTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub const MAX_PLY: usize = 128;
 
-#[derive(Clone, Copy, Default)]
+/// Search heuristics which the `Disable_*` options of `dev` builds turn off one at a time, to
+/// bisect a bug to one of them
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Heuristic {
+    Aspiration,
+    Nmp,
+    Lmr,
+    Rfp,
+    Razoring,
+    ProbCut,
+    Iir,
+    HistoryPruning,
+    SeePruning,
+    DeltaPruning,
+    UnderpromotionPruning,
+    Extensions,
+}
+
+impl Heuristic {
+    pub const ALL: [Heuristic; 12] = [
+        Heuristic::Aspiration,
+        Heuristic::Nmp,
+        Heuristic::Lmr,
+        Heuristic::Rfp,
+        Heuristic::Razoring,
+        Heuristic::ProbCut,
+        Heuristic::Iir,
+        Heuristic::HistoryPruning,
+        Heuristic::SeePruning,
+        Heuristic::DeltaPruning,
+        Heuristic::UnderpromotionPruning,
+        Heuristic::Extensions,
+    ];
+
+    /// Name of its UCI option
+    pub const fn option(self) -> &'static str {
+        match self {
+            Heuristic::Aspiration => "Disable_Aspiration",
+            Heuristic::Nmp => "Disable_NMP",
+            Heuristic::Lmr => "Disable_LMR",
+            Heuristic::Rfp => "Disable_RFP",
+            Heuristic::Razoring => "Disable_Razoring",
+            Heuristic::ProbCut => "Disable_ProbCut",
+            Heuristic::Iir => "Disable_IIR",
+            Heuristic::HistoryPruning => "Disable_HistoryPruning",
+            Heuristic::SeePruning => "Disable_SEEPruning",
+            Heuristic::DeltaPruning => "Disable_DeltaPruning",
+            Heuristic::UnderpromotionPruning => "Disable_UnderpromotionPruning",
+            Heuristic::Extensions => "Disable_Extensions",
+        }
+    }
+
+    /// Heuristic turned off by the option `name`, ignoring case
+    pub fn from_option(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|h| h.option().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Search statistics of a root move, kept for the whole search
+#[derive(Clone, Copy)]
+pub struct RootMove {
+    pub m: Move,
+    pub nodes: u64,
+    pub score: i32,
+    pub bound: Bound,
+    pub depth: u8, // Iteration of the score, 0 until one completes the search of the move
+}
+
+#[derive(Clone, Copy)]
 pub struct PlyData {
     pub killer: Move,
     pub eval: i32,
     pub pv: MoveList,
+    pub played: Move,           // Move made from this ply, NULL for null moves
+    pub moved: Piece,           // Piece moved by `played`
+    pub captured: Piece,        // Piece captured by `played`, Empty if quiet
+    pub extensions: u8,         // Extensions accumulated from the root to this ply
+    pub threat: Option<Square>, // Piece left hanging when a null move failed to cut
+}
+
+impl Default for PlyData {
+    fn default() -> Self {
+        Self {
+            killer: Move::NULL,
+            eval: 0,
+            pv: MoveList::default(),
+            played: Move::NULL,
+            moved: Piece::Empty,
+            captured: Piece::Empty,
+            extensions: 0,
+            threat: None,
+        }
+    }
+}
+
+impl PlyData {
+    pub fn record(&mut self, board: &Board, m: Move) {
+        self.played = m;
+        self.moved = board.piece_at(m.get_source());
+        self.captured = if m.get_type().is_capture() {
+            board.capture_piece(m)
+        } else {
+            Piece::Empty
+        };
+    }
+
+    pub fn record_null(&mut self) {
+        self.played = Move::NULL;
+        self.moved = Piece::Empty;
+        self.captured = Piece::Empty;
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.played == Move::NULL
+    }
 }
 
 pub struct SearchData {
     // Search Control
-    pub timing: Instant,
-    pub time_tp: u128,
-    pub stop: bool,
+    pub control: Arc<SearchControl>,
+    pub main_thread: bool, // Starts and reports the search, helpers share its control
+    pub control_started: bool, // The next search keeps the control, already started for it
+    pub limits: SearchLimits, // Of the current search, set by `find_best_move`
+    pub cancel: CancellationToken,
     pub depth: u8,
+    pub analyse_mode: bool,
+    pub white_pov: bool,
+    pub silent: bool,        // No info output, for searches other than the main one
+    pub deterministic: bool, // Limits searches by nodes only, never reading the clock
+    pub fail_hard: bool,     // Clamps every score into its window instead of failing soft
+    pub low_memory: bool,    // Tables shrunk by `set_low_memory`
+    pub root_moves_report: bool, // Prints the root move statistics after every iteration
+    pub chess960: bool,      // Castles are written as the king taking its rook
+    pub disabled: u16,       // Heuristics turned off by the `Disable_*` options, one bit each
+    pub contempt: i32,       // Centipawns a draw is worth less than zero to the root side
+    pub iteration_gate: u128, // Percent of the soft limit by which the next iteration must end
+    pub root_side: Colour,
+    pub root: Board, // Position of the current search, the start of crash dumps
+    pub resume: Option<Resume>, // Checkpointed analysis continued by the next search
 
     // Data
     pub ply: usize,
-    pub nodes: u64,
+    pub seldepth: usize,
+    pub best_move_changes: u32, // Root best move changes during the last iteration
+    pub last_pv: MoveList,      // PV of the last completed iteration, tried early by the next
+    pub stable_iterations: u8,  // Iterations in a row ending with the same best move
+    pub score_volatility: i32,  // Score difference between the last two iterations
+    score_deltas: [i32; MAX_DEPTH as usize + 1], // Average score change into each depth
+    pub best_move_share: u64,   // Percent of the root nodes of the last iteration on the best move
+    pub ponder_move: Move,      // Predicted reply to the best move
+    predicted: u64,             // Position after the best move and the predicted reply
+    easy_move: Move,            // Move expected from the last search when the prediction held
+    pub nodes: u64,             // Searched by this thread
+    root_node_cap: u64,         // Aborts the zero window search of a root move at this node count
+    published: u64,             // Part of `nodes` already added to the shared count
+    poll: u32,                  // Node checks left until the next poll of the limits
+    poll_interval: u32,
+    clock: u64,    // Microseconds since the start of the search, read at the last poll
+    reported: u64, // Microseconds since the start of the search at the last output
+    pub currmove: Move, // Root move being searched and its number, for progress reports
+    pub currmovenumber: usize,
+    pub root_moves: Vec<RootMove>,
     pub best_move: Move,
     pub eval: i32,
 
@@ -251,45 +627,158 @@ pub struct SearchData {
     pub ply_data: [PlyData; MAX_PLY],
     pub tt: TranspositionTable,
     pub cache: EvalTable,
+    pub accumulators: AccumulatorStack,
     pub history: HistoryTable,
     pub cap_history: CaptureHistoryTable,
+    pub counter_moves: CounterMoveTable,
     pub lmr_table: LmrTable,
 }
 
 impl SearchData {
+    /// Search data with the default table sizes, or those of the low memory mode in builds with
+    /// the `low-memory` feature
     pub fn new() -> Self {
-        Self {
-            timing: Instant::now(),
-            time_tp: 0,
-            stop: false,
+        let mut data = Self {
+            control: Arc::new(SearchControl::new()),
+            main_thread: true,
+            control_started: false,
+            limits: SearchLimits::new(),
+            cancel: CancellationToken::default(),
             depth: 0,
+            analyse_mode: false,
+            white_pov: false,
+            silent: false,
+            deterministic: false,
+            fail_hard: false,
+            low_memory: false,
+            root_moves_report: false,
+            chess960: false,
+            disabled: 0,
+            contempt: 0,
+            iteration_gate: DEFAULT_ITERATION_GATE,
+            root_side: Colour::White,
+            root: Board::default(),
+            resume: None,
 
             ply: 0,
+            seldepth: 0,
+            best_move_changes: 0,
+            last_pv: MoveList::default(),
+            stable_iterations: 0,
+            score_volatility: 0,
+            score_deltas: [INITIAL_SCORE_DELTA; MAX_DEPTH as usize + 1],
+            best_move_share: 0,
+            ponder_move: Move::NULL,
+            predicted: 0,
+            easy_move: Move::NULL,
             nodes: 0,
+            root_node_cap: u64::MAX,
+            published: 0,
+            poll: POLL_INTERVAL,
+            poll_interval: POLL_INTERVAL,
+            clock: 0,
+            reported: 0,
+            currmove: Move::NULL,
+            currmovenumber: 0,
+            root_moves: Vec::new(),
             best_move: Move::NULL,
-            eval: -INF,
+            eval: SCORE_NONE,
 
             stack: Vec::with_capacity(32),
             ply_data: [(); MAX_PLY].map(|_| PlyData::default()),
-            tt: TranspositionTable::with_size_mb(32),
+            tt: TranspositionTable::with_size_mb(DEFAULT_HASH_MB),
             cache: EvalTable::default(),
+            accumulators: AccumulatorStack::default(),
             history: HistoryTable::default(),
             cap_history: CaptureHistoryTable::default(),
+            counter_moves: CounterMoveTable::default(),
             lmr_table: LmrTable::new(),
+        };
+        if cfg!(feature = "low-memory") {
+            data.set_low_memory(true, DEFAULT_HASH_MB);
         }
+        data
     }
 
     pub fn start_search(&mut self) {
+        if self.main_thread && !std::mem::take(&mut self.control_started) {
+            self.control
+                .start(self.limits.nodes, self.limits.time, self.limits.time);
+        }
+
         self.depth = 1;
-        self.stop = false;
         self.best_move = Move::NULL;
         self.nodes = 0;
+        self.root_node_cap = u64::MAX;
+        self.published = 0;
+        self.poll = POLL_INTERVAL;
+        self.poll_interval = POLL_INTERVAL;
+        self.clock = 0;
+        self.reported = 0;
+        self.currmove = Move::NULL;
+        self.currmovenumber = 0;
+        self.root_moves.clear();
         self.ply = 0;
-        self.timing = Instant::now();
+        self.seldepth = 0;
+        self.best_move_changes = 0;
+        self.last_pv.clear();
+        self.ply_data[0].pv.clear();
+        self.stable_iterations = 0;
+        self.score_volatility = 0;
+        self.best_move_share = 0;
+    }
+
+    /// Takes the move the last search expected to play if the opponent answered with the
+    /// predicted reply, verified as the TT entry may have been overwritten by another position
+    pub fn start_easy_move(&mut self, board: &Board) {
+        self.easy_move = Move::NULL;
+        if self.predicted == board.hash.0 {
+            if let Some(entry) = self.tt.probe(board.hash.0) {
+                let m = entry.best_move;
+                if board.is_pseudo_legal(m) && board.is_legal(m) {
+                    self.easy_move = m;
+                }
+            }
+        }
+        self.predicted = 0;
+        self.ponder_move = Move::NULL;
+    }
+
+    /// Stores the reply predicted by the PV of the finished search and the position it leads to
+    pub fn predict(&mut self, root: &Board) {
+        self.extend_pv(root, 2);
+        let pv = self.ply_data[0].pv;
+        let [best, reply, ..] = pv.as_slice()[..] else {
+            return;
+        };
+        if best != self.best_move {
+            return;
+        }
+
+        let mut board = *root;
+        board.make_move(best);
+        board.make_move(reply);
+        self.ponder_move = reply;
+        self.predicted = board.hash.0;
+    }
+
+    /// The opponent played the predicted reply and the search keeps settling on the expected
+    /// move, spending most of its nodes on it
+    pub fn is_easy_move(&self) -> bool {
+        self.easy_move != Move::NULL
+            && self.best_move == self.easy_move
+            && self.depth >= EASY_MOVE_DEPTH
+            && self.best_move_share >= EASY_MOVE_SHARE
+    }
+
+    /// Stack entry of the move that led to the current ply, None at the root
+    pub fn previous(&self) -> Option<&PlyData> {
+        self.ply.checked_sub(1).map(|ply| &self.ply_data[ply])
     }
 
     pub fn push(&mut self, hash: u64) {
         self.ply += 1;
+        self.seldepth = self.seldepth.max(self.ply);
         self.stack.push(hash);
     }
 
@@ -298,16 +787,90 @@ impl SearchData {
         self.ply -= 1;
     }
 
+    /// Replaces the TT with an unallocated one of `mb_size` MB, at most `LOW_MEMORY_MAX_HASH_MB`
+    /// in the low memory mode
     pub fn resize_tt(&mut self, mb_size: usize) {
+        let mb_size = if self.low_memory {
+            mb_size.min(LOW_MEMORY_MAX_HASH_MB)
+        } else {
+            mb_size
+        };
         self.tt = TranspositionTable::with_size_mb(mb_size);
     }
 
+    /// Shrinks the tables to a 1MB TT, one refresh table entry per perspective and a shorter
+    /// accumulator stack, evaluating deeper plies through the refresh table. Turning it off restores the default sizes with a TT of `hash_mb`, the `Hash` set
+    /// before. Nothing is reallocated when the mode does not change.
+    pub fn set_low_memory(&mut self, low: bool, hash_mb: usize) {
+        if low == self.low_memory {
+            return;
+        }
+        self.low_memory = low;
+        if low {
+            self.resize_tt(LOW_MEMORY_HASH_MB);
+            self.cache.set_capacity(LOW_MEMORY_EVAL_ENTRIES);
+            self.accumulators = AccumulatorStack::with_plies(LOW_MEMORY_ACC_PLIES);
+        } else {
+            self.resize_tt(hash_mb);
+            self.cache.set_capacity(2 * NUM_BUCKETS);
+            self.accumulators = AccumulatorStack::default();
+        }
+    }
+
     pub fn clear(&mut self) {
         self.stack.clear();
         self.nodes = 0;
         self.ply = 0;
     }
 
+    /// Sets the contempt within -100..=100, clearing the TT when it changes as stored scores
+    /// include the old draw value. Returns whether it changed.
+    pub fn set_contempt(&mut self, contempt: i32) -> bool {
+        let contempt = contempt.clamp(-100, 100);
+        let changed = contempt != self.contempt;
+        if changed {
+            self.tt.clear();
+        }
+        self.contempt = contempt;
+        changed
+    }
+
+    /// Resets everything learnt from previous games: hash tables, histories, killers,
+    /// countermoves and the eval cache, so searches do not depend on what was played before
+    pub fn new_game(&mut self) {
+        self.clear();
+        self.tt.clear();
+        self.cache.clear();
+        self.history = HistoryTable::default();
+        self.cap_history = CaptureHistoryTable::default();
+        self.counter_moves = CounterMoveTable::default();
+        self.ply_data = [PlyData::default(); MAX_PLY];
+        self.eval = SCORE_NONE;
+        self.score_deltas = [INITIAL_SCORE_DELTA; MAX_DEPTH as usize + 1];
+        self.predicted = 0;
+    }
+
+    /// Carries what was learnt over to a search `plies` later in the same game: the histories
+    /// fade by `HISTORY_AGING` and killers move to the ply their positions are now at. Searching
+    /// the same position again keeps everything.
+    pub fn next_move(&mut self, plies: usize) {
+        if plies == 0 {
+            return;
+        }
+
+        let keep = HISTORY_AGING.get();
+        if keep < 1024 {
+            self.history.age(keep);
+            self.cap_history.age(keep);
+        }
+        for ply in 0..MAX_PLY {
+            self.ply_data[ply].killer = self
+                .ply_data
+                .get(ply + plies)
+                .map_or(Move::NULL, |data| data.killer);
+        }
+    }
+
     pub fn is_repetition(&self, board: &Board, curr_hash: u64, root: bool) -> bool {
         if self.stack.len() < 6 {
             return false;
@@ -330,35 +893,703 @@ impl SearchData {
         false
     }
 
-    pub fn continue_search(&self) -> bool {
-        let time = self.timing.elapsed().as_millis();
-        time < self.time_tp
+    /// Earlier occurrences of the position in the game history, as recorded by `position`
+    pub fn repetitions(&self, board: &Board) -> usize {
+        self.stack
+            .iter()
+            .rev()
+            .take(usize::from(board.halfmoves))
+            .skip(1)
+            .step_by(2)
+            .filter(|&&hash| hash == board.hash.0)
+            .count()
     }
-}
 
-impl std::fmt::Display for SearchData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let time = self.timing.elapsed().as_millis();
-        let nps = if time > 0 {
-            (1000 * self.nodes as u128 / time) as u64
+    /// Line from the root to the current node as a `position` command reproducing it, null moves
+    /// written as `0000`
+    pub fn crash_dump(&self) -> String {
+        let line: Vec<String> = self.ply_data[..self.ply.min(MAX_PLY)]
+            .iter()
+            .map(|p| match p.played {
+                Move::NULL => String::from("0000"),
+                m => m.to_string(),
+            })
+            .collect();
+        format!(
+            "depth {} ply {}: position fen {} moves {}",
+            self.depth,
+            self.ply,
+            self.root.to_fen(),
+            line.join(" ")
+        )
+    }
+
+    /// Move of the last iteration's PV at the current ply, while the line from the root still
+    /// follows that PV. The TT entries of the PV may have been overwritten since.
+    pub fn last_pv_move(&self) -> Option<Move> {
+        let pv = self.last_pv.as_slice();
+        let on_pv = self.ply < pv.len()
+            && self.ply_data[..self.ply]
+                .iter()
+                .zip(pv)
+                .all(|(p, &m)| p.played == m);
+        on_pv.then(|| pv[self.ply])
+    }
+
+    /// Score of a draw for `side`
+    #[inline]
+    pub fn draw_score(&self, side: Colour) -> i32 {
+        if side == self.root_side {
+            -self.contempt
+        } else {
+            self.contempt
+        }
+    }
+
+    /// Draws by the fifty move rule or threefold repetition
+    pub fn is_claimable_draw(&self, board: &Board) -> bool {
+        board.halfmoves >= 100 || self.repetitions(board) >= 2
+    }
+
+    /// Completes the root PV up to `len` plies by walking the TT from its last position, so
+    /// lines truncated by cutoffs still reach the iteration depth. Moves are verified, the PV
+    /// of a stopped search may end in moves of other positions and is cut before them.
+    pub fn extend_pv(&mut self, root: &Board, len: usize) {
+        let mut board = *root;
+        let mut seen = Vec::with_capacity(self.depth as usize);
+        let mut pv = MoveList::default();
+        for m in &self.ply_data[0].pv {
+            if !board.is_pseudo_legal(m) || !board.is_legal(m) {
+                break;
+            }
+            seen.push(board.hash.0);
+            pv.push(m);
+            board.make_move(m);
+        }
+
+        while pv.as_slice().len() < len && !seen.contains(&board.hash.0) {
+            let Some(entry) = self.tt.probe(board.hash.0) else {
+                break;
+            };
+
+            let m = entry.best_move;
+            if !board.is_pseudo_legal(m) || !board.is_legal(m) {
+                break;
+            }
+
+            seen.push(board.hash.0);
+            pv.push(m);
+            board.make_move(m);
+        }
+        self.ply_data[0].pv = pv;
+    }
+
+    /// Plays `m` on a copy of `board` at the current ply, the only place where search nodes are
+    /// counted
+    #[inline]
+    pub fn make_move(&mut self, board: &Board, m: Move) -> Board {
+        let mut new_board = *board;
+        new_board.make_move(m);
+        self.accumulators
+            .push_move(self.ply, board, m, new_board.hash.0);
+        self.nodes += 1;
+        new_board
+    }
+
+    /// Evaluation of the position at the current ply, updating its accumulators from the
+    /// previous plies when possible
+    #[inline]
+    pub fn evaluate(&mut self, board: &Board) -> i32 {
+        self.accumulators.evaluate(board, self.ply, &mut self.cache)
+    }
+
+    /// Checked at every node of the main search and quiescence, only publishes the node count
+    /// and looks at the limits once the poll countdown runs out. Stopping here stops every thread
+    /// sharing the control block.
+    #[inline]
+    pub fn should_stop(&mut self) -> bool {
+        if self.aborted() {
+            return true;
+        }
+
+        self.poll -= 1;
+        if self.poll > 0 {
+            return false;
+        }
+
+        self.poll_limits()
+    }
+
+    #[cold]
+    fn poll_limits(&mut self) -> bool {
+        self.publish_nodes();
+
+        let now = self.control.elapsed_micros();
+        if !self.deterministic && self.control.node_limit() == u64::MAX {
+            // Scale the interval towards one poll per period, at most doubling or halving it so
+            // a single slow poll (e.g. the thread being descheduled) does not throw it off
+            let interval = u64::from(self.poll_interval);
+            let scaled = interval * POLL_PERIOD_US / (now - self.clock).max(1);
+            self.poll_interval = scaled
+                .clamp(interval / 2, interval * 2)
+                .clamp(u64::from(MIN_POLL_INTERVAL), u64::from(MAX_POLL_INTERVAL))
+                as u32;
+        }
+        self.clock = now;
+        self.poll = self.poll_interval;
+
+        if self.main_thread
+            && !self.silent
+            && !self.deterministic
+            && now - self.reported >= HEARTBEAT_PERIOD_US
+        {
+            self.heartbeat();
+        }
+
+        if !self.continue_search() {
+            self.control.stop();
+            return true;
+        }
+
+        false
+    }
+
+    /// Updates the PV stability metrics and the average score change into this depth once an
+    /// iteration completes, given the best move and score of the previous one, and keeps its PV
+    /// to order the next iteration
+    pub fn end_iteration(&mut self, previous: Option<(Move, i32)>) {
+        self.last_pv = self.ply_data[0].pv;
+        let Some((best_move, eval)) = previous else {
+            return;
+        };
+
+        self.stable_iterations = if best_move == self.best_move {
+            self.stable_iterations.saturating_add(1)
         } else {
             0
         };
+        self.score_volatility = (self.eval - eval).abs();
+
+        // Mate scores would blow the average up for the rest of the game
+        let delta = &mut self.score_deltas[self.depth as usize];
+        *delta = (3 * *delta + self.score_volatility.min(ASP_LIMIT.get())) / 4;
+    }
+
+    /// Half width of the first aspiration window of an iteration to `depth`
+    pub fn aspiration_delta(&self, depth: u8) -> i32 {
+        (self.score_deltas[depth as usize] * ASP_DELTA_SCALE.get() / 100).max(ASP_DELTA_MIN.get())
+    }
+
+    /// Soft limit scaling in percent: a best move which keeps changing or a swinging score is
+    /// worth more time, one which has held for several iterations less
+    pub fn soft_scale(&self) -> u128 {
+        let changes = 20 * u128::from(self.best_move_changes.min(5));
+        let volatility = self.score_volatility.clamp(0, 100) as u128 / 2;
+        let stable = 10 * u128::from(self.stable_iterations.min(4));
+
+        (100 + changes + volatility)
+            .saturating_sub(stable)
+            .clamp(MIN_SOFT_SCALE, MAX_SOFT_SCALE)
+    }
+
+    /// Soft limit for the stop decision after an iteration. Clock games scale it by the
+    /// stability of the PV, also moving the hard limit up within the maximum time when it grows,
+    /// and cut it short for an easy move.
+    pub fn stability_soft_limit(&self) -> u128 {
+        let soft = self.control.soft_limit();
+        if self.limits.max_time == 0 {
+            return soft;
+        }
+        if self.is_easy_move() {
+            return soft * EASY_MOVE_SCALE / 100;
+        }
+
+        let scale = self.soft_scale();
+        if scale > 100 {
+            let extended = (self.limits.time * scale / 100).min(self.limits.max_time);
+            self.control.extend(extended, extended);
+            self.control.soft_limit()
+        } else {
+            soft * scale / 100
+        }
+    }
+
+    /// Whether the next iteration, predicted to take `predicted` milliseconds, would end too far
+    /// past the soft limit to be worth starting
+    pub fn skip_iteration(&self, elapsed: u128, predicted: u128, soft: u128) -> bool {
+        self.iteration_gate > 0 && elapsed + predicted > soft * self.iteration_gate / 100
+    }
+
+    /// Progress within an iteration, so that GUIs do not take a long one for a hung engine
+    fn heartbeat(&mut self) {
+        let time = self.control.elapsed();
+        let nodes = self.control.nodes();
+        let nps = (1000 * nodes as u128).checked_div(time).unwrap_or(0) as u64;
+        uci_println!(
+            "info depth {} seldepth {} nodes {nodes} nps {nps} time {time} currmove {} currmovenumber {}",
+            self.depth,
+            self.seldepth,
+            self.root.move_to_uci(self.currmove, self.chess960),
+            self.currmovenumber
+        );
+        self.reported = self.clock;
+    }
+
+    /// Called after printing an iteration, delaying the next heartbeat
+    pub fn mark_reported(&mut self) {
+        self.reported = self.control.elapsed_micros();
+    }
+
+    /// The search was stopped, or the root move being searched went over its node cap
+    #[inline]
+    pub fn aborted(&self) -> bool {
+        self.nodes >= self.root_node_cap || self.control.is_stopped()
+    }
+
+    pub fn set_disabled(&mut self, heuristic: Heuristic, disabled: bool) {
+        let bit = 1 << heuristic as u16;
+        if disabled {
+            self.disabled |= bit;
+        } else {
+            self.disabled &= !bit;
+        }
+    }
+
+    /// Whether the search skips `heuristic`, never without the `dev` feature so that release
+    /// builds pay nothing for the checks
+    #[inline]
+    pub fn is_disabled(&self, heuristic: Heuristic) -> bool {
+        cfg!(feature = "dev") && self.disabled & (1 << heuristic as u16) != 0
+    }
+
+    /// Adds the `nodes` of a search of the root move `m` to its statistics, with its `score`
+    /// when the search was not cut short
+    pub fn record_root_move(&mut self, m: Move, nodes: u64, score: Option<(i32, Bound)>) {
+        let index = match self.root_moves.iter().position(|rm| rm.m == m) {
+            Some(index) => index,
+            None => {
+                self.root_moves.push(RootMove {
+                    m,
+                    nodes: 0,
+                    score: SCORE_NONE,
+                    bound: Bound::Upper,
+                    depth: 0,
+                });
+                self.root_moves.len() - 1
+            }
+        };
+
+        let rm = &mut self.root_moves[index];
+        rm.nodes += nodes;
+        if let Some((score, bound)) = score {
+            (rm.score, rm.bound, rm.depth) = (score, bound, self.depth);
+        }
+    }
+
+    pub fn set_root_node_cap(&mut self, nodes: u64) {
+        self.root_node_cap = self.nodes.saturating_add(nodes);
+    }
+
+    pub fn clear_root_node_cap(&mut self) {
+        self.root_node_cap = u64::MAX;
+    }
+
+    /// Panic time: the best move failed low late in an iteration, so allow the search to go
+    /// on until the hard limit to find a replacement
+    pub fn panic_time(&self) {
+        let (time, max_time) = (self.limits.time, self.limits.max_time);
+        if max_time > time {
+            let soft = (PANIC_SOFT_SCALE * time).min(max_time);
+            self.control.extend(soft, max_time);
+        }
+    }
+
+    pub fn publish_nodes(&mut self) {
+        self.control.add_nodes(self.nodes - self.published);
+        self.published = self.nodes;
+    }
 
-        if self.eval.abs() >= MATE - i32::from(MAX_DEPTH) {
-            let mate_in = (MATE - self.eval.abs()) / 2;
-            let sign = if self.eval < 0 { "-" } else { "" };
-            write!(
-                f,
-                "info depth {} score mate {sign}{mate_in} time {time} nodes {} nps {nps} pv{}",
-                self.depth, self.nodes, self.ply_data[0].pv
-            )
+    pub fn continue_search(&self) -> bool {
+        if self.cancel.is_cancelled() || self.control.nodes() >= self.control.node_limit() {
+            return false;
+        }
+
+        self.deterministic || u128::from(self.clock / 1000) < self.control.hard_limit()
+    }
+}
+
+impl Default for SearchData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SearchData {
+    /// Info line reporting `score` for the current depth, marked as a `lowerbound` or an
+    /// `upperbound` when the root failed outside its window and the real score is unknown
+    pub fn info_line(&self, score: i32, bound: Bound) -> String {
+        let time = self.control.elapsed();
+        let nodes = self.control.nodes();
+        let nps = (1000 * nodes as u128).checked_div(time).unwrap_or(0) as u64;
+
+        format!(
+            "info depth {} seldepth {} score {} time {time} nodes {nodes} nps {nps} pv{}",
+            self.depth,
+            self.seldepth,
+            self.uci_score(score, bound),
+            self.root.line_to_uci(&self.ply_data[0].pv, self.chess960)
+        )
+    }
+
+    /// Statistics of every root move searched so far, best first: nodes over the whole search,
+    /// and the score with its bound and depth of the last iteration that searched it
+    pub fn root_moves_info(&self) -> Vec<String> {
+        let mut moves = self.root_moves.clone();
+        // Upper bounds tie with the alpha they failed to beat
+        moves.sort_by_key(|rm| {
+            let exact = rm.bound != Bound::Upper;
+            (std::cmp::Reverse((rm.depth, rm.score, exact)), rm.m)
+        });
+        moves
+            .iter()
+            .filter(|rm| rm.depth > 0)
+            .map(|rm| {
+                format!(
+                    "info string rootmoves {} depth {} score {} nodes {}",
+                    self.root.move_to_uci(rm.m, self.chess960),
+                    rm.depth,
+                    self.uci_score(rm.score, rm.bound),
+                    rm.nodes
+                )
+            })
+            .collect()
+    }
+
+    /// Score as written after `score` in info lines, `cp` or `mate` and the bound if any
+    pub fn uci_score(&self, score: i32, bound: Bound) -> String {
+        // From white's point of view the score of black flips, and so does its bound
+        let (score, bound) = match (bound, self.white_pov && self.root_side == Colour::Black) {
+            (_, false) => (score, bound),
+            (Bound::Lower, true) => (-score, Bound::Upper),
+            (Bound::Upper, true) => (-score, Bound::Lower),
+            (Bound::Exact, true) => (-score, Bound::Exact),
+        };
+        let score = if is_mate(score) {
+            let mate_in = (MATE - score.abs() + 1) / 2;
+            let sign = if score < 0 { "-" } else { "" };
+            format!("mate {sign}{mate_in}")
         } else {
-            write!(
-                f,
-                "info depth {} score cp {} time {time} nodes {} nps {nps} pv{}",
-                self.depth, self.eval, self.nodes, self.ply_data[0].pv
-            )
+            format!("cp {score}")
+        };
+        let bound = match bound {
+            Bound::Exact => "",
+            Bound::Lower => " lowerbound",
+            Bound::Upper => " upperbound",
+        };
+        format!("{score}{bound}")
+    }
+}
+
+impl std::fmt::Display for SearchData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.info_line(self.eval, Bound::Exact))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moves::MoveKind;
+
+    #[test]
+    fn test_crash_dump() {
+        let mut data = SearchData::new();
+        let mut board = Board::default();
+        data.root = board;
+        data.depth = 4;
+        for (ply, uci) in ["e2e4", "", "d2d4"].into_iter().enumerate() {
+            if uci.is_empty() {
+                data.ply_data[ply].record_null();
+                board.make_null_move();
+                continue;
+            }
+            let m = board.find_move(uci).unwrap();
+            data.ply_data[ply].record(&board, m);
+            board.make_move(m);
+        }
+        data.ply = 3;
+
+        assert_eq!(
+            data.crash_dump(),
+            "depth 4 ply 3: position fen rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 \
+             moves e2e4 0000 d2d4"
+        );
+    }
+
+    #[test]
+    fn test_last_pv_move() {
+        let mut data = SearchData::new();
+        let pv = [Move(1), Move(2), Move(3)];
+        pv.iter().for_each(|&m| data.last_pv.push(m));
+
+        // Followed along the PV, which gives no move past its end
+        for (ply, &m) in pv.iter().enumerate() {
+            data.ply = ply;
+            assert_eq!(data.last_pv_move(), Some(m));
+            data.ply_data[ply].played = m;
+        }
+        data.ply = 3;
+        assert_eq!(data.last_pv_move(), None);
+
+        // Leaving the PV at any ply drops it for the rest of the line
+        data.ply_data[0].played = Move(4);
+        data.ply = 2;
+        assert_eq!(data.last_pv_move(), None);
+
+        // Every search starts without one
+        data.ply = 0;
+        data.start_search();
+        assert_eq!(data.last_pv_move(), None);
+    }
+
+    #[test]
+    fn test_lazy_tt() {
+        // Resizing before the first use never allocates the previous size
+        let mut data = SearchData::new();
+        data.resize_tt(2048);
+        assert!(!data.tt.is_allocated());
+        assert!(data.tt.probe(0x1234).is_none());
+        data.tt.clear();
+        data.resize_tt(2);
+        assert!(!data.tt.is_allocated());
+
+        let m = Move::new(Square::from("e2"), Square::from("e4"), MoveKind::DoublePush);
+        data.tt.insert(0x1234, Bound::Exact, m, 15, 3, true);
+        assert_eq!(
+            data.tt.tt.len(),
+            2 * 1_048_576 / std::mem::size_of::<TTEntry>()
+        );
+        let entry = data.tt.probe(0x1234).unwrap();
+        assert_eq!((entry.best_move, entry.value, entry.depth()), (m, 15, 3));
+        assert!(data.tt.tt.iter().filter(|e| e.key != 0).count() == 1);
+    }
+
+    #[test]
+    fn test_soft_scale() {
+        let mut data = SearchData::new();
+        data.best_move = Move(1);
+        data.eval = 30;
+        assert_eq!(data.soft_scale(), 100);
+
+        // A best move holding for several iterations takes less time, down to the minimum
+        for _ in 0..10 {
+            data.end_iteration(Some((Move(1), 30)));
+        }
+        assert_eq!(data.stable_iterations, 10);
+        assert_eq!(data.soft_scale(), 60);
+
+        // A new best move with a swinging score takes more, up to the maximum
+        data.end_iteration(Some((Move(2), -50)));
+        assert_eq!((data.stable_iterations, data.score_volatility), (0, 80));
+        assert_eq!(data.soft_scale(), 140);
+        data.best_move_changes = 10;
+        assert_eq!(data.soft_scale(), MAX_SOFT_SCALE);
+
+        // Only clock games scale the soft limit
+        data.control.start(u64::MAX, 1000, 1000);
+        assert_eq!(data.stability_soft_limit(), 1000);
+        data.limits = SearchLimits::new().clock(1000, 1500);
+        assert_eq!(data.stability_soft_limit(), 1500);
+        assert_eq!(data.control.hard_limit(), 1500);
+    }
+
+    #[test]
+    fn test_aspiration_delta() {
+        let mut data = SearchData::new();
+        data.depth = 10;
+        assert_eq!(data.aspiration_delta(10), INITIAL_SCORE_DELTA);
+
+        // Stable scores narrow the window of that depth only, down to the minimum
+        data.eval = 20;
+        for _ in 0..20 {
+            data.end_iteration(Some((Move(1), 20)));
+        }
+        assert_eq!(data.aspiration_delta(10), ASP_DELTA_MIN.get());
+        assert_eq!(data.aspiration_delta(11), INITIAL_SCORE_DELTA);
+
+        // Swings widen it, a mate score only counting up to the limit
+        data.eval = MATE - 5;
+        data.end_iteration(Some((Move(1), 20)));
+        assert_eq!(data.aspiration_delta(10), ASP_LIMIT.get() / 4);
+
+        data.new_game();
+        assert_eq!(data.aspiration_delta(10), INITIAL_SCORE_DELTA);
+    }
+
+    #[test]
+    fn test_easy_move() {
+        use crate::search::find_best_move;
+
+        let board = Board::default();
+        let mut data = SearchData::new();
+        data.deterministic = true;
+        data.silent = true;
+        find_best_move(&board, &SearchLimits::new().depth(8), &mut data);
+        let (best, reply) = (data.best_move, data.ponder_move);
+        assert!(reply != Move::NULL);
+
+        // Any other reply drops the prediction
+        let mut other = board;
+        other.make_move(best);
+        let deviation = other
+            .generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .find(|&m| m != reply && other.is_legal(m))
+            .unwrap();
+        let mut predicted = other;
+        predicted.make_move(reply);
+        other.make_move(deviation);
+        data.start_easy_move(&other);
+        assert_eq!(data.easy_move, Move::NULL);
+
+        data.predicted = predicted.hash.0;
+        data.start_easy_move(&predicted);
+        assert!(data.easy_move != Move::NULL);
+        assert_eq!((data.predicted, data.ponder_move), (0, Move::NULL));
+
+        // Only a dominant expected move at a sufficient depth cuts the soft limit of clock games
+        data.best_move = data.easy_move;
+        (data.depth, data.best_move_share) = (EASY_MOVE_DEPTH, EASY_MOVE_SHARE);
+        data.control.start(u64::MAX, 1000, 1000);
+        assert_eq!(data.stability_soft_limit(), 1000);
+        data.limits = SearchLimits::new().clock(1000, 3000);
+        assert_eq!(data.stability_soft_limit(), 10 * EASY_MOVE_SCALE);
+        data.best_move_share -= 1;
+        assert!(!data.is_easy_move());
+    }
+
+    #[test]
+    fn test_stopped_search_pv() {
+        use crate::search::find_best_move;
+
+        let board = Board::default();
+        let mut data = SearchData::new();
+        data.deterministic = true;
+        data.silent = true;
+        find_best_move(&board, &SearchLimits::new().depth(6), &mut data);
+
+        // A helper finding the search already stopped keeps no line of its last position
+        let other = Board::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 0 1");
+        data.main_thread = false;
+        data.control.stop();
+        find_best_move(&other, &SearchLimits::new().depth(6), &mut data);
+        assert!(other.is_legal(data.best_move));
+        assert_eq!(data.ponder_move, Move::NULL);
+
+        // Moves of other positions are cut from the PV rather than played
+        let mut pv = MoveList::default();
+        pv.push(board.find_move("e2e4").unwrap());
+        data.ply_data[0].pv = pv;
+        data.extend_pv(&other, 2);
+        assert!(data.ply_data[0].pv.as_slice().is_empty());
+    }
+
+    #[test]
+    fn test_skip_iteration() {
+        let mut data = SearchData::new();
+        assert!(!data.skip_iteration(400, 1600, 1000));
+        assert!(data.skip_iteration(400, 1601, 1000));
+
+        data.iteration_gate = 100;
+        assert!(data.skip_iteration(400, 601, 1000));
+        data.iteration_gate = 0;
+        assert!(!data.skip_iteration(900, 100_000, 1000));
+    }
+
+    #[test]
+    fn test_history() {
+        let board = Board::default();
+        let black = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        let mv = |src, dest, kind| Move::new(Square::from(src), Square::from(dest), kind);
+        let e2e4 = mv("e2", "e4", MoveKind::DoublePush);
+        let g1f3 = mv("g1", "f3", MoveKind::Quiet);
+        let f7f6 = mv("f7", "f6", MoveKind::Quiet);
+
+        let mut table = HistoryTable::default();
+        for _ in 0..100 {
+            table.update(&board, e2e4, history_bonus(20), &[g1f3]);
+        }
+        assert!(table.get(&board, e2e4) > 0);
+        assert!(table.get(&board, e2e4) as i32 <= MAX_HISTORY.get());
+        assert!(table.get(&board, g1f3) < 0);
+        assert!(table.get(&board, g1f3) as i32 >= -MAX_HISTORY.get());
+
+        // Other pieces and the other side have entries of their own
+        assert_eq!(table.get(&black, f7f6), 0);
+        assert_eq!(table.get(&board, mv("g2", "f3", MoveKind::Quiet)), 0);
+
+        // Aging rounds towards zero on both sides
+        let (good, bad) = (table.get(&board, e2e4), table.get(&board, g1f3));
+        table.age(512);
+        assert_eq!(table.get(&board, e2e4), good / 2);
+        assert_eq!(table.get(&board, g1f3), bad / 2);
+    }
+
+    #[test]
+    fn test_capture_history() {
+        let board = Board::from_fen("r3k2r/1P6/8/3pP3/2n5/1Q6/8/R3K2R w KQkq d6 0 1");
+        let mv = |src, dest, kind| Move::new(Square::from(src), Square::from(dest), kind);
+        let en_passant = mv("e5", "d6", MoveKind::EnPassant);
+        let promo = mv("b7", "a8", MoveKind::QueenCapPromo);
+        let under_promo = mv("b7", "a8", MoveKind::KnightCapPromo);
+        let queen = mv("b3", "c4", MoveKind::Capture);
+        let rook = mv("a1", "a8", MoveKind::Capture);
+
+        let mut table = CaptureHistoryTable::default();
+        table.update(&board, promo, 1000, &[en_passant, queen]);
+
+        // Both promotions are pawn takes rook, the rook takes rook entry is untouched
+        assert!(table.get(&board, promo) > 0);
+        assert_eq!(table.get(&board, under_promo), table.get(&board, promo));
+        assert_eq!(
+            table.score[PieceType::Pawn.index()][56][PieceType::Rook.index()],
+            1000
+        );
+        assert_eq!(table.get(&board, rook), 0);
+
+        assert!(table.get(&board, en_passant) < 0);
+        assert_eq!(
+            table.score[PieceType::Pawn.index()][43][PieceType::Pawn.index()],
+            -1000
+        );
+        assert!(table.get(&board, queen) < 0);
+        assert_eq!(
+            table.score[PieceType::Queen.index()][26][PieceType::Knight.index()],
+            -1000
+        );
+
+        // Every capture of either side, kings capturing included, has an entry
+        for fen in [
+            "r3k2r/1P6/8/3pP3/2n5/1Q6/8/R3K2R w KQkq d6 0 1",
+            "r3k2r/8/8/8/3pP3/8/1p6/R2QK2R b KQkq e3 0 1",
+            "k7/8/8/8/8/8/1p6/K7 w - - 0 1",
+            "7K/8/8/8/8/8/1P6/k7 b - - 0 1",
+        ] {
+            // Quiet promotions are generated along with the captures but have no entry
+            let board = Board::from_fen(fen);
+            let caps: Vec<Move> = board
+                .generate_pseudo_moves::<false, true>()
+                .as_slice()
+                .iter()
+                .copied()
+                .filter(|m| m.get_type().is_capture())
+                .collect();
+            assert!(!caps.is_empty());
+            let mut table = CaptureHistoryTable::default();
+            for &m in &caps {
+                table.update(&board, m, 500, &caps);
+                assert!(table.get(&board, m) != 0);
+            }
         }
     }
 }
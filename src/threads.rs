@@ -0,0 +1,439 @@
+use crate::board::Board;
+use crate::limits::SearchLimits;
+use crate::moves::{Move, MoveList};
+use crate::search::{find_best_move, MATE_BOUND};
+use crate::tables::{Bound, SearchData};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Search state owned by one thread, kept between searches so its tables are never reallocated
+pub struct SearchWorker {
+    pub id: usize,
+    pub data: SearchData,
+}
+
+impl SearchWorker {
+    fn new(id: usize) -> Self {
+        let mut data = SearchData::new();
+        data.silent = id != 0;
+        Self { id, data }
+    }
+
+    /// Depth of the last finished iteration
+    pub fn completed_depth(&self) -> u8 {
+        self.data.depth.saturating_sub(1)
+    }
+}
+
+/// Set of workers, the first one is the main thread which reports to the GUI
+pub struct ThreadPool {
+    workers: Vec<SearchWorker>,
+}
+
+impl ThreadPool {
+    pub fn new(threads: usize) -> Self {
+        let mut pool = Self {
+            workers: Vec::new(),
+        };
+        pool.resize(threads);
+        pool
+    }
+
+    /// Keeps existing workers and their tables, only creating or dropping the difference
+    pub fn resize(&mut self, threads: usize) {
+        let threads = threads.max(1);
+        self.workers.truncate(threads);
+        while self.workers.len() < threads {
+            self.workers.push(SearchWorker::new(self.workers.len()));
+        }
+        self.share_control();
+    }
+
+    /// Makes helpers use the control block of the main thread, which is the only one starting
+    /// the search, so that stopping any of them stops all and node limits count every thread
+    fn share_control(&mut self) {
+        let control = Arc::clone(&self.workers[0].data.control);
+        for worker in &mut self.workers[1..] {
+            worker.data.control = Arc::clone(&control);
+            worker.data.main_thread = false;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+
+    pub fn main(&mut self) -> &mut SearchData {
+        &mut self.workers[0].data
+    }
+
+    pub fn workers(&self) -> &[SearchWorker] {
+        &self.workers
+    }
+
+    pub fn new_game(&mut self) {
+        for worker in &mut self.workers {
+            worker.data.new_game();
+        }
+    }
+
+    /// Runs `f` on every worker at the same time, one thread each, returning their results
+    /// in worker order
+    pub fn run<T, F>(&mut self, f: F) -> Vec<T>
+    where
+        T: Send,
+        F: Fn(&mut SearchWorker) -> T + Sync,
+    {
+        let f = &f;
+        std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .workers
+                .iter_mut()
+                .map(|worker| s.spawn(move || f(worker)))
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("Search worker panicked"))
+                .collect()
+        })
+    }
+
+    pub fn nodes(&self) -> u64 {
+        self.workers.iter().map(|w| w.data.nodes).sum()
+    }
+
+    pub fn seldepth(&self) -> usize {
+        self.workers
+            .iter()
+            .map(|w| w.data.seldepth)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Worker whose move gets the most votes, each weighted by depth and by how much better
+    /// its score is than the worst one. Proven mates always win.
+    pub fn best_worker(&self) -> &SearchWorker {
+        let min_score = self.workers.iter().map(|w| w.data.eval).min().unwrap_or(0);
+        let mut votes: HashMap<Move, i64> = HashMap::new();
+        for w in &self.workers {
+            let weight = i64::from(w.data.eval - min_score + 14) * i64::from(w.completed_depth());
+            *votes.entry(w.data.best_move).or_default() += weight;
+        }
+
+        let mut best = &self.workers[0];
+        for w in &self.workers[1..] {
+            let (score, best_score) = (w.data.eval, best.data.eval);
+            let better = if best_score >= MATE_BOUND || score >= MATE_BOUND {
+                score > best_score
+            } else {
+                votes[&w.data.best_move] > votes[&best.data.best_move]
+            };
+
+            if better && w.data.best_move != Move::NULL {
+                best = w;
+            }
+        }
+
+        best
+    }
+}
+
+/// Depth of the search ranking the root moves before a root split
+const SPLIT_RANK_DEPTH: u8 = 5;
+
+/// What the helper of a root split found for its move
+#[derive(Debug, PartialEq)]
+pub struct SplitReport {
+    pub m: Move,    // Second best root move, searched by the helper
+    pub depth: u8,  // Last iteration the helper completed, counted from the root
+    pub score: i32, // From the root side
+    pub nodes: u64,
+    pub used: bool, // Replaced the move of the main thread
+}
+
+/// Two-thread search: while `main` searches every root move, `helper` searches the position
+/// after the runner-up of a shallow ranking, the move most likely to overtake the first one.
+/// Its result goes into the TT of `main`, and its move is played instead when it scores better
+/// at least as deep as the main search. `helper` must share the control of `main`.
+///
+/// Each thread keeps its own TT, so this is only a stepping stone towards Lazy SMP. Returns
+/// `None` when the root has a single candidate, so that `main` searched alone, or when the
+/// helper did not complete an iteration.
+pub fn search_root_split(
+    board: &Board,
+    limits: &SearchLimits,
+    main: &mut SearchData,
+    helper: &mut SearchData,
+) -> Option<SplitReport> {
+    mirror_settings(helper, main);
+    helper.tt.inc_age();
+    helper.stack.clone_from(&main.stack);
+
+    // Started once for the whole split, so that the ranking search counts towards the time and
+    // node limits and a `stop` sent during it is kept
+    main.control.start(limits.nodes, limits.time, limits.time);
+    main.control_started = true;
+
+    let rank_limits = SearchLimits::new()
+        .depth(SPLIT_RANK_DEPTH.min(limits.depth))
+        .search_moves(limits.search_moves.clone());
+    find_best_move(board, &rank_limits, helper);
+
+    let first = helper.best_move;
+    let second = helper
+        .root_moves
+        .iter()
+        .filter(|rm| rm.m != first)
+        .max_by_key(|rm| rm.nodes)
+        .map(|rm| rm.m);
+    let Some(second) = second else {
+        find_best_move(board, limits, main);
+        return None;
+    };
+
+    let mut child = *board;
+    child.make_move(second);
+    helper.stack.push(board.hash.0);
+    // Never extended, the main thread alone decides on more time up to the hard limit
+    let helper_limits = SearchLimits::new()
+        .depth(limits.depth.saturating_sub(1))
+        .nodes(limits.nodes)
+        .movetime(limits.max_time.max(limits.time));
+
+    let helper_done = std::thread::scope(|s| {
+        let handle = s.spawn(|| find_best_move(&child, &helper_limits, helper));
+        // The helper only stops with the main search, even when that panics
+        let searched = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            find_best_move(board, limits, main)
+        }));
+        main.control.stop();
+        let helper_done = handle.join().is_ok();
+        if let Err(panic) = searched {
+            std::panic::resume_unwind(panic);
+        }
+        helper_done
+    });
+
+    let depth = helper.depth.saturating_sub(1) + 1;
+    if !helper_done || helper.best_move == Move::NULL || depth < 2 {
+        return None;
+    }
+
+    // Scores at the root of the helper are exact and relative to it, as the TT stores them
+    main.tt.insert(
+        child.hash.0,
+        Bound::Exact,
+        helper.best_move,
+        helper.eval,
+        depth - 1,
+        true,
+    );
+
+    let score = -helper.eval;
+    let used = second != main.best_move
+        && depth >= main.depth.saturating_sub(1)
+        && score > main.eval
+        && main.limits.allows(second);
+    if used {
+        let mut pv = MoveList::default();
+        pv.push(second);
+        for &m in helper.last_pv.as_slice() {
+            pv.push(m);
+        }
+        main.best_move = second;
+        main.eval = score;
+        main.last_pv = pv;
+        main.ply_data[0].pv = pv;
+        main.ponder_move = Move::NULL;
+        main.predict(board);
+    }
+
+    Some(SplitReport {
+        m: second,
+        depth,
+        score,
+        nodes: helper.nodes,
+        used,
+    })
+}
+
+/// Makes `helper` search as `main` does. Its root is a ply deeper, on the other side, so the
+/// contempt changes sign to keep favouring the same side.
+fn mirror_settings(helper: &mut SearchData, main: &SearchData) {
+    helper.silent = true;
+    helper.analyse_mode = main.analyse_mode;
+    helper.fail_hard = main.fail_hard;
+    helper.chess960 = main.chess960;
+    helper.disabled = main.disabled;
+    helper.iteration_gate = main.iteration_gate;
+    helper.contempt = -main.contempt;
+}
+
+/// One thread per core, or a single one when that is unknown
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Splits the root moves, each with its expected nodes, into `threads` groups of about the same
+/// total: heaviest moves first, each going to the thread with the fewest nodes so far
+pub fn split_root_moves(moves: &[(Move, u64)], threads: usize) -> Vec<Vec<(Move, u64)>> {
+    let mut sorted = moves.to_vec();
+    sorted.sort_by_key(|&(_, nodes)| std::cmp::Reverse(nodes));
+
+    let mut split = vec![Vec::new(); threads.max(1)];
+    let mut loads = vec![0; split.len()];
+    for (m, nodes) in sorted {
+        let (thread, _) = loads
+            .iter()
+            .enumerate()
+            .min_by_key(|&(_, &load)| load)
+            .unwrap();
+        split[thread].push((m, nodes));
+        loads[thread] += nodes;
+    }
+
+    split
+}
+
+/// Nodes of the busiest thread over the average, 1.0 being a perfect split
+pub fn load_imbalance(split: &[Vec<(Move, u64)>]) -> f64 {
+    let loads: Vec<u64> = split
+        .iter()
+        .map(|moves| moves.iter().map(|&(_, nodes)| nodes).sum())
+        .collect();
+    let total: u64 = loads.iter().sum();
+    if total == 0 {
+        return 1.0;
+    }
+
+    let average = total as f64 / loads.len() as f64;
+    *loads.iter().max().unwrap() as f64 / average
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::Board;
+    use crate::control::POLL_INTERVAL;
+    use crate::limits::SearchLimits;
+    use crate::search::find_best_move;
+
+    #[test]
+    fn test_pool() {
+        let mut pool = ThreadPool::new(3);
+        assert_eq!(pool.len(), 3);
+        assert!(!pool.workers()[0].data.silent && pool.workers()[2].data.silent);
+        assert!(pool.workers()[1..].iter().all(|w| !w.data.main_thread
+            && Arc::ptr_eq(&w.data.control, &pool.workers()[0].data.control)));
+
+        let board = Board::default();
+        let depths = pool.run(|w| {
+            find_best_move(
+                &board,
+                &SearchLimits::new().depth(3 + w.id as u8),
+                &mut w.data,
+            );
+            w.completed_depth()
+        });
+        assert_eq!(depths, [3, 4, 5]);
+        assert!(pool.nodes() > pool.workers()[2].data.nodes);
+        assert!(pool.seldepth() >= 5);
+        assert!(board.is_legal(pool.best_worker().data.best_move));
+
+        // Shrinking keeps the remaining workers untouched
+        let nodes = pool.workers()[0].data.nodes;
+        pool.resize(1);
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.main().nodes, nodes);
+    }
+
+    #[test]
+    fn test_root_split() {
+        let mut main = SearchData::new();
+        let mut helper = SearchData::new();
+        main.silent = true;
+        helper.control = Arc::clone(&main.control);
+        helper.main_thread = false;
+
+        let board = Board::default();
+        let limits = SearchLimits::new().depth(7);
+        let split = search_root_split(&board, &limits, &mut main, &mut helper).unwrap();
+        assert!(board.is_legal(split.m) && board.is_legal(main.best_move));
+        assert!(split.depth >= 2 && split.nodes > 0);
+        assert_eq!(helper.contempt, -main.contempt);
+        assert!(helper.silent && !helper.main_thread);
+        // Both stopped together and the speculative result landed in the main table
+        assert!(main.control.is_stopped());
+        let mut child = board;
+        child.make_move(split.m);
+        let entry = main.tt.probe(child.hash.0).unwrap();
+        assert_eq!(-entry.value, split.score);
+        if split.used {
+            assert_eq!((main.best_move, main.eval), (split.m, split.score));
+        }
+
+        // Forced moves leave nothing to split
+        let check = Board::from_fen("4k3/8/8/8/8/8/r3P3/1r2K3 w - - 0 1");
+        assert_eq!(
+            search_root_split(&check, &limits, &mut main, &mut helper),
+            None
+        );
+        assert!(main.best_move != Move::NULL);
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, ignore = "timing only holds in release builds")]
+    fn test_root_split_limits() {
+        let mut main = SearchData::new();
+        let mut helper = SearchData::new();
+        main.silent = true;
+        helper.control = Arc::clone(&main.control);
+        helper.main_thread = false;
+        main.tt.allocate();
+        helper.tt.allocate();
+
+        // The ranking search is charged to the movetime like the rest of the split
+        let board =
+            Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+        let start = std::time::Instant::now();
+        let limits = SearchLimits::new().movetime(100);
+        search_root_split(&board, &limits, &mut main, &mut helper);
+        let elapsed = start.elapsed().as_millis();
+        assert!(elapsed < 250, "{elapsed}ms");
+        assert!(board.is_legal(main.best_move));
+
+        // And to the node limit, counting the nodes of both threads
+        let limits = SearchLimits::new().nodes(5_000);
+        search_root_split(&board, &limits, &mut main, &mut helper);
+        assert!(main.control.nodes() < 5_000 + 4 * u64::from(POLL_INTERVAL));
+        assert!(board.is_legal(main.best_move));
+    }
+
+    #[test]
+    fn test_split_root_moves() {
+        let board = Board::default();
+        let moves: Vec<(Move, u64)> = ["e2e4", "d2d4", "g1f3", "c2c4", "b1c3"]
+            .into_iter()
+            .zip([70, 30, 10, 10, 10])
+            .map(|(m, nodes)| (board.find_move(m).unwrap(), nodes))
+            .collect();
+
+        // The heaviest move keeps a thread to itself, the rest fill the other
+        let split = split_root_moves(&moves, 2);
+        let loads: Vec<u64> = split.iter().map(|t| t.iter().map(|m| m.1).sum()).collect();
+        assert_eq!(loads, [70, 60]);
+        assert_eq!(split[0], [moves[0]]);
+        assert!((load_imbalance(&split) - 70.0 / 65.0).abs() < 1e-9);
+
+        // More threads than moves leave some idle
+        let split = split_root_moves(&moves, 8);
+        assert_eq!(split.iter().filter(|t| t.is_empty()).count(), 3);
+        assert!((load_imbalance(&split) - 70.0 * 8.0 / 130.0).abs() < 1e-9);
+        assert_eq!(load_imbalance(&split_root_moves(&[], 4)), 1.0);
+    }
+}
@@ -1,15 +1,27 @@
-use crate::network::EvalTable;
+use crate::checkpoint::Checkpoint;
+use crate::constants::verify_tables;
+use crate::limits::SearchLimits;
+use crate::network::{CacheStats, EvalTable};
 use crate::piece::Colour;
-use crate::search::{find_best_move, MAX_DEPTH};
-use crate::tables::SearchData;
+use crate::repertoire::Repertoire;
+use crate::search::{find_best_move, legal_best_move, MAX_DEPTH};
+#[cfg(feature = "dev")]
+use crate::tables::Heuristic;
+use crate::tables::{
+    SearchData, DEFAULT_HASH_MB, DEFAULT_ITERATION_GATE, LOW_MEMORY_HASH_MB, MAX_HASH_MB,
+};
+use crate::threads::{
+    default_threads, load_imbalance, search_root_split, split_root_moves, ThreadPool,
+};
+use crate::tunables;
+use crate::{logging, uci_println};
 use std::env;
 use std::io::BufRead;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
-use super::{
-    board::Board,
-    moves::{Move, MoveKind},
-    square::Square,
-};
+use super::{board::Board, moves::Move};
 
 const NAME: &str = "Oxide";
 const AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
@@ -17,21 +29,271 @@ const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 /// Time Control constants
 const MAX_TIME: u128 = 180000;
+const PANIC_SCALE: u128 = 3; // Hard limit of clock games as a multiple of the allocated time
+const CLOCK_TOLERANCE: u128 = 50; // Disagreement with the GUI clock taken as noise, in ms
+
+/// Nominal speed used to turn time limits into node limits in deterministic mode
+const DETERMINISTIC_NODES_PER_MS: u64 = 1000;
+
+/// Parameters of `go`, which end the move list of `searchmoves`
+const GO_PARAMETERS: [&str; 12] = [
+    "searchmoves",
+    "ponder",
+    "wtime",
+    "btime",
+    "winc",
+    "binc",
+    "movestogo",
+    "depth",
+    "nodes",
+    "mate",
+    "movetime",
+    "infinite",
+];
+
+/// Perft depth estimating the nodes of each root move for `splitinfo` when there is no search
+const SPLIT_PERFT_DEPTH: usize = 3;
+
+/// Depth of every search in `bench smp`, lower than `bench` as the suite runs once per thread count
+pub const SMP_BENCH_DEPTH: u8 = 10;
+
+/// Commands understood by the engine, anything before the first of them on a line is skipped
+const COMMANDS: [&str; 22] = [
+    "uci",
+    "ucinewgame",
+    "debug",
+    "isready",
+    "register",
+    "position",
+    "perft",
+    "go",
+    "stop",
+    "ponderhit",
+    "setoption",
+    "d",
+    "islegal",
+    "spsa",
+    "checkpoint",
+    "resume",
+    "repertoire",
+    "eval",
+    "bench",
+    "tables",
+    "splitinfo",
+    "quit",
+];
+
+/// Game as received through the last `position` command, used to tell whether the next one
+/// continues it, as some GUIs never send `ucinewgame`
+#[derive(Default)]
+struct GameHistory {
+    start: u64,
+    moves: Vec<Move>,
+}
+
+impl GameHistory {
+    /// Plies the position is ahead of the last one, when it continues the game: if its moves
+    /// extend the previous ones from the same start position, or if it is reachable within two
+    /// plies of the last position (GUIs which only send the current FEN)
+    fn plies_to(&self, last: &Board, start: u64, moves: &[Move], board: &Board) -> Option<usize> {
+        if self.start == start && moves.starts_with(&self.moves) {
+            return Some(moves.len() - self.moves.len());
+        }
+        (0..=2).find(|&plies| reachable(last, board.hash.0, plies as u8))
+    }
+}
+
+/// Our clock carried across the moves of a game, predicting the time the GUI should report next
+/// if it only charged us for our searches. Time lost elsewhere (network lag, GUI overhead) is
+/// kept as a safety margin, while reports of more time, as when a new period of `movestogo`
+/// moves starts, resync the prediction.
+#[derive(Default)]
+struct GameClock {
+    expected: Option<u128>, // Time left predicted for the next search
+    left: u128,             // Time left trusted at the start of the current search
+    lag: u128,              // Time lost outside our searches, decaying move after move
+}
+
+impl GameClock {
+    /// Takes the time `reported` by the GUI, returning the time the search may assume is left
+    fn observe(&mut self, reported: u128) -> u128 {
+        self.left = match self.expected {
+            Some(expected) => {
+                let lost = expected.saturating_sub(reported);
+                let lost = if lost > CLOCK_TOLERANCE { lost } else { 0 };
+                self.lag = lost.max(self.lag / 2);
+                // Past `expected + CLOCK_TOLERANCE` the GUI added time, which is trusted
+                reported
+            }
+            None => reported,
+        };
+        self.left.saturating_sub(self.lag)
+    }
+
+    /// Charges the time spent on the move, adding the increment received after it
+    fn spend(&mut self, elapsed: u128, inc: u128) {
+        self.expected = Some(self.left.saturating_sub(elapsed) + inc);
+    }
+}
+
+/// Time usage over the searches of a game, reported when the game ends with `TimeReport` set
+#[derive(Default)]
+struct GameStats {
+    moves: u32,
+    time: u128,     // Total time spent searching
+    depth: u32,     // Sum of the depths completed
+    timed: u32,     // Searches with a time budget
+    budget: u128,   // Sum of the budgets of the timed searches
+    spent: u128,    // Time spent by the timed searches
+    overruns: u32,  // Timed searches which took longer than their budget
+    max_over: u128, // Largest overrun of a budget
+    max_lag: u128,  // Largest time lost outside our searches
+}
+
+impl GameStats {
+    fn record(&mut self, elapsed: u128, depth: u8, budget: Option<u128>) {
+        self.moves += 1;
+        self.time += elapsed;
+        self.depth += u32::from(depth);
+        if let Some(budget) = budget {
+            self.timed += 1;
+            self.budget += budget;
+            self.spent += elapsed;
+            if elapsed > budget {
+                self.overruns += 1;
+                self.max_over = self.max_over.max(elapsed - budget);
+            }
+        }
+    }
+
+    fn summary(&self) -> String {
+        let moves = self.moves.max(1);
+        let mut summary = format!(
+            "time report moves {} time {} avg {} depth {:.1}",
+            self.moves,
+            self.time,
+            self.time / u128::from(moves),
+            f64::from(self.depth) / f64::from(moves)
+        );
+        if self.timed > 0 {
+            summary += &format!(
+                " budget {} used {}% overruns {} maxover {} maxlag {}",
+                self.budget / u128::from(self.timed),
+                100 * self.spent / self.budget.max(1),
+                self.overruns,
+                self.max_over,
+                self.max_lag
+            );
+        }
+        summary
+    }
+}
+
+/// Finds the move written in UCI notation, explaining why it cannot be played otherwise
+fn check_move(board: &Board, move_str: &str) -> Result<Move, String> {
+    let m = board.find_move(move_str)?;
+    if !board.is_legal(m) {
+        return Err(String::from("leaves the king in check"));
+    }
+
+    Ok(m)
+}
+
+/// Position of the fields of a FEN, whose move counters may be left out
+pub fn parse_fen(fields: &[&str]) -> Result<Board, String> {
+    if !(4..=6).contains(&fields.len()) {
+        return Err(format!("Invalid FEN: {}", fields.join(" ")));
+    }
+    let counters = ["0", "1"];
+    let fen: Vec<&str> = fields
+        .iter()
+        .chain(&counters[fields.len() - 4..])
+        .copied()
+        .collect();
+    Board::try_from_fen(&fen.join(" "))
+}
+
+/// Splits `setoption name <id> [value <x>]` into the name and the value, which is empty when
+/// missing. Both may contain spaces, the name ends at the first `value` word and the value
+/// keeps its inner spacing, without the surrounding quotes if it is quoted.
+fn parse_setoption(command: &str) -> Option<(&str, &str)> {
+    let rest = command.trim().strip_prefix("setoption")?.trim_start();
+    let rest = rest.strip_prefix("name")?;
+    if !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+
+    let is_value = |i: usize| {
+        rest[..i].ends_with(char::is_whitespace)
+            && rest[i + 5..].chars().next().is_none_or(char::is_whitespace)
+    };
+    let (name, value) = match rest.match_indices("value").find(|&(i, _)| is_value(i)) {
+        Some((i, _)) => (&rest[..i], rest[i + 5..].trim()),
+        None => (rest, ""),
+    };
+
+    let name = name.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    (!name.is_empty()).then_some((name, value))
+}
+
+fn reachable(board: &Board, target: u64, plies: u8) -> bool {
+    if board.hash.0 == target {
+        return true;
+    }
+
+    plies > 0
+        && board
+            .generate_pseudo_moves::<true, true>()
+            .into_iter()
+            .filter(|&m| board.is_legal(m))
+            .any(|m| {
+                let mut new_board = *board;
+                new_board.make_move(m);
+                reachable(&new_board, target, plies - 1)
+            })
+}
 
 pub struct UCIEngine {
     board: Board,
+    game: GameHistory,
+    clock: GameClock,
+    stats: GameStats,
+    time_report: bool,
+    debug: bool,
+    repertoire: Option<Repertoire>, // Records the best move of every search when set
+    hash_mb: usize,
     pub data: SearchData,
+    helper: Option<Box<SearchData>>, // Second thread of the root split, with `Threads` at 2
+}
+
+impl Default for UCIEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl UCIEngine {
     pub fn new() -> Self {
         UCIEngine {
             board: Board::default(),
+            game: GameHistory::default(),
+            clock: GameClock::default(),
+            stats: GameStats::default(),
+            time_report: false,
+            debug: false,
+            repertoire: None,
+            hash_mb: DEFAULT_HASH_MB,
             data: SearchData::new(),
+            helper: None,
         }
     }
 
     pub fn run(&mut self) {
+        logging::install_panic_hook();
         let stdin = std::io::stdin();
 
         for line in stdin.lock().lines() {
@@ -40,32 +302,102 @@ impl UCIEngine {
                 continue;
             }
 
+            logging::input(&line);
             self.process_command(&line);
         }
     }
 
-    fn process_command(&mut self, command: &str) {
-        let parts: Vec<&str> = command.split_whitespace().collect();
-        if parts.is_empty() {
+    fn process_command(&mut self, line: &str) {
+        // Unknown tokens before a command are skipped, as the UCI spec asks ("joho debug on")
+        let mut command = line.trim();
+        while let Some(token) = command.split_whitespace().next() {
+            if COMMANDS.contains(&token) {
+                break;
+            }
+            command = command[token.len()..].trim_start();
+        }
+        if command.is_empty() {
+            if !line.trim().is_empty() {
+                logging::warn(&format!("Unknown command: {}", line.trim()));
+            }
             return;
         }
 
+        let parts: Vec<&str> = command.split_whitespace().collect();
         match parts[0] {
+            // Only describes the engine, so it can be sent again at any time, even mid-game,
+            // without touching the position or the search state
             "uci" => {
-                println!("id name {NAME} {VERSION}");
-                println!("id author {AUTHOR}");
-                println!("option name Hash type spin default 32 min 1 max 4096");
-                println!("option name Threads type spin default 1 min 1 max 1");
-                println!("uciok");
+                uci_println!("id name {NAME} {VERSION}");
+                // Cargo separates several authors with colons
+                uci_println!("id author {}", AUTHOR.replace(':', ", "));
+                let hash = if self.data.low_memory {
+                    LOW_MEMORY_HASH_MB
+                } else {
+                    DEFAULT_HASH_MB
+                };
+                uci_println!("option name Hash type spin default {hash} min 1 max {MAX_HASH_MB}");
+                uci_println!("option name Threads type spin default 1 min 1 max 2");
+                uci_println!("option name UCI_AnalyseMode type check default false");
+                uci_println!("option name WhitePOV type check default false");
+                uci_println!("option name UCI_Chess960 type check default false");
+                uci_println!("option name NNUECache type check default true");
+                uci_println!("option name Deterministic type check default false");
+                uci_println!("option name FailHard type check default false");
+                uci_println!("option name RootMoves type check default false");
+                uci_println!(
+                    "option name LowMemory type check default {}",
+                    cfg!(feature = "low-memory")
+                );
+                uci_println!("option name Contempt type spin default 0 min -100 max 100");
+                uci_println!("option name Repertoire type string default <empty>");
+                uci_println!("option name Log File type string default <empty>");
+                uci_println!("option name TimeReport type check default false");
+                uci_println!(
+                    "option name IterationGate type spin default {DEFAULT_ITERATION_GATE} min 0 max 1000"
+                );
+                #[cfg(feature = "dev")]
+                for heuristic in Heuristic::ALL {
+                    uci_println!(
+                        "option name {} type check default false",
+                        heuristic.option()
+                    );
+                }
+                #[cfg(feature = "tune")]
+                for t in tunables::TUNABLES {
+                    uci_println!(
+                        "option name {} type spin default {} min {} max {}",
+                        t.name,
+                        t.default,
+                        t.min,
+                        t.max
+                    );
+                }
+                uci_println!("uciok");
+                // Oxide is free, both checks pass at once
+                uci_println!("copyprotection ok");
+                uci_println!("registration ok");
             }
             "ucinewgame" => {
                 self.board = Board::default();
-                self.data.clear();
-                self.data.tt.clear();
+                self.new_game();
+            }
+            "debug" => {
+                self.debug = parts.get(1) == Some(&"on");
             }
             "isready" => {
-                println!("readyok");
+                self.data.tt.allocate();
+                uci_println!("readyok");
             }
+            "register" => match &parts[1..] {
+                ["later"] => {}
+                [] => logging::warn("Usage: register (later | name <name> code <code>)"),
+                // Any name and code are accepted
+                _ => {
+                    uci_println!("registration checking");
+                    uci_println!("registration ok");
+                }
+            },
             "position" => {
                 self.parse_position(&parts[1..]);
             }
@@ -73,39 +405,237 @@ impl UCIEngine {
             "go" => {
                 self.go(&parts[1..]);
             }
-            "setoption" => {
-                if parts.len() >= 5 && parts[1] == "name" {
-                    match parts[2] {
-                        "Hash" if parts[3] == "value" => {
-                            if let Ok(mb) = parts[4].parse() {
-                                if mb > 0 {
-                                    self.data.resize_tt(mb);
-                                }
-                            }
-                        }
-                        "Threads" if parts[3] == "value" => {
-                            if let Ok(n) = parts[4].parse::<u8>() {
-                                if n != 1 {
-                                    println!("Only one thread supported!")
-                                }
-                            }
+            "setoption" => match parse_setoption(command) {
+                Some((name, value)) => self.set_option(name, value),
+                None => uci_println!("Usage: setoption name <id> [value <x>]"),
+            },
+            "d" => {
+                uci_println!("{}", self.board);
+                uci_println!("Fen: {}", self.board.to_fen());
+                uci_println!("Key: {:016X}", self.board.hash.0);
+                uci_println!("Halfmove clock: {}", self.board.halfmoves);
+                uci_println!("Repetitions: {}", self.data.repetitions(&self.board));
+                let claimable = self.data.is_claimable_draw(&self.board);
+                uci_println!("Draw claimable: {}", if claimable { "yes" } else { "no" });
+            }
+            "islegal" => match parts.get(1) {
+                Some(move_str) => match check_move(&self.board, move_str) {
+                    Ok(_) => uci_println!("{move_str} is legal"),
+                    Err(reason) => uci_println!("{move_str} is illegal: {reason}"),
+                },
+                None => uci_println!("Usage: islegal <move>"),
+            },
+            "spsa" => {
+                for line in tunables::spsa_input().lines() {
+                    uci_println!("{line}");
+                }
+            }
+            "checkpoint" => match parts.get(1) {
+                Some(path) => {
+                    let with_tt = parts.get(2) == Some(&"tt");
+                    let saved = Checkpoint::capture(&self.board, &self.data, with_tt)
+                        .and_then(|cp| cp.save(path).map(|_| cp.depth));
+                    match saved {
+                        Ok(depth) => uci_println!("info string Saved analysis at depth {depth}"),
+                        Err(e) => logging::warn(&e),
+                    }
+                }
+                None => uci_println!("Usage: checkpoint <file> [tt]"),
+            },
+            "resume" => match parts.get(1).map(|path| Checkpoint::load(path)) {
+                Some(Ok(cp)) => {
+                    let depth = cp.depth;
+                    self.data.new_game();
+                    match cp.restore(&mut self.data) {
+                        Ok(board) => {
+                            self.board = board;
+                            self.game = GameHistory {
+                                start: self.board.hash.0,
+                                moves: Vec::new(),
+                            };
+                            uci_println!("info string Resuming analysis after depth {depth}");
                         }
-                        _ => {}
+                        Err(e) => logging::warn(&e),
                     }
                 }
-            }
+                Some(Err(e)) => logging::warn(&e),
+                None => uci_println!("Usage: resume <file>"),
+            },
+            "repertoire" => match (parts.get(1), &self.repertoire) {
+                (Some(&"probe"), Some(rep)) => {
+                    let moves = rep.probe(&self.board);
+                    if moves.is_empty() {
+                        uci_println!("No moves stored for this position");
+                    }
+                    for e in moves {
+                        uci_println!("{} count {} score {}", e.mv, e.count, e.score);
+                    }
+                }
+                (Some(&"probe"), None) => {
+                    uci_println!("No repertoire set, use setoption name Repertoire value <file>")
+                }
+                _ => uci_println!("Usage: repertoire probe"),
+            },
             "eval" => {
-                println!("eval: {}cp", self.board.evaluate(&mut EvalTable::default()));
+                // A FEN is evaluated on its own, leaving the game and the search caches as they are
+                let board = match &parts[1..] {
+                    [] => self.board,
+                    fen => match parse_fen(fen) {
+                        Ok(board) => board,
+                        Err(e) => {
+                            logging::warn(&e);
+                            uci_println!("Usage: eval [fen]");
+                            return;
+                        }
+                    },
+                };
+
+                let mut cache = EvalTable::default();
+                uci_println!("nnue: {}cp", board.network_eval(&mut cache));
+                uci_println!("eval: {}cp", board.evaluate(&mut cache));
             }
-            "bench" => self.bench(),
+            "bench" => match (parts.get(1), parts.get(2)) {
+                (Some(&"smp"), Some(threads)) => match threads.parse() {
+                    Ok(threads) => {
+                        let depth = parts.get(3).and_then(|d| d.parse().ok());
+                        self.bench_smp(threads, depth.unwrap_or(SMP_BENCH_DEPTH));
+                    }
+                    Err(_) => uci_println!("Invalid number of threads: {threads}"),
+                },
+                _ => self.bench(),
+            },
+            "tables" => match parts.get(1) {
+                Some(&"verify") => match verify_tables() {
+                    Ok(checked) => uci_println!("Tables ok, {checked} entries checked"),
+                    Err(e) => uci_println!("Tables wrong: {e}"),
+                },
+                _ => uci_println!("Usage: tables verify"),
+            },
+            "splitinfo" => match parts.get(1).map(|n| n.parse::<usize>()) {
+                None => self.split_info(default_threads()),
+                Some(Ok(threads)) if threads > 0 => self.split_info(threads),
+                _ => uci_println!("Usage: splitinfo [threads]"),
+            },
             "quit" => {
+                self.report_game();
                 std::process::exit(0);
             }
-            _ => println!("Unexpected command"),
+            // Searches run to completion before the next command is read
+            "stop" | "ponderhit" => {}
+            _ => logging::warn(&format!("Unknown command: {command}")),
+        }
+    }
+
+    /// Applies an option, whose name is matched ignoring case as the UCI spec requires
+    fn set_option(&mut self, name: &str, value: &str) {
+        match name.to_ascii_lowercase().as_str() {
+            "hash" => {
+                if let Ok(mb) = value.parse::<usize>() {
+                    if mb > 0 {
+                        let mb = mb.min(MAX_HASH_MB);
+                        self.hash_mb = mb;
+                        self.data.resize_tt(mb);
+                        if let Some(helper) = &mut self.helper {
+                            helper.resize_tt(mb);
+                        }
+                    }
+                }
+            }
+            "threads" => match value.parse::<u8>() {
+                Ok(1) => self.helper = None,
+                Ok(2) => self.set_root_split(),
+                Ok(_) => logging::warn("Only one or two threads supported!"),
+                Err(_) => logging::warn(&format!("Invalid value for Threads: {value}")),
+            },
+            "uci_analysemode" => {
+                self.data.analyse_mode = value == "true";
+            }
+            "uci_chess960" => {
+                self.data.chess960 = value == "true";
+            }
+            "whitepov" => {
+                self.data.white_pov = value == "true";
+            }
+            "deterministic" => {
+                self.data.deterministic = value == "true";
+            }
+            "failhard" => {
+                self.data.fail_hard = value == "true";
+            }
+            "rootmoves" => {
+                self.data.root_moves_report = value == "true";
+            }
+            "lowmemory" => {
+                self.data.set_low_memory(value == "true", self.hash_mb);
+                if let Some(helper) = &mut self.helper {
+                    helper.set_low_memory(value == "true", self.hash_mb);
+                }
+            }
+            "contempt" => {
+                if let Ok(contempt) = value.parse::<i32>() {
+                    if self.data.set_contempt(contempt) {
+                        if let Some(helper) = &mut self.helper {
+                            helper.tt.clear();
+                        }
+                    }
+                }
+            }
+            "repertoire" => {
+                self.repertoire = match value {
+                    "" | "<empty>" => None,
+                    path => match Repertoire::load(path) {
+                        Ok(rep) => Some(rep),
+                        Err(e) => {
+                            logging::warn(&e);
+                            None
+                        }
+                    },
+                };
+            }
+            "iterationgate" => {
+                if let Ok(gate) = value.parse::<u128>() {
+                    self.data.iteration_gate = gate.min(1000);
+                }
+            }
+            "log file" => {
+                if let Err(e) = logging::set_file(value) {
+                    logging::warn(&e);
+                }
+            }
+            "timereport" => {
+                self.time_report = value == "true";
+            }
+            "nnuecache" => {
+                self.data.cache.enabled = value == "true";
+            }
+            _ => {
+                #[cfg(feature = "dev")]
+                if let Some(heuristic) = Heuristic::from_option(name) {
+                    self.data.set_disabled(heuristic, value == "true");
+                    return;
+                }
+
+                match tunables::find(name) {
+                    Some(t) => match value.parse() {
+                        Ok(value) => {
+                            if let Err(e) = t.set(value) {
+                                logging::warn(&e);
+                            }
+                        }
+                        Err(_) => logging::warn(&format!("Invalid value for {}: {value}", t.name)),
+                    },
+                    None => logging::warn(&format!("Unknown option {name}")),
+                }
+            }
         }
     }
 
     fn parse_position(&mut self, args: &[&str]) {
+        if args.is_empty() {
+            uci_println!("Usage: position (startpos | fen <fen> | current) [moves <moves>]");
+            return;
+        }
+
         let mut board = if args[0] == "startpos" {
             Board::default()
         } else if args[0] == "fen" {
@@ -113,48 +643,138 @@ impl UCIEngine {
                 .iter()
                 .position(|&x| x == "moves")
                 .unwrap_or(args.len());
-            let fen = args[1..fen_end].join(" ");
-            Board::from_fen(&fen)
+            match parse_fen(&args[1..fen_end]) {
+                Ok(board) => board,
+                Err(e) => {
+                    logging::warn(&e);
+                    return;
+                }
+            }
+        } else if args[0] == "current" {
+            self.board
         } else {
             return;
         };
 
-        self.data.clear();
+        // `position current moves ...` extends the game with the given moves only
+        let (start_hash, mut moves, mut stack) = if args[0] == "current" {
+            (
+                self.game.start,
+                self.game.moves.clone(),
+                self.data.stack.clone(),
+            )
+        } else {
+            (board.hash.0, Vec::new(), Vec::new())
+        };
 
         let moves_start = args.iter().position(|&x| x == "moves");
         if let Some(start) = moves_start {
             for move_str in &args[start + 1..] {
-                self.data.stack.push(board.hash.0);
+                stack.push(board.hash.0);
                 let m = self.parse_move(&board, move_str);
                 board.make_move(m);
+                moves.push(m);
+            }
+        }
+
+        // Histories and killers carry over to the next move of the game, fading
+        match self.game.plies_to(&self.board, start_hash, &moves, &board) {
+            Some(plies) => {
+                self.data.next_move(plies);
+                if let Some(helper) = &mut self.helper {
+                    helper.next_move(plies);
+                }
             }
+            None => self.new_game(),
         }
 
+        self.data.clear();
+        self.data.stack.extend(stack);
+        self.game = GameHistory {
+            start: start_hash,
+            moves,
+        };
         self.board = board;
     }
 
+    fn new_game(&mut self) {
+        self.report_game();
+        self.game = GameHistory {
+            start: self.board.hash.0,
+            moves: Vec::new(),
+        };
+        self.clock = GameClock::default();
+        self.data.new_game();
+        if let Some(helper) = &mut self.helper {
+            helper.new_game();
+        }
+    }
+
+    /// Adds the second thread of the root split, sharing the control of the main one so that
+    /// `stop` and node limits apply to both
+    fn set_root_split(&mut self) {
+        if self.helper.is_some() {
+            return;
+        }
+        let mut helper = Box::new(SearchData::new());
+        helper.set_low_memory(self.data.low_memory, self.hash_mb);
+        helper.resize_tt(self.hash_mb);
+        helper.control = Arc::clone(&self.data.control);
+        helper.main_thread = false;
+        self.helper = Some(helper);
+    }
+
+    /// Prints the time usage of the game played so far and starts counting anew
+    fn report_game(&mut self) {
+        let stats = std::mem::take(&mut self.stats);
+        if self.time_report && stats.moves > 0 {
+            uci_println!("info string {}", stats.summary());
+        }
+    }
+
     fn go(&mut self, args: &[&str]) {
+        let start = Instant::now();
         self.data.tt.inc_age();
-        let mut depth: u8 = 64;
+        self.data.cache.stats = CacheStats::default();
+        let mut depth = MAX_DEPTH;
         let mut wtime: Option<usize> = None;
         let mut btime: Option<usize> = None;
         let mut winc: Option<usize> = None;
         let mut binc: Option<usize> = None;
         let mut moves_left: Option<f64> = None;
         let mut movetime: Option<u128> = None;
+        let mut nodes: Option<u64> = None;
+        let mut mate: Option<u8> = None;
+        let mut search_moves = Vec::new();
 
         let mut i = 0;
         while i + 1 < args.len() {
             let value = args[i];
             i += 1;
+            if value == "searchmoves" {
+                // Every move up to the next parameter, if any
+                while let Some(move_str) = args.get(i).filter(|a| !GO_PARAMETERS.contains(a)) {
+                    match check_move(&self.board, move_str) {
+                        Ok(m) => search_moves.push(m),
+                        Err(e) => logging::warn(&format!("Ignoring searchmoves {move_str}: {e}")),
+                    }
+                    i += 1;
+                }
+                continue;
+            }
             match value {
-                "depth" => depth = args[i].parse().unwrap_or(MAX_DEPTH).clamp(1, 64),
+                "depth" => match args[i].parse::<u8>() {
+                    Ok(d) => depth = d.clamp(1, MAX_DEPTH),
+                    Err(_) => logging::warn(&format!("Ignoring invalid depth {}", args[i])),
+                },
                 "wtime" => wtime = args[i].parse().ok(),
                 "btime" => btime = args[i].parse().ok(),
                 "winc" => winc = args[i].parse().ok(),
                 "binc" => binc = args[i].parse().ok(),
                 "movestogo" => moves_left = args[i].parse().ok(),
                 "movetime" => movetime = args[i].parse().ok(),
+                "nodes" => nodes = args[i].parse().ok(),
+                "mate" => mate = args[i].parse().ok(),
                 _ => i -= 1,
             }
             i += 1;
@@ -169,7 +789,27 @@ impl UCIEngine {
             Colour::Black => binc,
         };
 
-        self.data.time_tp = if let Some(t) = time_left {
+        let time_left = match time_left {
+            Some(reported) => {
+                let expected = self.clock.expected;
+                let left = self.clock.observe(reported as u128);
+                if self.debug {
+                    let expected = expected.map_or(String::from("none"), |e| e.to_string());
+                    uci_println!(
+                        "info string clock reported {reported} expected {expected} lag {} left {left}",
+                        self.clock.lag
+                    );
+                }
+                self.stats.max_lag = self.stats.max_lag.max(self.clock.lag);
+                Some(left as usize)
+            }
+            None => {
+                self.clock = GameClock::default();
+                None
+            }
+        };
+
+        let time = if let Some(t) = time_left {
             (if let Some(inc) = time_incr {
                 (t / 20 + 4 * inc / 5) as u128
             } else {
@@ -189,68 +829,233 @@ impl UCIEngine {
         }
         .min(MAX_TIME);
 
-        find_best_move(&self.board, depth, &mut self.data);
-        println!("bestmove {}", self.data.best_move);
-    }
+        // Only clock games can spend more than the allocated time
+        let max_time = match time_left {
+            Some(t) => (PANIC_SCALE * time).min(t as u128 / 4),
+            None => 0,
+        };
 
-    fn parse_move(&self, board: &Board, move_str: &str) -> Move {
-        let src = Square::from(&move_str[0..2]);
-        let dest = Square::from(&move_str[2..4]);
-        let promo = move_str.get(4..5);
+        let node_limit = match nodes {
+            Some(nodes) => nodes,
+            None if self.data.deterministic => time as u64 * DETERMINISTIC_NODES_PER_MS,
+            None => u64::MAX,
+        };
 
-        board
-            .generate_pseudo_moves::<true, true>()
-            .into_iter()
-            .find(|&m| {
-                if m.get_source() != src || m.get_dest() != dest {
-                    return false;
-                }
+        let mut limits = SearchLimits::new()
+            .depth(depth)
+            .nodes(node_limit)
+            .clock(time, max_time)
+            .search_moves(search_moves);
+        if let Some(mate) = mate {
+            limits = limits.mate(mate);
+        }
+
+        // A bug tripping in the search must not lose the game on time, the best move found so
+        // far is still played
+        let stack_len = self.data.stack.len();
+        // Splitting makes searches depend on thread timing
+        let helper = self.helper.as_mut().filter(|_| !self.data.deterministic);
+        let searched = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match helper {
+            Some(helper) => search_root_split(&self.board, &limits, &mut self.data, helper),
+            None => {
+                find_best_move(&self.board, &limits, &mut self.data);
+                None
+            }
+        }));
+        match searched {
+            Ok(Some(split)) if self.debug => uci_println!(
+                "info string rootsplit move {} depth {} score {} nodes {} used {}",
+                self.board.move_to_uci(split.m, self.data.chess960),
+                split.depth,
+                split.score,
+                split.nodes,
+                split.used
+            ),
+            Ok(_) => {}
+            Err(_) => self.recover_search(stack_len),
+        }
 
-                match (promo, m.get_type()) {
-                    (None, t) => !t.is_promotion(),
-                    (Some("q"), MoveKind::QueenPromotion | MoveKind::QueenCapPromo) => true,
-                    (Some("r"), MoveKind::RookPromotion | MoveKind::RookCapPromo) => true,
-                    (Some("b"), MoveKind::BishopPromotion | MoveKind::BishopCapPromo) => true,
-                    (Some("n"), MoveKind::KnightPromotion | MoveKind::KnightCapPromo) => true,
-                    _ => false,
+        if self.debug {
+            uci_println!("info string {}", self.data.cache.stats);
+        }
+        let elapsed = start.elapsed().as_millis();
+        if time_left.is_some() {
+            let inc = time_incr.unwrap_or(0) as u128;
+            self.clock.spend(elapsed, inc);
+        }
+        let budget = (time_left.is_some() || movetime.is_some()).then_some(limits.time);
+        self.stats
+            .record(elapsed, self.data.depth.saturating_sub(1), budget);
+        if let Some(rep) = &mut self.repertoire {
+            if self.data.best_move != Move::NULL {
+                rep.record(self.board.hash.0, self.data.best_move, self.data.eval);
+                if let Err(e) = rep.save() {
+                    logging::warn(&e);
                 }
-            })
-            .expect("UCI Error: Invalid move received")
+            }
+        }
+        let chess960 = self.data.chess960;
+        let best = self.board.move_to_uci(self.data.best_move, chess960);
+        match self.data.ponder_move {
+            Move::NULL => uci_println!("bestmove {best}"),
+            ponder => {
+                let mut after = self.board;
+                after.make_move(self.data.best_move);
+                let ponder = after.move_to_uci(ponder, chess960);
+                uci_println!("bestmove {best} ponder {ponder}");
+            }
+        }
+    }
+
+    /// Reports the state a search panicked in and leaves the data ready for the next one,
+    /// keeping the best move of the last completed iteration if it is legal
+    fn recover_search(&mut self, stack_len: usize) {
+        logging::error(&format!(
+            "Search panicked at {} from {:?}",
+            self.data.crash_dump(),
+            self.board
+        ));
+
+        self.data.control.stop();
+        self.data.stack.truncate(stack_len);
+        self.data.ply = 0;
+        self.data.best_move = legal_best_move(&self.board, &self.data);
+        self.data.ponder_move = Move::NULL;
+    }
+
+    fn parse_move(&self, board: &Board, move_str: &str) -> Move {
+        board
+            .find_move(move_str)
+            .unwrap_or_else(|e| panic!("UCI Error: Invalid move {move_str} received: {e}"))
     }
 
     fn run_perft(&mut self, args: &[&str]) {
+        if let ["detail", depth @ ..] = args {
+            let depth = depth.first().and_then(|d| d.parse().ok()).unwrap_or(4);
+            self.board.perft_detail_table(depth);
+            return;
+        }
+
         let depth = if args.is_empty() {
             7
         } else {
             args[0].parse().unwrap_or(8)
         };
 
-        self.board.perft(depth);
+        match args.get(1..) {
+            Some(["threads", threads, ..]) => {
+                let threads = threads.parse().unwrap_or_else(|_| default_threads());
+                self.board.perft_threads(depth, threads);
+            }
+            _ => {
+                self.board.perft(depth);
+            }
+        }
+    }
+
+    /// Shows how the root moves would be shared among `threads` threads, weighting each by its
+    /// nodes in the last search of this position or else by a shallow perft
+    fn split_info(&self, threads: usize) {
+        let searched = self.data.root.hash == self.board.hash && !self.data.root_moves.is_empty();
+        let (moves, source): (Vec<(Move, u64)>, String) = if searched {
+            let moves = self.data.root_moves.iter().map(|rm| (rm.m, rm.nodes));
+            (moves.collect(), "last search".to_string())
+        } else {
+            let moves = self.board.perft_root_moves(SPLIT_PERFT_DEPTH, threads);
+            let moves = moves.into_iter().map(|(m, count)| (m, count as u64));
+            (moves.collect(), format!("perft {SPLIT_PERFT_DEPTH}"))
+        };
+
+        let total: u64 = moves.iter().map(|&(_, nodes)| nodes).sum();
+        uci_println!(
+            "Split of {} root moves over {threads} threads, nodes from {source}",
+            moves.len()
+        );
+        let split = split_root_moves(&moves, threads);
+        for (i, thread) in split.iter().enumerate() {
+            let nodes: u64 = thread.iter().map(|&(_, nodes)| nodes).sum();
+            let share = 100.0 * nodes as f64 / total.max(1) as f64;
+            let moves: Vec<String> = thread
+                .iter()
+                .map(|&(m, _)| self.board.move_to_uci(m, self.data.chess960))
+                .collect();
+            uci_println!(
+                "thread {i}: {nodes} nodes ({share:.1}%) {}",
+                moves.join(" ")
+            );
+        }
+        uci_println!("Imbalance: {:.2}", load_imbalance(&split));
     }
 
     pub fn bench(&mut self) {
         let start = std::time::Instant::now();
         self.data.tt.inc_age();
-        self.data.cache = EvalTable::default();
+        self.data.cache.clear();
 
         let mut nodes = 0;
 
         for fen in BENCH_POSITIONS {
             self.board = Board::from_fen(fen);
-            self.data.time_tp = MAX_TIME;
-            println!("------------------------------------------------------------");
-            println!("Current FEN: {fen}");
-            println!("------------------------------------------------------------");
-            find_best_move(&self.board, 14, &mut self.data);
+            uci_println!("------------------------------------------------------------");
+            uci_println!("Current FEN: {fen}");
+            uci_println!("------------------------------------------------------------");
+            let limits = SearchLimits::new().depth(14).movetime(MAX_TIME);
+            find_best_move(&self.board, &limits, &mut self.data);
             nodes += self.data.nodes;
             self.data.clear();
             self.data.tt.inc_age();
         }
 
         let time = start.elapsed().as_secs_f64();
-        println!("\x1b[1;33mResults for bench:");
-        println!("{time:.2} seconds");
-        println!("{} nodes {} nps", nodes, (nodes as f64 / time) as u64);
+        uci_println!("\x1b[1;33mResults for bench:");
+        uci_println!("{time:.2} seconds");
+        uci_println!("{} nodes {} nps", nodes, (nodes as f64 / time) as u64);
+    }
+
+    /// Runs the bench suite on 1 to `max_threads` concurrent searchers, reporting NPS scaling
+    /// and the time to depth ratio against a single thread. Every searcher owns its tables, so
+    /// this measures how throughput scales on the machine rather than search speedup.
+    pub fn bench_smp(&mut self, max_threads: usize, depth: u8) {
+        let mut base: Option<(f64, f64)> = None; // (nps, time to depth) of one thread
+        let mut pool = ThreadPool::new(1);
+
+        uci_println!("Threads        Nodes          NPS  Scaling  TTD ratio");
+        for threads in 1..=max_threads.max(1) {
+            pool.resize(threads);
+            pool.new_game();
+            let nodes = AtomicU64::new(0);
+            let start = Instant::now();
+
+            let suite_times = pool.run(|worker| {
+                let data = &mut worker.data;
+                data.silent = true;
+                let suite = Instant::now();
+
+                let limits = SearchLimits::new().depth(depth).movetime(MAX_TIME);
+                for fen in BENCH_POSITIONS {
+                    find_best_move(&Board::from_fen(fen), &limits, data);
+                    // One atomic update per search keeps counting off the hot path
+                    nodes.fetch_add(data.nodes, Ordering::Relaxed);
+                    data.clear();
+                    data.tt.inc_age();
+                }
+
+                suite.elapsed().as_secs_f64()
+            });
+
+            let time = start.elapsed().as_secs_f64();
+            let nodes = nodes.load(Ordering::Relaxed);
+            let nps = nodes as f64 / time;
+            let ttd = suite_times.iter().sum::<f64>() / threads as f64;
+            let (base_nps, base_ttd) = *base.get_or_insert((nps, ttd));
+
+            uci_println!(
+                "{threads:>7} {nodes:>12} {:>12} {:>8.2} {:>10.2}",
+                nps as u64,
+                nps / base_nps,
+                ttd / base_ttd
+            );
+        }
     }
 }
 
@@ -306,3 +1111,284 @@ const BENCH_POSITIONS: [&str; 50] = [
     "3br1k1/p1pn3p/1p3n2/5pNq/2P1p3/1PN3PP/P2Q1PB1/4R1K1 w - - 0 23",
     "2r2b2/5p2/5k2/p1r1pP2/P2pB3/1P3P2/K1P3R1/7R w - - 23 93",
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::POLL_INTERVAL;
+    use crate::tables::Bound;
+    use crate::tunables::HISTORY_AGING;
+
+    #[test]
+    fn test_parse_setoption() {
+        assert_eq!(
+            parse_setoption("setoption name Hash value 64"),
+            Some(("Hash", "64"))
+        );
+        assert_eq!(
+            parse_setoption("setoption name Syzygy Path value /my path/tb"),
+            Some(("Syzygy Path", "/my path/tb"))
+        );
+        assert_eq!(
+            parse_setoption("setoption  name EvalFile value \"C:\\nets\\my net.bin\"  "),
+            Some(("EvalFile", "C:\\nets\\my net.bin"))
+        );
+        assert_eq!(
+            parse_setoption("setoption name Repertoire value"),
+            Some(("Repertoire", ""))
+        );
+        assert_eq!(
+            parse_setoption("setoption name Clear Hash"),
+            Some(("Clear Hash", ""))
+        );
+        assert_eq!(
+            parse_setoption("setoption name Values value valued"),
+            Some(("Values", "valued"))
+        );
+        assert_eq!(parse_setoption("setoption name value 3"), None);
+        assert_eq!(parse_setoption("setoption Hash value 3"), None);
+        assert_eq!(parse_setoption("setoption namex value 3"), None);
+    }
+
+    #[test]
+    fn test_game_clock() {
+        let mut clock = GameClock::default();
+        assert_eq!(clock.observe(10_000), 10_000);
+        clock.spend(1_000, 100);
+        assert_eq!(clock.expected, Some(9_100));
+
+        // Small differences are noise, larger losses are kept as a margin which decays
+        assert_eq!(clock.observe(9_080), 9_080);
+        clock.spend(1_000, 100);
+        assert_eq!(clock.observe(7_880), 7_580);
+        assert_eq!(clock.lag, 300);
+        clock.spend(1_000, 100);
+        assert_eq!(clock.observe(6_980), 6_830);
+
+        // Crossing a `movestogo` boundary adds the time of the next period to the clock
+        clock.spend(1_000, 100);
+        assert_eq!(clock.expected, Some(6_080));
+        assert_eq!(clock.observe(60_000), 60_000 - 75);
+        clock.spend(1_000, 0);
+        assert_eq!(clock.expected, Some(59_000));
+        assert_eq!(clock.observe(59_000), 59_000 - 37);
+        assert_eq!(clock.lag, 37);
+    }
+
+    #[test]
+    fn test_game_stats() {
+        let mut stats = GameStats::default();
+        stats.record(120, 10, Some(100));
+        stats.record(80, 12, Some(100));
+        stats.record(1_000, 20, None);
+        stats.max_lag = 30;
+        assert_eq!(
+            stats.summary(),
+            "time report moves 3 time 1200 avg 400 depth 14.0 \
+             budget 100 used 100% overruns 1 maxover 20 maxlag 30"
+        );
+        assert_eq!(
+            GameStats::default().summary(),
+            "time report moves 0 time 0 avg 0 depth 0.0"
+        );
+    }
+
+    #[test]
+    fn test_lenient_commands() {
+        let mut engine = UCIEngine::new();
+
+        // Unknown tokens are skipped up to the first command
+        engine.process_command("joho debug on");
+        assert!(engine.debug);
+        engine.process_command("  xyzzy  position startpos moves e2e4");
+        assert_eq!(
+            engine.board.to_fen(),
+            "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1"
+        );
+        engine.process_command("foo setoption name Contempt value 20");
+        assert_eq!(engine.data.contempt, 20);
+
+        // Lines without any command and partial commands change nothing
+        let fen = engine.board.to_fen();
+        for line in ["", "   ", "foo bar", "position", "stop", "debugging off"] {
+            engine.process_command(line);
+            assert_eq!(engine.board.to_fen(), fen, "{line}");
+            assert!(engine.debug, "{line}");
+        }
+    }
+
+    #[test]
+    fn test_threads_option() {
+        let mut engine = UCIEngine::new();
+        engine.process_command("setoption name Threads value 2");
+        assert!(engine.helper.is_some());
+
+        // Values which cannot be set are reported and keep the current thread count
+        for value in ["3", "two", "-1"] {
+            engine.process_command(&format!("setoption name Threads value {value}"));
+            assert!(engine.helper.is_some(), "{value}");
+        }
+        engine.process_command("setoption name Threads value 1");
+        assert!(engine.helper.is_none());
+    }
+
+    #[test]
+    fn test_low_memory_option() {
+        let mut engine = UCIEngine::new();
+        let entries = |engine: &mut UCIEngine| {
+            engine.data.tt.allocate();
+            engine.data.tt.tt.len()
+        };
+        engine.process_command("setoption name LowMemory value false");
+        engine.process_command("setoption name Hash value 64");
+        let hash = entries(&mut engine);
+
+        // Leaving the low memory mode gives back the Hash set before, not the default one
+        engine.process_command("setoption name LowMemory value true");
+        assert!(entries(&mut engine) < hash);
+        engine.process_command("setoption name LowMemory value false");
+        assert_eq!(entries(&mut engine), hash);
+
+        // Setting the current mode again keeps the table and its entries
+        let tt = &mut engine.data.tt;
+        tt.insert(1, Bound::Exact, Move::NULL, 10, 5, false);
+        engine.process_command("setoption name LowMemory value false");
+        assert!(engine.data.tt.probe(1).is_some());
+    }
+
+    #[test]
+    fn test_handshake_mid_game() {
+        let mut engine = UCIEngine::new();
+        engine.process_command("position startpos moves e2e4 e7e5");
+        engine.process_command("setoption name Contempt value 15");
+        let fen = engine.board.to_fen();
+        let stack = engine.data.stack.clone();
+
+        // Asking again for the identity, readiness or registration leaves the game alone
+        for line in [
+            "uci",
+            "isready",
+            "uci",
+            "register",
+            "register later",
+            "register name A code 1",
+            "isready",
+        ] {
+            engine.process_command(line);
+            assert_eq!(engine.board.to_fen(), fen, "{line}");
+            assert_eq!(engine.data.stack, stack, "{line}");
+            assert_eq!(engine.data.contempt, 15, "{line}");
+        }
+        assert!(engine.data.tt.is_allocated());
+    }
+
+    #[test]
+    fn test_go_limits() {
+        let mut engine = UCIEngine::new();
+        engine.data.silent = true;
+
+        // A malformed depth is reported and leaves the default, only the other limits apply
+        engine.go(&["depth", "x", "movetime", "20"]);
+        assert_eq!(engine.data.limits.depth, MAX_DEPTH);
+        assert_eq!(engine.data.limits.time, 20);
+
+        // The move list ends at the next parameter, illegal moves are left out
+        engine.go(&[
+            "searchmoves",
+            "a2a3",
+            "e2e5",
+            "h2h3",
+            "depth",
+            "3",
+            "mate",
+            "2",
+        ]);
+        let a2a3 = engine.board.find_move("a2a3").unwrap();
+        let h2h3 = engine.board.find_move("h2h3").unwrap();
+        let limits = &engine.data.limits;
+        assert_eq!(limits.search_moves, [a2a3, h2h3]);
+        assert_eq!((limits.depth, limits.mate), (3, Some(2)));
+        assert!([a2a3, h2h3].contains(&engine.data.best_move));
+
+        engine.go(&["movetime", "50", "searchmoves", "g1f3"]);
+        let limits = &engine.data.limits;
+        assert_eq!((limits.time, limits.max_time, limits.mate), (50, 0, None));
+        assert_eq!(limits.depth, MAX_DEPTH);
+        assert_eq!(
+            engine.data.best_move,
+            engine.board.find_move("g1f3").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_go_nodes() {
+        let mut engine = UCIEngine::new();
+        engine.data.silent = true;
+        let position =
+            "position fen r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq -";
+        engine.process_command(position);
+
+        // The search stops within a poll of the limit, whatever the depth reached
+        engine.go(&["nodes", "20000"]);
+        assert_eq!(engine.data.limits.nodes, 20_000);
+        assert!(engine.data.nodes >= 20_000);
+        assert!(engine.data.nodes < 20_000 + u64::from(POLL_INTERVAL));
+        assert!(engine.board.is_legal(engine.data.best_move));
+
+        // Fixed node searches repeat exactly, which is what they are used for
+        let (best_move, eval) = (engine.data.best_move, engine.data.eval);
+        engine.process_command("ucinewgame");
+        engine.process_command(position);
+        engine.go(&["nodes", "20000"]);
+        assert_eq!((engine.data.best_move, engine.data.eval), (best_move, eval));
+
+        // Without the parameter nothing but the other limits stop the search
+        engine.go(&["depth", "2"]);
+        assert_eq!(engine.data.limits.nodes, u64::MAX);
+    }
+
+    #[test]
+    fn test_history_between_moves() {
+        let mut engine = UCIEngine::new();
+        let e2e4 = engine.board.find_move("e2e4").unwrap();
+        engine.process_command("position startpos moves g1f3");
+        engine.data.history.score[0][28] = 1024;
+        engine.data.ply_data[3].killer = e2e4;
+
+        // Same position again, then two plies on with moves and one more from a FEN alone
+        engine.process_command("position startpos moves g1f3");
+        assert_eq!(engine.data.history.score[0][28], 1024);
+        engine.process_command("position startpos moves g1f3 g8f6 e2e4");
+        let aged = (1024 * HISTORY_AGING.get() / 1024) as i16;
+        assert_eq!(engine.data.history.score[0][28], aged);
+        assert!(engine.data.ply_data[1].killer == e2e4);
+        let fen = engine.board.to_fen();
+        engine.process_command(&format!("position fen {fen} moves f6e4"));
+        let aged_twice = (i32::from(aged) * HISTORY_AGING.get() / 1024) as i16;
+        assert_eq!(engine.data.history.score[0][28], aged_twice);
+        assert!(engine.data.ply_data[0].killer == e2e4);
+
+        // Another game starts from scratch
+        engine.process_command("position startpos moves d2d4");
+        assert_eq!(engine.data.history.score[0][28], 0);
+        assert!(engine.data.ply_data[0].killer == Move::NULL);
+    }
+
+    #[test]
+    fn test_recover_search() {
+        let mut engine = UCIEngine::new();
+        engine.parse_position(&["startpos", "moves", "e2e4"]);
+        let stack_len = engine.data.stack.len();
+
+        // State left by a search which panicked deep in a line before completing an iteration
+        engine.data.stack.extend([1, 2, 3]);
+        engine.data.ply = 3;
+        engine.data.best_move = Move::NULL;
+        engine.recover_search(stack_len);
+
+        assert_eq!((engine.data.stack.len(), engine.data.ply), (stack_len, 0));
+        let best_move = engine.data.best_move;
+        assert!(engine.board.is_pseudo_legal(best_move) && engine.board.is_legal(best_move));
+        assert!(engine.data.control.is_stopped());
+    }
+}
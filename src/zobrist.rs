@@ -3,7 +3,7 @@ use super::{
     board::Board,
     castle::CastlingRights,
     constants::{CASTLE_KEYS, EP_KEYS, PIECE_KEYS, SIDE_KEY},
-    piece::{Colour, Piece},
+    piece::{Colour, Piece, PieceType},
     square::Square,
 };
 
@@ -35,6 +35,72 @@ impl ZHash {
         hash
     }
 
+    /// Hash of the pawn structure alone, kept incrementally in `Board::pawn_hash`
+    pub fn pawns(board: &Board) -> Self {
+        let mut hash = Self::NULL;
+
+        let mut pawns = board.pieces[PieceType::Pawn];
+        while pawns != BitBoard::EMPTY {
+            let sq = pawns.pop_lsb();
+            hash.hash_piece(board.piece_at(sq), sq);
+        }
+
+        hash
+    }
+
+    /// Material key of the board, kept incrementally in `Board::material_hash`. Each piece has
+    /// a nibble counting its copies, so the key tells materials apart without collisions and
+    /// also gives the number of pieces on the board.
+    pub fn material(board: &Board) -> Self {
+        let mut hash = Self::NULL;
+
+        for piece in Piece::ALL {
+            let bb = board.pieces[piece.piece_type()] & board.sides[piece.colour() as usize];
+            for _ in 0..bb.count_bits() {
+                hash.add_material(piece);
+            }
+        }
+
+        hash
+    }
+
+    /// Material key of a position with the given pieces, listing a piece once per copy, so
+    /// that endgames can be recognised by key
+    pub const fn material_key(pieces: &[Piece]) -> Self {
+        let mut key = 0;
+        let mut i = 0;
+        while i < pieces.len() {
+            key += 1 << (4 * pieces[i] as u64);
+            i += 1;
+        }
+
+        Self(key)
+    }
+
+    /// Counts one more piece of a kind, of which a side has at most 15 besides its king
+    #[inline]
+    pub fn add_material(&mut self, piece: Piece) {
+        debug_assert!(
+            (self.0 >> (4 * piece as u64)) & 0xF < 0xF,
+            "Too many pieces"
+        );
+        self.0 += 1 << (4 * piece as u64);
+    }
+
+    #[inline]
+    pub fn remove_material(&mut self, piece: Piece) {
+        self.0 -= 1 << (4 * piece as u64);
+    }
+
+    /// Pieces on the board of a material key, kings included
+    #[inline]
+    pub const fn piece_count(self) -> usize {
+        // Pairs of nibbles summed into bytes, then the bytes into the top one
+        const NIBBLES: u64 = 0x0F0F_0F0F_0F0F_0F0F;
+        let bytes = (self.0 & NIBBLES) + ((self.0 >> 4) & NIBBLES);
+        (bytes.wrapping_mul(0x0101_0101_0101_0101) >> 56) as usize
+    }
+
     pub fn hash_piece(&mut self, piece: Piece, square: Square) {
         self.0 ^= PIECE_KEYS[piece as usize][square.index()];
     }
@@ -0,0 +1,65 @@
+//! Heap usage of searches, in its own test binary so that the allocator only counts them
+
+use oxide::board::Board;
+use oxide::limits::SearchLimits;
+use oxide::search::find_best_move;
+use oxide::tables::{SearchData, DEFAULT_HASH_MB};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// System allocator keeping track of the bytes in use and their peak
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(in_use, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let in_use = IN_USE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(in_use, Ordering::Relaxed);
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Peak heap of a search on a fresh `SearchData`, the data itself included
+fn search_peak(low_memory: bool) -> usize {
+    PEAK.store(IN_USE.load(Ordering::Relaxed), Ordering::Relaxed);
+    let start = IN_USE.load(Ordering::Relaxed);
+
+    let board =
+        Board::from_fen("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1");
+    let mut data = Box::new(SearchData::new());
+    data.set_low_memory(low_memory, DEFAULT_HASH_MB);
+    data.deterministic = true;
+    data.silent = true;
+    find_best_move(&board, &SearchLimits::new().nodes(50_000), &mut data);
+    assert!(board.is_legal(data.best_move));
+    drop(data);
+
+    PEAK.load(Ordering::Relaxed) - start
+}
+
+#[test]
+fn test_low_memory() {
+    const MB: usize = 1 << 20;
+
+    // Well within the 16MB of mobile GUIs, while the default TT alone takes 32MB
+    let low = search_peak(true);
+    assert!(low < 4 * MB, "{low} bytes");
+    let default = search_peak(false);
+    assert!(default > 32 * MB, "{default} bytes");
+}